@@ -1,159 +1,145 @@
-use cidr_matcher::cidr_bs::GeoIPMatcher;
-use cidr_matcher::geoip;
-use cidr_matcher::geoip::GeoIPList;
+use cidr_matcher::cidr::Cidr;
+use cidr_matcher::cidr_bs::{GeoIPMatcher, STreeMatcher};
+use cidr_matcher::dir24::Dir24Table;
+use cidr_matcher::dual_stack::DualStackTrie;
+use cidr_matcher::geoip_db::GeoIpDatabase;
 use cidr_matcher::lpc_trie::LPCTrie;
-use criterion::{criterion_group, criterion_main, Criterion};
+use cidr_matcher::matcher::IpMatcher;
+use cidr_matcher::poptrie::Poptrie;
+use cidr_matcher::prefilter::Prefilter;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use radix_trie::Trie;
-use std::convert::TryInto;
-use std::fs::File;
+use std::net::IpAddr;
 
-pub fn read_file() -> geoip::GeoIPList {
-    let file = "data/geoip.dat";
-    let mut f = match File::open(&file) {
-        Ok(f) => f,
+// GEOIP_DAT or data/geoip.dat, None with a note when it cannot be loaded.
+fn load_geoip() -> Option<GeoIpDatabase> {
+    let path = std::env::var("GEOIP_DAT").unwrap_or_else(|_| "data/geoip.dat".to_string());
+    match GeoIpDatabase::from_path(&path) {
+        Ok(db) => Some(db),
         Err(e) => {
-            panic!("open dat file {} failed: {}", file, e);
+            eprintln!("skipping, {} could not be loaded: {}", path, e);
+            None
         }
-    };
-    let geo_ip_list: geoip::GeoIPList = match protobuf::Message::parse_from_reader(&mut f) {
-        Ok(v) => v,
-        Err(e) => {
-            panic!("dat file {} has invalid format: {}", file, e);
-        }
-    };
-    geo_ip_list
+    }
 }
 
-fn benchmark_lpc(b: &mut Criterion) {
-    let mut geoip_list = read_file();
-    let mut lpc_trie_cn_v6 = LPCTrie::<u128>::new();
-    let mut lpc_trie_cn_v4 = LPCTrie::<u32>::new();
-    for i in geoip_list.entry.iter_mut() {
-        if i.country_code.to_uppercase() == "CN" {
-            for pair in i.cidr.iter() {
-                let len = pair.ip.len();
-                match len {
-                    16 => {
-                        let inner = pair.ip.clone().try_into().unwrap();
-                        let key = u128::from_be_bytes(inner) >> (128 - pair.prefix)
-                            << (128 - pair.prefix);
-                        lpc_trie_cn_v6.put(key, pair.prefix as u8, "CN".to_string());
-                    }
-                    4 => {
-                        let inner = pair.ip.clone().try_into().unwrap();
-                        let key =
-                            u32::from_be_bytes(inner) >> (32 - pair.prefix) << (32 - pair.prefix);
-                        lpc_trie_cn_v4.put(key, pair.prefix as u8, "CN".to_string());
-                    }
-                    _ => {
-                        eprintln!("invalid ip length detected");
-                    }
-                }
+// the CN cidrs to build from, and every cidr of the database to look up.
+fn cn_cidrs_and_queries(db: &GeoIpDatabase) -> (Vec<Cidr>, Vec<Cidr>) {
+    let mut cidrs = vec![];
+    let mut queries = vec![];
+    for (country_code, trie) in db.iter() {
+        for (cidr, _) in trie.iter() {
+            if country_code == "CN" {
+                cidrs.push(cidr);
             }
+            queries.push(cidr);
         }
     }
-    b.bench_function("benchmark lpc", |b| {
-        b.iter(|| benchmark_lpc_impl(&lpc_trie_cn_v6, &lpc_trie_cn_v4, &geoip_list))
-    });
+    (cidrs, queries)
 }
 
-fn benchmark_radix(b: &mut Criterion) {
-    let mut geoip_list = read_file();
-    let mut radix_trie_v6 = Trie::<Vec<u8>, String>::new();
-    let mut radix_trie_v4 = Trie::<u32, String>::new();
-    for i in geoip_list.entry.iter_mut() {
-        if i.country_code.to_uppercase() == "CN" {
-            for pair in i.cidr.iter() {
-                let len = pair.ip.len();
-                match len {
-                    16 => {
-                        radix_trie_v6.insert(pair.ip.clone(), "CN".to_string());
-                    }
-                    4 => {
-                        let inner = pair.ip.clone().try_into().unwrap();
-                        let key =
-                            u32::from_be_bytes(inner) >> (32 - pair.prefix) << (32 - pair.prefix);
-                        radix_trie_v4.insert(key, "CN".to_string());
-                    }
-                    _ => {
-                        eprintln!("invalid ip length detected");
-                    }
-                }
+fn benchmark_matcher<M: IpMatcher>(b: &mut Criterion, name: &str) {
+    let db = match load_geoip() {
+        Some(db) => db,
+        None => return,
+    };
+    let (cidrs, queries) = cn_cidrs_and_queries(&db);
+    let queries: Vec<IpAddr> = queries.iter().map(|c| c.addr()).collect();
+    let matcher = M::build_from(cidrs);
+    b.bench_function(name, |b| {
+        b.iter(|| {
+            for ip in queries.iter() {
+                black_box(matcher.contains(*ip));
             }
-        }
-    }
-    b.bench_function("benchmark radix trie", |b| {
-        b.iter(|| benchmark_radix_trie_impl(&radix_trie_v4, &radix_trie_v6, &geoip_list))
+        })
     });
 }
 
+fn benchmark_lpc(b: &mut Criterion) {
+    benchmark_matcher::<DualStackTrie>(b, "benchmark lpc");
+}
+
+fn benchmark_prefilter(b: &mut Criterion) {
+    benchmark_matcher::<Prefilter<DualStackTrie>>(b, "benchmark prefiltered lpc");
+}
+
 fn benchmark_v2ray_core_matcher(b: &mut Criterion) {
-    let mut geoip_list = read_file();
-    let mut matcher = GeoIPMatcher::new();
-    for i in geoip_list.entry.iter_mut() {
-        if i.country_code.to_uppercase() == "CN" {
-            matcher.put(i);
-        }
-    }
-    b.bench_function("benchmark bs-matcher", |b| {
-        b.iter(|| benchmark_geoip_matcher_impl(&matcher, &geoip_list))
-    });
+    benchmark_matcher::<GeoIPMatcher>(b, "benchmark bs-matcher");
 }
 
-fn benchmark_lpc_impl(
-    lpc_trie_v6: &LPCTrie<u128>,
-    lpc_trie_v4: &LPCTrie<u32>,
-    geoip_list: &GeoIPList,
-) {
-    for i in geoip_list.entry.iter() {
-        for pair in i.cidr.iter() {
-            let len = pair.ip.len();
-            match len {
-                16 => {
-                    let inner = pair.ip.clone().try_into().unwrap();
-                    lpc_trie_v6.get(u128::from_be_bytes(inner));
-                }
-                4 => {
-                    let inner = pair.ip.clone().try_into().unwrap();
-                    lpc_trie_v4.get(u32::from_be_bytes(inner));
-                }
-                _ => {
-                    eprintln!("invalid ip length detected");
-                }
+fn benchmark_s_tree(b: &mut Criterion) {
+    benchmark_matcher::<STreeMatcher>(b, "benchmark s-tree");
+}
+
+// The single family engines against the trie of the same family, on the
+// queries of that family only.
+fn benchmark_family<M: IpMatcher>(b: &mut Criterion, name: &str, ipv4: bool) {
+    let db = match load_geoip() {
+        Some(db) => db,
+        None => return,
+    };
+    let (cidrs, queries) = cn_cidrs_and_queries(&db);
+    let queries: Vec<IpAddr> = queries
+        .iter()
+        .filter(|c| c.is_ipv4() == ipv4)
+        .map(|c| c.addr())
+        .collect();
+    let matcher = M::build_from(cidrs.into_iter().filter(|c| c.is_ipv4() == ipv4));
+    b.bench_function(name, |b| {
+        b.iter(|| {
+            for ip in queries.iter() {
+                black_box(matcher.contains(*ip));
             }
-        }
-    }
+        })
+    });
 }
 
-fn benchmark_geoip_matcher_impl(matcher: &GeoIPMatcher, geoip_list: &GeoIPList) {
-    for i in geoip_list.entry.iter() {
-        for pair in i.cidr.iter() {
-            matcher.match_ip(&pair.ip);
+fn benchmark_ipv4(b: &mut Criterion) {
+    benchmark_family::<LPCTrie<u32>>(b, "benchmark lpc v4", true);
+    benchmark_family::<Dir24Table>(b, "benchmark dir-24-8", true);
+    benchmark_family::<Poptrie<u32>>(b, "benchmark poptrie v4", true);
+}
+
+fn benchmark_ipv6(b: &mut Criterion) {
+    benchmark_family::<LPCTrie<u128>>(b, "benchmark lpc v6", false);
+    benchmark_family::<Poptrie<u128>>(b, "benchmark poptrie v6", false);
+}
+
+fn benchmark_radix(b: &mut Criterion) {
+    let db = match load_geoip() {
+        Some(db) => db,
+        None => return,
+    };
+    let (cidrs, queries) = cn_cidrs_and_queries(&db);
+    let mut radix_trie_v6 = Trie::<Vec<u8>, String>::new();
+    let mut radix_trie_v4 = Trie::<u32, String>::new();
+    for cidr in cidrs {
+        match cidr.addr() {
+            IpAddr::V6(ip) => {
+                radix_trie_v6.insert(ip.octets().to_vec(), "CN".to_string());
+            }
+            IpAddr::V4(ip) => {
+                radix_trie_v4.insert(u32::from(ip), "CN".to_string());
+            }
         }
     }
+    b.bench_function("benchmark radix trie", |b| {
+        b.iter(|| benchmark_radix_trie_impl(&radix_trie_v4, &radix_trie_v6, &queries))
+    });
 }
 
 fn benchmark_radix_trie_impl(
     trie_v4: &Trie<u32, String>,
     trie_v6: &Trie<Vec<u8>, String>,
-    geoip_list: &GeoIPList,
+    queries: &[Cidr],
 ) {
-    for i in geoip_list.entry.iter() {
-        for pair in i.cidr.iter() {
-            let len = pair.ip.len();
-            match len {
-                16 => {
-                    trie_v6.get(&pair.ip);
-                }
-                4 => {
-                    let inner = pair.ip.clone().try_into().unwrap();
-                    let bin_str =
-                        u32::from_be_bytes(inner) >> (32 - pair.prefix) << (32 - pair.prefix);
-                    trie_v4.get(&bin_str);
-                }
-                _ => {
-                    eprintln!("invalid ip length detected");
-                }
+    for cidr in queries {
+        match cidr.addr() {
+            IpAddr::V6(ip) => {
+                trie_v6.get(&ip.octets().to_vec());
+            }
+            IpAddr::V4(ip) => {
+                trie_v4.get(&u32::from(ip));
             }
         }
     }
@@ -162,6 +148,10 @@ criterion_group!(
     benches,
     benchmark_radix,
     benchmark_lpc,
-    benchmark_v2ray_core_matcher
+    benchmark_prefilter,
+    benchmark_v2ray_core_matcher,
+    benchmark_s_tree,
+    benchmark_ipv4,
+    benchmark_ipv6
 );
 criterion_main!(benches);
@@ -0,0 +1,3 @@
+// The exported functions live in cidr_matcher::ffi, this crate only gives
+// them a cdylib and a staticlib to be linked from.
+pub use cidr_matcher::ffi::*;
@@ -0,0 +1,117 @@
+/*
+ * C interface of the cidr_matcher crate, generated from src/ffi.rs by
+ * cbindgen. Do not edit, regenerate it with
+ *
+ *   CIDR_MATCHER_WRITE_HEADER=1 cargo test --features ffi --test ffi
+ *
+ * Build the library with
+ *
+ *   cargo build --release -p cidr_matcher_ffi
+ *
+ * for libcidr_matcher_ffi.a and libcidr_matcher_ffi.so.
+ *
+ * A handle holds an IPv4 and an IPv6 longest prefix match trie whose
+ * prefixes map to string values, the country codes of a geoip.dat or any
+ * value given to cidr_matcher_add. Values are numbered by id in the order
+ * they are first seen, and ids stay valid until the handle is freed.
+ *
+ * A handle may be read from several threads at once, but must not be
+ * changed while it is read. Strings are nul terminated UTF-8.
+ */
+
+#ifndef CIDR_MATCHER_H
+#define CIDR_MATCHER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * a NULL handle or string, or a string that is not UTF-8
+ */
+#define CIDR_MATCHER_ERR_ARGUMENT -1
+
+/**
+ * a cidr that does not parse, in the argument or in a geoip.dat
+ */
+#define CIDR_MATCHER_ERR_CIDR -2
+
+/**
+ * the geoip.dat could not be read
+ */
+#define CIDR_MATCHER_ERR_IO -3
+
+/**
+ * the geoip.dat is not a GeoIPList, or lists a code with and without
+ * reverse_match
+ */
+#define CIDR_MATCHER_ERR_FORMAT -4
+
+/**
+ * a bug in the library, the call was abandoned
+ */
+#define CIDR_MATCHER_ERR_PANIC -5
+
+typedef struct CidrMatcher CidrMatcher;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * an empty matcher, released with cidr_matcher_free.
+ */
+CidrMatcher *cidr_matcher_new(void);
+
+/**
+ * frees the handle and its values, NULL is ignored.
+ */
+void cidr_matcher_free(CidrMatcher *matcher);
+
+/**
+ * Adds every cidr of a geoip.dat under its upper case country code. A
+ * prefix that is already there keeps its value, so on a prefix listed by
+ * two codes the one loaded first wins. reverse_match is not applied.
+ * Returns the number of cidrs read or a CIDR_MATCHER_ERR_* code, nothing is
+ * added on error.
+ */
+int cidr_matcher_load_geoip_file(CidrMatcher *matcher, const char *path);
+
+/**
+ * The same for the len bytes of a geoip.dat at data, which may be NULL when
+ * len is 0.
+ */
+int cidr_matcher_load_geoip_buffer(CidrMatcher *matcher, const uint8_t *data, size_t len);
+
+/**
+ * Sets the value of a cidr such as "1.0.1.0/24" or "240e::/20", replacing
+ * what the prefix held. Returns the id of value or a CIDR_MATCHER_ERR_* code.
+ */
+int cidr_matcher_add(CidrMatcher *matcher, const char *cidr, const char *value);
+
+/**
+ * Drops exactly that prefix. Returns 1 if it was there, 0 if not, or a
+ * CIDR_MATCHER_ERR_* code.
+ */
+int cidr_matcher_remove(CidrMatcher *matcher, const char *cidr);
+
+/**
+ * The value id of the longest prefix covering the address, -1 when none
+ * does. The address is in host order, 0x01020304 for 1.2.3.4.
+ */
+int32_t cidr_matcher_lookup_v4(const CidrMatcher *matcher, uint32_t addr);
+
+/**
+ * The same for an IPv6 address, 16 bytes in network order.
+ */
+int32_t cidr_matcher_lookup_v6(const CidrMatcher *matcher, const uint8_t *addr);
+
+/**
+ * The value of an id, owned by the handle, or NULL for an unknown id.
+ */
+const char *cidr_matcher_value(const CidrMatcher *matcher, int32_t id);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CIDR_MATCHER_H */
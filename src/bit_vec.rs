@@ -13,6 +13,14 @@ pub trait BitVec: Sized + Copy + Clone + Eq + PartialEq {
     fn from_bit_str(_: &str) -> Self;
 
     fn is_empty(&self) -> bool;
+
+    // width of the vector in bits.
+    fn bits() -> u32;
+
+    fn to_u128(&self) -> u128;
+
+    // truncating conversion, only the low bits() bits are kept.
+    fn from_u128(value: u128) -> Self;
 }
 
 const fn bit_size_of<T>() -> usize {
@@ -79,6 +87,21 @@ macro_rules! bit_vec_impl {
             fn is_empty(&self) -> bool {
                 *self == 0
             }
+
+            #[inline(always)]
+            fn bits() -> u32 {
+                bit_size_of::<$T>() as u32
+            }
+
+            #[inline(always)]
+            fn to_u128(&self) -> u128 {
+                *self as u128
+            }
+
+            #[inline(always)]
+            fn from_u128(value: u128) -> Self {
+                value as $T
+            }
         }
     };
 }
@@ -0,0 +1,434 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Cidr {
+    // network address with host bits cleared, and the prefix length.
+    V4(u32, u8),
+    V6(u128, u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CidrError {
+    // ip should be either 4 or 16 bytes.
+    InvalidLength(usize),
+    InvalidPrefix(u32),
+    InvalidAddress(String),
+}
+
+impl fmt::Display for CidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CidrError::InvalidLength(len) => write!(f, "invalid ip length {}", len),
+            CidrError::InvalidPrefix(prefix) => write!(f, "invalid prefix length {}", prefix),
+            CidrError::InvalidAddress(s) => write!(f, "invalid address {:?}", s),
+        }
+    }
+}
+
+impl std::error::Error for CidrError {}
+
+#[inline]
+pub fn mask_v4(addr: u32, prefix: u8) -> u32 {
+    addr & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+#[inline]
+pub fn mask_v6(addr: u128, prefix: u8) -> u128 {
+    addr & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Cidr, CidrError> {
+        match addr {
+            IpAddr::V4(v4) if prefix <= 32 => Ok(Cidr::V4(mask_v4(v4.into(), prefix), prefix)),
+            IpAddr::V6(v6) if prefix <= 128 => Ok(Cidr::V6(mask_v6(v6.into(), prefix), prefix)),
+            _ => Err(CidrError::InvalidPrefix(prefix as u32)),
+        }
+    }
+
+    // build from the raw big endian bytes used by geoip.dat.
+    pub fn from_bytes(ip: &[u8], prefix: u32) -> Result<Cidr, CidrError> {
+        let addr = match ip.len() {
+            4 => IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
+            16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(ip);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            len => return Err(CidrError::InvalidLength(len)),
+        };
+        if prefix > 128 {
+            return Err(CidrError::InvalidPrefix(prefix));
+        }
+        Cidr::new(addr, prefix as u8)
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        match *self {
+            Cidr::V4(addr, _) => IpAddr::V4(addr.into()),
+            Cidr::V6(addr, _) => IpAddr::V6(addr.into()),
+        }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        match *self {
+            Cidr::V4(_, prefix) | Cidr::V6(_, prefix) => prefix,
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        matches!(self, Cidr::V4(..))
+    }
+
+    // first and last address as integers, and the address width in bits.
+    pub(crate) fn bounds(&self) -> (u128, u128, u32) {
+        match *self {
+            Cidr::V4(addr, prefix) => {
+                let addr = addr as u128;
+                (addr, addr | host_mask(32 - prefix as u32), 32)
+            }
+            Cidr::V6(addr, prefix) => (addr, addr | host_mask(128 - prefix as u32), 128),
+        }
+    }
+
+    pub fn first(&self) -> IpAddr {
+        self.addr()
+    }
+
+    pub fn last(&self) -> IpAddr {
+        match self.bounds() {
+            (_, last, 32) => IpAddr::V4((last as u32).into()),
+            (_, last, _) => IpAddr::V6(last.into()),
+        }
+    }
+
+    // the minimal list of cidrs covering start..=end, both of the same family.
+    pub fn range(start: IpAddr, end: IpAddr) -> Result<Vec<Cidr>, CidrError> {
+        match (start, end) {
+            (IpAddr::V4(s), IpAddr::V4(e)) if s <= e => Ok(range_to_cidrs(
+                u32::from(s) as u128,
+                u32::from(e) as u128,
+                32,
+            )),
+            (IpAddr::V6(s), IpAddr::V6(e)) if s <= e => {
+                Ok(range_to_cidrs(u128::from(s), u128::from(e), 128))
+            }
+            _ => Err(CidrError::InvalidAddress(format!("{}-{}", start, end))),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (*self, ip) {
+            (Cidr::V4(addr, prefix), IpAddr::V4(ip)) => mask_v4(ip.into(), prefix) == addr,
+            (Cidr::V6(addr, prefix), IpAddr::V6(ip)) => mask_v6(ip.into(), prefix) == addr,
+            _ => false,
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn host_mask(bits: u32) -> u128 {
+    u128::MAX.checked_shr(128 - bits).unwrap_or(0)
+}
+
+fn range_to_cidrs(start: u128, end: u128, width: u32) -> Vec<Cidr> {
+    range_to_prefixes(start, end, width)
+        .into_iter()
+        .map(|(addr, prefix)| {
+            if width == 32 {
+                Cidr::V4(addr as u32, prefix)
+            } else {
+                Cidr::V6(addr, prefix)
+            }
+        })
+        .collect()
+}
+
+// the minimal (network, prefix) list covering start..=end of a width bits space.
+pub(crate) fn range_to_prefixes(mut start: u128, end: u128, width: u32) -> Vec<(u128, u8)> {
+    let mut prefixes = vec![];
+    loop {
+        // the biggest block aligned at start which does not run past end.
+        let mut bits = if start == 0 {
+            width
+        } else {
+            start.trailing_zeros().min(width)
+        };
+        while end - start < host_mask(bits) {
+            bits -= 1;
+        }
+        prefixes.push((start, (width - bits) as u8));
+        let last = start + host_mask(bits);
+        if last >= end {
+            return prefixes;
+        }
+        start = last + 1;
+    }
+}
+
+// Sort (first, last) ranges and merge the overlapping and adjacent ones.
+pub(crate) fn merge_ranges(mut ranges: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some((_, l)) if *l == u128::MAX || first <= *l + 1 => *l = (*l).max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+// The gaps left by the ranges in a width bits space.
+pub(crate) fn complement_ranges(ranges: Vec<(u128, u128)>, width: u32) -> Vec<(u128, u128)> {
+    let max = host_mask(width);
+    let mut gaps = vec![];
+    let mut next = Some(0u128);
+    for (first, last) in merge_ranges(ranges) {
+        if let Some(n) = next {
+            if first > n {
+                gaps.push((n, first - 1));
+            }
+        }
+        next = if last >= max { None } else { Some(last + 1) };
+    }
+    if let Some(n) = next {
+        gaps.push((n, max));
+    }
+    gaps
+}
+
+// Merge overlapping and adjacent cidrs into the minimal sorted list covering
+// the same addresses, IPv4 first.
+pub fn aggregate<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Vec<Cidr> {
+    let mut v4 = vec![];
+    let mut v6 = vec![];
+    for cidr in cidrs {
+        let (first, last, width) = cidr.bounds();
+        if width == 32 {
+            v4.push((first, last));
+        } else {
+            v6.push((first, last));
+        }
+    }
+    let mut result = vec![];
+    for (first, last) in merge_ranges(v4) {
+        result.extend(range_to_cidrs(first, last, 32));
+    }
+    for (first, last) in merge_ranges(v6) {
+        result.extend(range_to_cidrs(first, last, 128));
+    }
+    result
+}
+
+// the merged ranges of each family, IPv4 first.
+fn family_ranges<I: IntoIterator<Item = Cidr>>(cidrs: I) -> [Vec<(u128, u128)>; 2] {
+    let mut ranges = [vec![], vec![]];
+    for cidr in cidrs {
+        let (first, last, width) = cidr.bounds();
+        ranges[(width == 128) as usize].push((first, last));
+    }
+    ranges
+}
+
+// a set operation on the ranges of one family, back to a sorted cidr list.
+fn combine<F>(a: Vec<Cidr>, b: Vec<Cidr>, op: F) -> Vec<Cidr>
+where
+    F: Fn(Vec<(u128, u128)>, Vec<(u128, u128)>, u32) -> Vec<(u128, u128)>,
+{
+    let [a4, a6] = family_ranges(a);
+    let [b4, b6] = family_ranges(b);
+    let mut result = vec![];
+    for (first, last) in merge_ranges(op(a4, b4, 32)) {
+        result.extend(range_to_cidrs(first, last, 32));
+    }
+    for (first, last) in merge_ranges(op(a6, b6, 128)) {
+        result.extend(range_to_cidrs(first, last, 128));
+    }
+    result
+}
+
+// The addresses of both lists, as aggregate gives them.
+pub fn union<A, B>(a: A, b: B) -> Vec<Cidr>
+where
+    A: IntoIterator<Item = Cidr>,
+    B: IntoIterator<Item = Cidr>,
+{
+    aggregate(a.into_iter().chain(b))
+}
+
+// The addresses in both lists, as a minimal sorted list.
+pub fn intersection<A, B>(a: A, b: B) -> Vec<Cidr>
+where
+    A: IntoIterator<Item = Cidr>,
+    B: IntoIterator<Item = Cidr>,
+{
+    combine(
+        a.into_iter().collect(),
+        b.into_iter().collect(),
+        |a, b, w| {
+            let mut outside = complement_ranges(a, w);
+            outside.extend(complement_ranges(b, w));
+            complement_ranges(outside, w)
+        },
+    )
+}
+
+// The addresses of a outside b, as a minimal sorted list.
+pub fn difference<A, B>(a: A, b: B) -> Vec<Cidr>
+where
+    A: IntoIterator<Item = Cidr>,
+    B: IntoIterator<Item = Cidr>,
+{
+    combine(
+        a.into_iter().collect(),
+        b.into_iter().collect(),
+        |a, b, w| {
+            let mut outside = complement_ranges(a, w);
+            outside.extend(b);
+            complement_ranges(outside, w)
+        },
+    )
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    // accepts "addr/prefix", a bare address is taken as a host route.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.find('/') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| CidrError::InvalidAddress(s.to_string()))?;
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .map_err(|_| CidrError::InvalidAddress(s.to_string()))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr(), self.prefix())
+    }
+}
+
+#[test]
+fn test_cidr() {
+    let cidr: Cidr = "10.1.2.3/8".parse().unwrap();
+    assert_eq!(cidr, Cidr::V4(0x0a00_0000, 8));
+    assert_eq!(cidr.to_string(), "10.0.0.0/8");
+    assert!(cidr.contains("10.255.0.1".parse().unwrap()));
+    assert!(!cidr.contains("11.0.0.1".parse().unwrap()));
+    assert!(!cidr.contains("::a00:1".parse().unwrap()));
+
+    assert_eq!("0.0.0.0/0".parse::<Cidr>().unwrap(), Cidr::V4(0, 0));
+    assert_eq!("1.2.3.4".parse::<Cidr>().unwrap().prefix(), 32);
+    assert_eq!("2001:db8::1".parse::<Cidr>().unwrap().prefix(), 128);
+    assert_eq!(
+        "2001:db8:ffff::/32".parse::<Cidr>().unwrap().to_string(),
+        "2001:db8::/32"
+    );
+    assert_eq!(
+        "1.2.3.4/33".parse::<Cidr>(),
+        Err(CidrError::InvalidPrefix(33))
+    );
+    assert!("1.2.3/24".parse::<Cidr>().is_err());
+    assert_eq!(
+        Cidr::from_bytes(&[1, 2, 3], 24),
+        Err(CidrError::InvalidLength(3))
+    );
+    assert_eq!(
+        Cidr::from_bytes(&[192, 168, 1, 1], 16).unwrap(),
+        "192.168.0.0/16".parse().unwrap()
+    );
+    assert_eq!(cidr.last(), "10.255.255.255".parse::<IpAddr>().unwrap());
+}
+
+#[test]
+fn test_range_and_aggregate() {
+    let parse = |v: &[&str]| v.iter().map(|s| s.parse().unwrap()).collect::<Vec<Cidr>>();
+    assert_eq!(
+        Cidr::range("10.0.0.1".parse().unwrap(), "10.0.0.6".parse().unwrap()).unwrap(),
+        parse(&["10.0.0.1/32", "10.0.0.2/31", "10.0.0.4/31", "10.0.0.6/32"])
+    );
+    assert_eq!(
+        Cidr::range(
+            "0.0.0.0".parse().unwrap(),
+            "255.255.255.255".parse().unwrap()
+        )
+        .unwrap(),
+        parse(&["0.0.0.0/0"])
+    );
+    assert_eq!(
+        Cidr::range(
+            "::".parse().unwrap(),
+            "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".parse().unwrap()
+        )
+        .unwrap(),
+        parse(&["::/0"])
+    );
+    assert!(Cidr::range("10.0.0.2".parse().unwrap(), "10.0.0.1".parse().unwrap()).is_err());
+    assert!(Cidr::range("10.0.0.2".parse().unwrap(), "::1".parse().unwrap()).is_err());
+
+    assert_eq!(
+        aggregate(parse(&[
+            "2001:db8::/33",
+            "10.0.1.0/24",
+            "10.0.0.0/24",
+            "10.0.0.128/25",
+            "2001:db8:8000::/33",
+            "192.168.0.0/16",
+            "192.169.0.0/24",
+        ])),
+        parse(&[
+            "10.0.0.0/23",
+            "192.168.0.0/16",
+            "192.169.0.0/24",
+            "2001:db8::/32"
+        ])
+    );
+    assert_eq!(
+        aggregate(parse(&["0.0.0.0/1", "128.0.0.0/1", "10.0.0.0/8"])),
+        parse(&["0.0.0.0/0"])
+    );
+
+    let a = parse(&["10.0.0.0/8", "192.168.0.0/24", "2001:db8::/32"]);
+    let b = parse(&["10.1.0.0/16", "192.168.0.128/25", "172.16.0.0/12", "::/0"]);
+    assert_eq!(
+        union(a.clone(), b.clone()),
+        parse(&["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/24", "::/0"])
+    );
+    assert_eq!(
+        intersection(a.clone(), b.clone()),
+        parse(&["10.1.0.0/16", "192.168.0.128/25", "2001:db8::/32"])
+    );
+    assert_eq!(
+        difference(a.clone(), b.clone()),
+        parse(&[
+            "10.0.0.0/16",
+            "10.2.0.0/15",
+            "10.4.0.0/14",
+            "10.8.0.0/13",
+            "10.16.0.0/12",
+            "10.32.0.0/11",
+            "10.64.0.0/10",
+            "10.128.0.0/9",
+            "192.168.0.0/25"
+        ])
+    );
+    assert_eq!(
+        difference(b, parse(&["0.0.0.0/0", "::/1", "8000::/1"])),
+        vec![]
+    );
+    assert!(intersection(a, vec![]).is_empty());
+}
@@ -1,173 +1,641 @@
-use crate::geoip::GeoIP;
-use std::cmp::Ordering;
+use crate::cidr::{merge_ranges, Cidr};
+use crate::geoip::{GeoIP, GeoIPList};
+use crate::geoip_db::{entry_cidrs, GeoIpError};
+use crate::matcher::IpMatcher;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::convert::TryInto;
+use std::net::IpAddr;
+use std::sync::OnceLock;
 
-#[derive(Eq, PartialEq)]
-pub struct V6 {
-    a: u64,
-    b: u64,
+#[cfg(test)]
+use crate::test_util::XorShift;
+
+// The binary search matcher of v2ray-core. The cidrs are kept as sorted,
+// disjoint [first, last] intervals, overlapping and adjacent ones merged, so
+// a lookup only has to check the last interval starting at or before the
+// address.
+#[derive(Clone)]
+pub struct GeoIPMatcher {
+    country_code: String,
+    reverse_match: bool,
+    ip4: Vec<(u32, u32)>,
+    ip6: Vec<(u128, u128)>,
+}
+
+fn search<K: Ord + Copy>(ranges: &[(K, K)], ip: K) -> bool {
+    let idx = ranges.partition_point(|(first, _)| *first <= ip);
+    idx > 0 && ip <= ranges[idx - 1].1
 }
 
-impl Ord for V6 {
-    fn cmp(&self, other: &Self) -> Ordering {
-        if self.a < other.a || (self.a == other.a && self.b < other.b) {
-            return Ordering::Less;
-        } else if self == other {
-            return Ordering::Equal;
+impl GeoIPMatcher {
+    fn match4(&self, ip: u32) -> bool {
+        search(&self.ip4, ip)
+    }
+
+    fn match6(&self, ip: u128) -> bool {
+        search(&self.ip6, ip)
+    }
+
+    // add the cidrs of geoip to the ones already put. The country code and
+    // reverse match flag are taken from geoip. Nothing is added when one of
+    // its cidrs is invalid.
+    pub fn put(&mut self, geoip: &GeoIP) -> Result<(), GeoIpError> {
+        let country_code = geoip.country_code.to_uppercase();
+        let cidrs = entry_cidrs(geoip, &country_code)?;
+        self.country_code = country_code;
+        self.reverse_match = geoip.reverse_match;
+        self.add_cidrs(cidrs);
+        Ok(())
+    }
+
+    fn add_cidrs<I: IntoIterator<Item = Cidr>>(&mut self, cidrs: I) {
+        let mut v4: Vec<(u128, u128)> = self
+            .ip4
+            .iter()
+            .map(|&(first, last)| (first as u128, last as u128))
+            .collect();
+        let mut v6 = std::mem::take(&mut self.ip6);
+        for cidr in cidrs {
+            let (first, last, width) = cidr.bounds();
+            if width == 32 {
+                v4.push((first, last));
+            } else {
+                v6.push((first, last));
+            }
         }
-        Ordering::Greater
+        self.ip4 = merge_ranges(v4)
+            .into_iter()
+            .map(|(first, last)| (first as u32, last as u32))
+            .collect();
+        self.ip6 = merge_ranges(v6);
+    }
+
+    pub fn new() -> GeoIPMatcher {
+        GeoIPMatcher {
+            country_code: "".to_string(),
+            reverse_match: false,
+            ip4: vec![],
+            ip6: vec![],
+        }
+    }
+
+    pub fn set_reverse_match(&mut self, reverse_match: bool) {
+        self.reverse_match = reverse_match;
     }
-}
 
-impl PartialOrd for V6 {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    // a matcher over the same cidrs that matches exactly the addresses this one does not.
+    pub fn complement(&self) -> GeoIPMatcher {
+        let mut matcher = self.clone();
+        matcher.reverse_match = !self.reverse_match;
+        matcher
+    }
+
+    // false for anything but a 4 or 16 byte address, reverse match or not.
+    pub fn match_ip(&self, v: &std::vec::Vec<u8>) -> bool {
+        let matched = match v.len() {
+            4 => self.match4(u32::from_be_bytes(v[..].try_into().unwrap())),
+            16 => self.match6(u128::from_be_bytes(v[..].try_into().unwrap())),
+            _ => return false,
+        };
+        matched != self.reverse_match
     }
 }
 
-impl V6 {
-    fn new(vec: &Vec<u8>) -> V6 {
-        let inner = vec[0..8].try_into().unwrap();
-        let inner2 = vec[0..8].try_into().unwrap();
-        let v6 = V6 {
-            a: u64::from_be_bytes(inner),
-            b: u64::from_be_bytes(inner2),
+impl IpMatcher for GeoIPMatcher {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let matched = match ip {
+            IpAddr::V4(ip) => self.match4(ip.into()),
+            IpAddr::V6(ip) => self.match6(ip.into()),
         };
-        v6
+        matched != self.reverse_match
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        let mut matcher = GeoIPMatcher::new();
+        matcher.add_cidrs(cidrs);
+        matcher
+    }
+}
+
+// The keys of an STree. MAX is what unused slots of its lines hold, rank
+// how many of the sorted keys of a line are at or before ip.
+trait Key: Ord + Copy {
+    const MAX: Self;
+
+    #[inline]
+    fn rank(keys: &[Self], ip: Self) -> usize {
+        keys.iter().map(|key| (*key <= ip) as usize).sum()
     }
+}
+
+impl Key for u32 {
+    const MAX: Self = u32::MAX;
+
+    // The keys past ip are a run of high bits in the compare mask, its
+    // trailing zeros count the rest. Lines hold 8 or 16 keys.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn rank(keys: &[Self], ip: Self) -> usize {
+        use std::arch::x86_64::*;
+        assert!(keys.len() == 8 || keys.len() == 16);
+        // SSE2 is part of x86_64, and the loads stay in keys.
+        unsafe {
+            let flip = _mm_set1_epi32(i32::MIN);
+            let ip = _mm_xor_si128(_mm_set1_epi32(ip as i32), flip);
+            let p = keys.as_ptr() as *const __m128i;
+            let after = |i| _mm_cmpgt_epi32(_mm_xor_si128(_mm_loadu_si128(p.add(i)), flip), ip);
+            let low = _mm_packs_epi32(after(0), after(1));
+            let high = if keys.len() == 16 {
+                _mm_packs_epi32(after(2), after(3))
+            } else {
+                _mm_set1_epi16(-1)
+            };
+            let mask = _mm_movemask_epi8(_mm_packs_epi16(low, high)) as u32;
+            (mask | 1 << 16).trailing_zeros() as usize
+        }
+    }
+}
+
+impl Key for u128 {
+    const MAX: Self = u128::MAX;
+}
 
-    fn normalize_new(vec: &Vec<u8>, prefix: u8) -> V6 {
-        let inner = vec[0..8].try_into().unwrap();
-        let inner2 = vec[0..8].try_into().unwrap();
-        let mut v6 = V6 {
-            a: u64::from_be_bytes(inner),
-            b: u64::from_be_bytes(inner2),
+// an inner node of an STree, N separator keys filling a cache line.
+#[derive(Clone)]
+#[repr(C, align(64))]
+struct Separators<K, const N: usize>([K; N]);
+
+// a leaf of an STree, the first and last addresses of L intervals in one
+// cache line.
+#[derive(Clone)]
+#[repr(C, align(64))]
+struct Leaf<K, const L: usize> {
+    first: [K; L],
+    last: [K; L],
+}
+
+// Sorted intervals in an implicit B+ tree (S+ tree) of cache line sized
+// nodes, stored level by level from the root. Child c of node k sits at
+// k * (N + 1) + c one level down, separator c of a node is the first
+// address under child c + 1, so a lookup follows the count of separators at
+// or before ip. Slots past the last interval hold K::MAX, which a lookup for
+// K::MAX itself would count, so that one is answered apart.
+#[derive(Clone)]
+struct STree<K, const N: usize, const L: usize> {
+    inner: Vec<Separators<K, N>>,
+    levels: Vec<usize>,
+    leaves: Vec<Leaf<K, L>>,
+    covers_max: bool,
+}
+
+impl<K: Key, const N: usize, const L: usize> STree<K, N, L> {
+    fn new(sorted: &[(K, K)]) -> Self {
+        let leaves: Vec<Leaf<K, L>> = sorted
+            .chunks(L)
+            .map(|chunk| {
+                let mut leaf = Leaf {
+                    first: [K::MAX; L],
+                    last: [K::MAX; L],
+                };
+                for (i, &(first, last)) in chunk.iter().enumerate() {
+                    leaf.first[i] = first;
+                    leaf.last[i] = last;
+                }
+                leaf
+            })
+            .collect();
+        let mut sizes = vec![];
+        let mut size = leaves.len();
+        while size > 1 {
+            size = size.div_ceil(N + 1);
+            sizes.push(size);
+        }
+        let mut tree = STree {
+            inner: vec![],
+            levels: vec![],
+            leaves: vec![],
+            covers_max: sorted.last().is_some_and(|&(_, last)| last == K::MAX),
         };
-        v6.normalize(prefix);
-        v6
+        // span leaves hang below each child of a node on level height.
+        for (height, &size) in sizes.iter().enumerate().rev() {
+            let span = (N + 1).pow(height as u32);
+            tree.levels.push(tree.inner.len());
+            for k in 0..size {
+                let mut keys = [K::MAX; N];
+                for (c, key) in keys.iter_mut().enumerate() {
+                    if let Some(leaf) = leaves.get((k * (N + 1) + c + 1) * span) {
+                        *key = leaf.first[0];
+                    }
+                }
+                tree.inner.push(Separators(keys));
+            }
+        }
+        tree.leaves = leaves;
+        tree
     }
 
-    fn normalize(&mut self, prefix: u8) {
-        if prefix <= 64 {
-            self.a = self.a >> (64 - prefix) << (64 - prefix);
-            self.b = 0;
-        } else {
-            self.b = self.b >> (128 - prefix) << (128 - prefix);
+    #[inline]
+    fn contains(&self, ip: K) -> bool {
+        if ip == K::MAX {
+            return self.covers_max;
+        }
+        let mut k = 0;
+        for &level in self.levels.iter() {
+            let keys = &self.inner[level + k].0;
+            k = k * (N + 1) + K::rank(keys, ip);
+        }
+        let leaf = match self.leaves.get(k) {
+            Some(leaf) => leaf,
+            None => return false,
+        };
+        match K::rank(&leaf.first, ip) {
+            0 => false,
+            i => ip <= leaf.last[i - 1],
         }
     }
-    fn normalize6(ip: &V6, prefix: u8) -> V6 {
-        let mut a = 0u64;
-        let b: u64;
-        if prefix <= 64 {
-            a = ip.a >> (64 - prefix) << (64 - prefix);
-            b = 0;
-        } else {
-            b = ip.b >> (128 - prefix) << (128 - prefix);
+}
+
+// A read only copy of a GeoIPMatcher in S+ tree order, a lookup reads one
+// cache line per level: 16 IPv4 or 4 IPv6 separators per inner node, 8 IPv4
+// or 2 IPv6 intervals per leaf.
+#[derive(Clone)]
+pub struct STreeMatcher {
+    reverse_match: bool,
+    ip4: STree<u32, 16, 8>,
+    ip6: STree<u128, 4, 2>,
+}
+
+impl From<&GeoIPMatcher> for STreeMatcher {
+    fn from(matcher: &GeoIPMatcher) -> Self {
+        STreeMatcher {
+            reverse_match: matcher.reverse_match,
+            ip4: STree::new(&matcher.ip4),
+            ip6: STree::new(&matcher.ip6),
         }
-        V6 { a, b }
     }
 }
 
-pub struct GeoIPMatcher {
-    country_code: String,
-    ip4: Vec<u32>,
-    prefix4: Vec<u8>,
-    ip6: Vec<V6>,
-    prefix6: Vec<u8>,
+impl IpMatcher for STreeMatcher {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let matched = match ip {
+            IpAddr::V4(ip) => self.ip4.contains(u32::from(ip)),
+            IpAddr::V6(ip) => self.ip6.contains(u128::from(ip)),
+        };
+        matched != self.reverse_match
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        STreeMatcher::from(&GeoIPMatcher::build_from(cidrs))
+    }
 }
 
-impl GeoIPMatcher {
-    fn match4(&self, ip: u32) -> bool {
-        if ip < self.ip4[0] {
-            return false;
-        }
-        let mut r = self.ip4.len();
-        let mut l: usize = 0;
-        while l < r {
-            let x = (l + r) >> 1;
-            if ip < self.ip4[x] {
-                r = x;
-                continue;
+// Sorted, disjoint (first, last, country) intervals from the intervals of
+// every country, where countries overlap the lowest index owns the
+// addresses. One sweep over the starts, the candidates kept in a heap.
+fn paint(mut ranges: Vec<(u128, u128, u32)>) -> Vec<(u128, u128, u32)> {
+    ranges.sort_unstable();
+    let mut painted: Vec<(u128, u128, u32)> = vec![];
+    // (country, last) of the intervals started so far, ended ones are
+    // dropped once they come up.
+    let mut active = BinaryHeap::new();
+    let mut next = 0;
+    let mut pos = 0;
+    loop {
+        if active.is_empty() {
+            match ranges.get(next) {
+                Some(&(first, _, _)) => pos = first,
+                None => break,
             }
-            let nip = ip >> (32 - self.prefix4[x]) << (32 - self.prefix4[x]);
-            if nip == self.ip4[x] {
-                return true;
+        }
+        while let Some(&(first, last, country)) = ranges.get(next) {
+            if first > pos {
+                break;
             }
-            l = x + 1;
+            active.push(Reverse((country, last)));
+            next += 1;
         }
-        let nip = ip >> (32 - self.prefix4[l - 1]) << (32 - self.prefix4[l - 1]);
-        return l > 0 && nip == self.ip4[l - 1];
+        let (country, last) = match active.peek() {
+            Some(&Reverse((_, last))) if last < pos => {
+                active.pop();
+                continue;
+            }
+            Some(&Reverse(top)) => top,
+            None => continue,
+        };
+        // the owner holds up to where the next interval starts.
+        let end = match ranges.get(next) {
+            Some(&(first, _, _)) if first <= last => first - 1,
+            _ => last,
+        };
+        match painted.last_mut() {
+            Some(p) if p.2 == country && p.1.checked_add(1) == Some(pos) => p.1 = end,
+            _ => painted.push((pos, end, country)),
+        }
+        pos = match end.checked_add(1) {
+            Some(pos) => pos,
+            None => break,
+        };
+    }
+    painted
+}
+
+fn locate<K: Ord + Copy>(ranges: &[(K, K, u32)], ip: K) -> Option<u32> {
+    let idx = ranges.partition_point(|(first, _, _)| *first <= ip);
+    match ranges.get(idx.checked_sub(1)?) {
+        Some(&(_, last, country)) if ip <= last => Some(country),
+        _ => None,
+    }
+}
+
+// Several countries in one sorted interval array, each interval holding the
+// index of its country. Where countries overlap the first one put owns the
+// addresses, as GeoIpDatabase::country_of answers. The array is built on the
+// first lookup after a put, or by finish.
+#[derive(Clone, Default)]
+pub struct GeoIPMatcherSet {
+    countries: Vec<GeoIPMatcher>,
+    index: HashMap<String, u32>,
+    // reset by every put.
+    intervals: OnceLock<Intervals>,
+}
+
+#[derive(Clone)]
+struct Intervals {
+    ip4: Vec<(u32, u32, u32)>,
+    ip6: Vec<(u128, u128, u32)>,
+}
+
+impl GeoIPMatcherSet {
+    pub fn new() -> GeoIPMatcherSet {
+        GeoIPMatcherSet::default()
     }
 
-    fn match6(&self, ip: &V6) -> bool {
-        if ip < &self.ip6[0] {
-            return false;
+    pub fn from_list(list: &GeoIPList) -> Result<GeoIPMatcherSet, GeoIpError> {
+        let mut set = GeoIPMatcherSet::new();
+        for geoip in list.entry.iter() {
+            set.put(geoip)?;
         }
-        let mut r = self.ip6.len();
-        let mut l: usize = 0;
-        while l < r {
-            let x = (l + r) >> 1;
-            if ip < &self.ip6[x] {
-                r = x;
-                continue;
+        set.finish();
+        Ok(set)
+    }
+
+    // A country put again keeps its place.
+    pub fn put(&mut self, geoip: &GeoIP) -> Result<(), GeoIpError> {
+        let code = geoip.country_code.to_uppercase();
+        match self.index.get(&code) {
+            Some(&idx) => self.countries[idx as usize].put(geoip)?,
+            None => {
+                let mut matcher = GeoIPMatcher::new();
+                matcher.put(geoip)?;
+                self.index.insert(code, self.countries.len() as u32);
+                self.countries.push(matcher);
             }
-            let nip = V6::normalize6(ip, self.prefix6[x]);
-            if nip == self.ip6[x] {
-                return true;
+        }
+        self.intervals = OnceLock::new();
+        Ok(())
+    }
+
+    // build the interval arrays now rather than on the next lookup.
+    pub fn finish(&self) {
+        self.arrays();
+    }
+
+    fn arrays(&self) -> &Intervals {
+        self.intervals.get_or_init(|| {
+            let mut ip4 = vec![];
+            let mut ip6 = vec![];
+            for (idx, country) in self.countries.iter().enumerate() {
+                let idx = idx as u32;
+                ip4.extend(
+                    country
+                        .ip4
+                        .iter()
+                        .map(|&(first, last)| (first as u128, last as u128, idx)),
+                );
+                ip6.extend(country.ip6.iter().map(|&(first, last)| (first, last, idx)));
             }
-            l = x + 1;
-        }
-        let nip = V6::normalize6(ip, self.prefix6[l - 1]);
-        return l > 0 && nip == self.ip6[l - 1];
-    }
-
-    pub fn put(&mut self, geoip: &mut GeoIP) {
-        geoip.cidr.sort_by(|a, b| a.prefix.cmp(&b.prefix));
-        self.country_code = geoip.country_code.to_uppercase().clone();
-        for pair in geoip.cidr.iter() {
-            let len = pair.ip.len();
-            match len {
-                16 => {
-                    let prefix = pair.prefix as u8;
-                    let v6 = V6::normalize_new(&pair.ip, prefix);
-                    self.ip6.push(v6);
-                    self.prefix6.push(prefix);
-                }
-                4 => {
-                    let inner = pair.ip.clone().try_into().unwrap();
-                    let prefix = pair.prefix as u8;
-                    let ip = u32::from_be_bytes(inner) >> (32 - pair.prefix) << (32 - pair.prefix);
-                    self.ip4.push(ip);
-                    self.prefix4.push(prefix);
-                }
-                _ => {
-                    eprintln!("invalid ip length detected");
-                }
+            Intervals {
+                ip4: paint(ip4)
+                    .into_iter()
+                    .map(|(first, last, c)| (first as u32, last as u32, c))
+                    .collect(),
+                ip6: paint(ip6),
             }
+        })
+    }
+
+    // the country whose cidrs hold ip, reverse_match is not applied.
+    pub fn country_of(&self, ip: IpAddr) -> Option<&str> {
+        let intervals = self.arrays();
+        let idx = match ip {
+            IpAddr::V4(ip) => locate(&intervals.ip4, u32::from(ip)),
+            IpAddr::V6(ip) => locate(&intervals.ip6, u128::from(ip)),
+        }?;
+        Some(&self.countries[idx as usize].country_code)
+    }
+
+    // whether ip matches the code, honouring its reverse_match flag. The
+    // country's own cidrs are searched, overlapping countries do not hide
+    // them.
+    pub fn contains(&self, country_code: &str, ip: IpAddr) -> bool {
+        match self.index.get(&country_code.to_uppercase()) {
+            Some(&idx) => self.countries[idx as usize].contains(ip),
+            None => false,
         }
     }
 
-    pub fn new() -> GeoIPMatcher {
-        GeoIPMatcher {
-            country_code: "".to_string(),
-            ip4: vec![],
-            prefix4: vec![],
-            ip6: vec![],
-            prefix6: vec![],
+    pub fn country_codes(&self) -> impl Iterator<Item = &str> {
+        self.countries.iter().map(|c| c.country_code.as_str())
+    }
+
+    // number of intervals in the shared IPv4 and IPv6 arrays.
+    pub fn intervals(&self) -> (usize, usize) {
+        let intervals = self.arrays();
+        (intervals.ip4.len(), intervals.ip6.len())
+    }
+}
+
+#[test]
+fn test_geoip_matcher_reverse_match() {
+    let mut geoip = crate::geoip_db::geoip_entry("cn", &["1.0.1.0/24", "2400:da00::/32"]);
+    let mut matcher = GeoIPMatcher::new();
+    matcher.put(&geoip).unwrap();
+    let complement = matcher.complement();
+    for (ip, in_cn) in [
+        (vec![1, 0, 1, 7], true),
+        (vec![8, 8, 8, 8], false),
+        (
+            vec![0x24, 0, 0xda, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            true,
+        ),
+        (
+            vec![0x20, 1, 0x48, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            false,
+        ),
+    ]
+    .iter()
+    {
+        assert_eq!(matcher.match_ip(ip), *in_cn);
+        assert_eq!(complement.match_ip(ip), !*in_cn);
+    }
+
+    geoip.reverse_match = true;
+    let mut matcher = GeoIPMatcher::new();
+    matcher.put(&geoip).unwrap();
+    assert!(matcher.match_ip(&vec![8, 8, 8, 8]));
+    assert!(!matcher.match_ip(&vec![1, 0, 1, 7]));
+    assert!(!matcher.match_ip(&vec![1, 0, 1]));
+
+    // an invalid cidr fails the entry, its valid ones are not added either.
+    let mut bad = crate::geoip_db::geoip_entry("xx", &["8.8.8.0/24"]);
+    let mut pair = crate::geoip::CIDR::new();
+    pair.ip = vec![1, 2, 3];
+    pair.prefix = 8;
+    bad.cidr.push(pair);
+    match matcher.put(&bad) {
+        Err(GeoIpError::InvalidCidr { country_code, .. }) => assert_eq!(country_code, "XX"),
+        _ => panic!("expected an invalid cidr"),
+    }
+    assert!(matcher.match_ip(&vec![8, 8, 8, 8]));
+
+    let matcher = GeoIPMatcher::build_from(
+        ["1.0.1.0/24", "2400:da00::/32"]
+            .iter()
+            .map(|s| s.parse().unwrap()),
+    );
+    assert!(matcher.contains("1.0.1.7".parse().unwrap()));
+    assert!(matcher.contains("2400:da00::1".parse().unwrap()));
+    assert!(!matcher.contains("8.8.8.8".parse().unwrap()));
+}
+
+#[test]
+fn test_geoip_matcher_against_lpc_trie() {
+    use crate::dual_stack::DualStackTrie;
+
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    for round in 0..20 {
+        let cidrs = random.cidrs(200);
+        let matcher = GeoIPMatcher::build_from(cidrs.iter().copied());
+        let trie = DualStackTrie::build_from(cidrs.iter().copied());
+        for ip in random.probes(&cidrs) {
+            assert_eq!(
+                matcher.contains(ip),
+                trie.contains(ip),
+                "round {} ip {}",
+                round,
+                ip
+            );
         }
     }
+}
 
-    pub fn match_ip(&self, v: &std::vec::Vec<u8>) -> bool {
-        return match v.len() {
-            4 => {
-                let inner = v.clone().try_into().unwrap();
-                self.match4(u32::from_be_bytes(inner))
-            }
-            16 => {
-                let v6 = V6::new(v);
-                self.match6(&v6)
-            }
-            _ => unimplemented!(),
-        };
+#[test]
+fn test_geoip_matcher_set() {
+    use crate::geoip_db::{geoip_entry, GeoIpDatabase};
+
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let mut set = GeoIPMatcherSet::new();
+    set.put(&geoip_entry("cn", &["1.0.0.0/16", "2400:da00::/32"]))
+        .unwrap();
+    set.put(&geoip_entry("private", &["10.0.0.0/8", "1.0.1.0/24"]))
+        .unwrap();
+    set.put(&geoip_entry("us", &["1.0.0.0/8", "2001:4860::/32"]))
+        .unwrap();
+    set.finish();
+    assert_eq!(
+        set.country_codes().collect::<Vec<_>>(),
+        vec!["CN", "PRIVATE", "US"]
+    );
+    // US keeps the part of 1.0.0.0/8 after CN's 1.0.0.0/16.
+    assert_eq!(set.intervals(), (3, 2));
+    assert_eq!(set.country_of(ip("1.0.1.1")), Some("CN"));
+    assert_eq!(set.country_of(ip("1.1.0.0")), Some("US"));
+    assert_eq!(set.country_of(ip("0.255.255.255")), None);
+    assert_eq!(set.country_of(ip("10.9.9.9")), Some("PRIVATE"));
+    assert_eq!(set.country_of(ip("2400:da00::1")), Some("CN"));
+    assert_eq!(set.country_of(ip("2001:4860::8888")), Some("US"));
+    assert_eq!(set.country_of(ip("::1")), None);
+    assert!(set.contains("private", ip("1.0.1.1")));
+    assert!(!set.contains("private", ip("1.0.2.1")));
+    assert!(!set.contains("jp", ip("1.0.2.1")));
+
+    // cn keeps its place when more of it is put, lookups see it at once.
+    set.put(&geoip_entry("CN", &["10.1.0.0/16"])).unwrap();
+    assert!(set.contains("cn", ip("10.1.2.3")));
+    assert_eq!(set.country_of(ip("10.1.2.3")), Some("CN"));
+    assert_eq!(set.country_of(ip("10.2.0.0")), Some("PRIVATE"));
+    assert_eq!(set.country_of(ip("1.0.1.1")), Some("CN"));
+    assert_eq!(set.intervals(), (5, 2));
+
+    // country 0 cuts into 1, which resumes after it up to the last address.
+    assert_eq!(
+        paint(vec![(12, u128::MAX, 1), (0, 10, 1), (5, 20, 0), (6, 8, 2)]),
+        vec![(0, 4, 1), (5, 20, 0), (21, u128::MAX, 1)]
+    );
+
+    let mut random = XorShift(0x9e37_79b9_7f4a_7c15);
+    for round in 0..5 {
+        let mut list = GeoIPList::new();
+        for code in ["AA", "BB", "CC", "DD"].iter() {
+            let cidrs: Vec<String> = random.cidrs(50).iter().map(|c| c.to_string()).collect();
+            let cidrs: Vec<&str> = cidrs.iter().map(|s| s.as_str()).collect();
+            list.entry.push(geoip_entry(code, &cidrs));
+        }
+        let set = GeoIPMatcherSet::from_list(&list).unwrap();
+        let db = GeoIpDatabase::from_list(&list).unwrap();
+        let cidrs = random.cidrs(100);
+        for ip in random.probes(&cidrs) {
+            assert_eq!(
+                set.country_of(ip),
+                db.country_of(ip),
+                "round {} ip {}",
+                round,
+                ip
+            );
+        }
+    }
+}
+
+#[test]
+fn test_s_tree_matcher() {
+    // 40 leaves of 8 below a root and a level of 3 nodes.
+    let sorted: Vec<(u32, u32)> = (0..320).map(|i| (i * 10, i * 10 + 5)).collect();
+    let tree: STree<u32, 16, 8> = STree::new(&sorted);
+    assert_eq!(
+        (tree.levels.clone(), tree.inner.len(), tree.leaves.len()),
+        (vec![0, 1], 4, 40)
+    );
+    assert_eq!(tree.inner[0].0[..3], [1360, 2720, u32::MAX]);
+    assert_eq!(tree.inner[1].0[..2], [80, 160]);
+    assert!(tree.contains(0));
+    assert!(tree.contains(1365));
+    assert!(!tree.contains(1366));
+    assert!(tree.contains(3195));
+    assert!(!tree.contains(3196));
+    assert!(!tree.contains(u32::MAX));
+    let tree: STree<u32, 16, 8> = STree::new(&[(5, 6), (u32::MAX - 1, u32::MAX)]);
+    assert!(!tree.contains(4));
+    assert!(tree.contains(u32::MAX));
+    let tree: STree<u128, 4, 2> = STree::new(&[(0, u128::MAX)]);
+    assert!(tree.contains(0) && tree.contains(u128::MAX));
+
+    let empty = STreeMatcher::build_from(vec![]);
+    assert!(!empty.contains("1.1.1.1".parse().unwrap()));
+    assert!(!empty.contains("255.255.255.255".parse().unwrap()));
+    assert!(!empty.contains("::1".parse().unwrap()));
+
+    // one leaf up to several levels, against the binary search.
+    let mut random = XorShift(0xd1b5_4a32_d192_ed03);
+    for n in (1..40).chain([150, 300, 700, 3000].iter().copied()) {
+        let cidrs = random.cidrs(n);
+        let mut matcher = GeoIPMatcher::build_from(cidrs.iter().copied());
+        let tree = STreeMatcher::from(&matcher);
+        for ip in random.probes(&cidrs) {
+            assert_eq!(tree.contains(ip), matcher.contains(ip), "n {} ip {}", n, ip);
+        }
+        matcher.set_reverse_match(true);
+        let tree = STreeMatcher::from(&matcher);
+        assert!(!tree.contains(cidrs[0].addr()));
     }
 }
@@ -0,0 +1,245 @@
+use crate::cidr::Cidr;
+use crate::text_list::ParseError;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Split one csv record, fields may be quoted and use "" as an escaped quote.
+pub(crate) fn split_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+// Column indices of a csv header, looked up by name.
+struct Header(Vec<String>);
+
+impl Header {
+    fn column(&self, name: &str, line: usize) -> Result<usize, ParseError> {
+        match self.0.iter().position(|c| c == name) {
+            Some(idx) => Ok(idx),
+            None => Err(ParseError::at(line, format!("missing column {}", name))),
+        }
+    }
+}
+
+// line number and fields of a csv record.
+type Record = (usize, Vec<String>);
+
+// Read csv records, the first non empty line is returned as the header.
+fn read_records<R: BufRead>(reader: R) -> Result<(Header, Vec<Record>), ParseError> {
+    let mut header = None;
+    let mut records = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv(&line).map_err(|e| ParseError::at(idx + 1, e))?;
+        match header {
+            None => header = Some(Header(fields)),
+            Some(_) => records.push((idx + 1, fields)),
+        }
+    }
+    Ok((header.unwrap_or(Header(vec![])), records))
+}
+
+fn field(fields: &[String], idx: usize, line: usize) -> Result<&str, ParseError> {
+    match fields.get(idx) {
+        Some(f) => Ok(f.trim()),
+        None => Err(ParseError::at(line, format!("missing field {}", idx + 1))),
+    }
+}
+
+// GeoLite2-Country-Locations-xx.csv, maps geoname_id to country_iso_code.
+// Locations without a country, such as continents, are left out.
+pub fn read_geolite2_locations<R: BufRead>(
+    reader: R,
+) -> Result<HashMap<String, String>, ParseError> {
+    let (header, records) = read_records(reader)?;
+    let id = header.column("geoname_id", 1)?;
+    let code = header.column("country_iso_code", 1)?;
+    let mut locations = HashMap::new();
+    for (line, fields) in records.iter() {
+        let iso_code = field(fields, code, *line)?;
+        if !iso_code.is_empty() {
+            locations.insert(
+                field(fields, id, *line)?.to_string(),
+                iso_code.to_uppercase(),
+            );
+        }
+    }
+    Ok(locations)
+}
+
+// GeoLite2-Country-Blocks-IPv4.csv or -IPv6.csv. The country comes from
+// geoname_id, or registered_country_geoname_id when the network has no
+// located country. Networks without either are left out.
+pub fn read_geolite2_blocks<R: BufRead>(
+    reader: R,
+    locations: &HashMap<String, String>,
+) -> Result<Vec<(Cidr, String)>, ParseError> {
+    let (header, records) = read_records(reader)?;
+    let network = header.column("network", 1)?;
+    let ids = [
+        header.column("geoname_id", 1)?,
+        header.column("registered_country_geoname_id", 1)?,
+    ];
+    let mut networks = vec![];
+    for (line, fields) in records.iter() {
+        let cidr: Cidr = field(fields, network, *line)?
+            .parse()
+            .map_err(|e| ParseError::at(*line, e))?;
+        for idx in ids.iter() {
+            let id = field(fields, *idx, *line)?;
+            if id.is_empty() {
+                continue;
+            }
+            match locations.get(id) {
+                Some(code) => {
+                    networks.push((cidr, code.clone()));
+                    break;
+                }
+                None => {
+                    return Err(ParseError::at(*line, format!("unknown geoname_id {}", id)));
+                }
+            }
+        }
+    }
+    Ok(networks)
+}
+
+const IPV4_MAPPED: u128 = 0xffff << 32;
+
+fn ip2location_addr(value: &str, ipv4: bool, line: usize) -> Result<IpAddr, ParseError> {
+    let n: u128 = value
+        .parse()
+        .map_err(|_| ParseError::at(line, format!("invalid integer address {:?}", value)))?;
+    Ok(match (ipv4, n) {
+        (true, n) if n >= IPV4_MAPPED => IpAddr::V4(Ipv4Addr::from((n - IPV4_MAPPED) as u32)),
+        (true, n) => IpAddr::V4(Ipv4Addr::from(n as u32)),
+        (false, n) => IpAddr::V6(Ipv6Addr::from(n)),
+    })
+}
+
+// IP2Location style csv: "ip_from","ip_to","country_code",... with integer
+// addresses and no header. Ranges are split into cidrs. A range up to
+// u32::MAX, or inside ::ffff:0:0/96 as the IPv6 editions store IPv4, is
+// taken as IPv4. Rows with the "-" placeholder code are left out.
+pub fn read_ip2location<R: BufRead>(reader: R) -> Result<Vec<(Cidr, String)>, ParseError> {
+    let mut networks = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv(&line).map_err(|e| ParseError::at(line_no, e))?;
+        let (from, to) = (field(&fields, 0, line_no)?, field(&fields, 1, line_no)?);
+        let code = field(&fields, 2, line_no)?;
+        if code == "-" || code.is_empty() {
+            continue;
+        }
+        let last: u128 = to
+            .parse()
+            .map_err(|_| ParseError::at(line_no, format!("invalid integer address {:?}", to)))?;
+        let first: u128 = from.parse().unwrap_or(u128::MAX);
+        let ipv4 = last <= u32::MAX as u128
+            || (first >= IPV4_MAPPED && last <= IPV4_MAPPED | u32::MAX as u128);
+        let start = ip2location_addr(from, ipv4, line_no)?;
+        let end = ip2location_addr(to, ipv4, line_no)?;
+        let cidrs = Cidr::range(start, end).map_err(|e| ParseError::at(line_no, e))?;
+        networks.extend(cidrs.into_iter().map(|c| (c, code.to_uppercase())));
+    }
+    Ok(networks)
+}
+
+#[test]
+fn test_csv_import() {
+    use crate::dual_stack::DualStackTrie;
+
+    assert_eq!(
+        split_csv("\"1\",\"Korea, Republic of\",\"a \"\"b\"\"\",,x\r").unwrap(),
+        vec!["1", "Korea, Republic of", "a \"b\"", "", "x"]
+    );
+    assert!(split_csv("\"open").is_err());
+
+    let locations = "geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,is_in_european_union\n\
+                     1814991,en,AS,Asia,CN,China,0\n\
+                     6252001,en,NA,\"North America\",US,\"United States\",0\n\
+                     6255147,en,AS,Asia,,,0\n";
+    let locations = read_geolite2_locations(locations.as_bytes()).unwrap();
+    assert_eq!(locations.len(), 2);
+
+    let blocks = "network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider\n\
+                  1.0.1.0/24,1814991,1814991,,0,0\n\
+                  1.1.1.0/24,,6252001,,0,0\n\
+                  1.2.3.0/24,,,,1,0\n";
+    let v4 = read_geolite2_blocks(blocks.as_bytes(), &locations).unwrap();
+    assert_eq!(
+        v4,
+        vec![
+            ("1.0.1.0/24".parse().unwrap(), "CN".to_string()),
+            ("1.1.1.0/24".parse().unwrap(), "US".to_string())
+        ]
+    );
+    let v6 = "network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider\n\
+              2400:da00::/32,1814991,1814991,,0,0\n";
+    let v6 = read_geolite2_blocks(v6.as_bytes(), &locations).unwrap();
+    let trie: DualStackTrie = v4.into_iter().chain(v6).collect();
+    assert_eq!(trie.get_with_value("1.1.1.1".parse().unwrap()), "US");
+    assert_eq!(trie.get_with_value("2400:da00::8".parse().unwrap()), "CN");
+
+    match read_geolite2_blocks("network,geoname_id\n1.0.0.0/24,1\n".as_bytes(), &locations) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "line 1: missing column registered_country_geoname_id"
+        ),
+        _ => panic!("expected a missing column"),
+    }
+    let bad = "network,geoname_id,registered_country_geoname_id\n1.0.0.0/24,42,\n";
+    match read_geolite2_blocks(bad.as_bytes(), &locations) {
+        Err(e) => assert_eq!(e.to_string(), "line 2: unknown geoname_id 42"),
+        _ => panic!("expected an unknown geoname_id"),
+    }
+
+    let ip2location = "\"0\",\"16777215\",\"-\",\"-\"\n\
+                       \"16777472\",\"16778239\",\"CN\",\"China\"\n\
+                       \"16843008\",\"16843263\",\"us\",\"United States of America\"\n\
+                       \"281470698520576\",\"281470698520831\",\"AU\",\"Australia\"\n\
+                       \"58569105395146355079250494851844669440\",\"58569105395146355079250494851844669443\",\"JP\",\"Japan\"\n";
+    let networks = read_ip2location(ip2location.as_bytes()).unwrap();
+    let expected: Vec<(Cidr, String)> = vec![
+        ("1.0.1.0/24", "CN"),
+        ("1.0.2.0/23", "CN"),
+        ("1.1.1.0/24", "US"),
+        ("1.0.0.0/24", "AU"),
+        ("2c0f:ffd8::/126", "JP"),
+    ]
+    .into_iter()
+    .map(|(c, code)| (c.parse().unwrap(), code.to_string()))
+    .collect();
+    assert_eq!(networks, expected);
+    match read_ip2location("\"1\",\"x\",\"CN\",\"China\"\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: invalid integer address \"x\""),
+        _ => panic!("expected an invalid address"),
+    }
+}
@@ -0,0 +1,191 @@
+use crate::cidr::{aggregate, Cidr};
+use crate::dual_stack::DualStackTrie;
+#[cfg(feature = "pb")]
+use crate::geoip_db::GeoIpDatabase;
+use crate::text_list::ParseError;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+// One address record of a RIR delegated stats file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delegation {
+    pub registry: String,
+    pub country_code: String,
+    pub cidrs: Vec<Cidr>,
+    pub status: String,
+}
+
+fn parse_record(fields: &[&str], line: usize) -> Result<Option<Delegation>, ParseError> {
+    if fields.len() < 7 {
+        return Err(ParseError::at(line, "expected at least 7 fields"));
+    }
+    let (registry, cc, kind, start, value, status) = (
+        fields[0], fields[1], fields[2], fields[3], fields[4], fields[6],
+    );
+    // asn records, and the available or reserved space without a country.
+    if kind != "ipv4" && kind != "ipv6" || cc.is_empty() || cc == "*" || cc == "ZZ" {
+        return Ok(None);
+    }
+    if status != "allocated" && status != "assigned" {
+        return Ok(None);
+    }
+    let addr: IpAddr = start
+        .parse()
+        .map_err(|_| ParseError::at(line, format!("invalid address {:?}", start)))?;
+    let count: u128 = value
+        .parse()
+        .map_err(|_| ParseError::at(line, format!("invalid count {:?}", value)))?;
+    let cidrs = match addr {
+        // ipv4 gives the number of addresses, which need not be a power of two.
+        IpAddr::V4(v4) if kind == "ipv4" && count > 0 => {
+            let last = (u32::from(v4) as u128).checked_add(count - 1);
+            match last.filter(|l| *l <= u32::MAX as u128) {
+                Some(l) => Cidr::range(addr, IpAddr::V4(Ipv4Addr::from(l as u32))),
+                None => return Err(ParseError::at(line, format!("count {} overflows", count))),
+            }
+        }
+        // ipv6 gives the prefix length.
+        IpAddr::V6(_) if kind == "ipv6" && count <= 128 => {
+            Cidr::new(addr, count as u8).map(|c| vec![c])
+        }
+        _ => return Err(ParseError::at(line, format!("invalid {} record", kind))),
+    }
+    .map_err(|e| ParseError::at(line, e))?;
+    Ok(Some(Delegation {
+        registry: registry.to_string(),
+        country_code: cc.to_uppercase(),
+        cidrs,
+        status: status.to_string(),
+    }))
+}
+
+// Read a delegated-<rir>-latest or delegated-<rir>-extended-latest file. The
+// version line, the summary lines, asn records and the space that is not
+// allocated or assigned to a country are skipped.
+pub fn read_delegated<R: BufRead>(reader: R) -> Result<Vec<Delegation>, ParseError> {
+    let mut records = vec![];
+    let mut version_seen = false;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = text.split('|').collect();
+        // the version line, e.g. "2.3|apnic|20210101|...".
+        if !version_seen {
+            version_seen = true;
+            if fields[0].starts_with(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+        }
+        // summary lines, e.g. "apnic|*|ipv4|*|44000|summary".
+        if fields.last() == Some(&"summary") {
+            continue;
+        }
+        if let Some(record) = parse_record(&fields, idx + 1)? {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+pub fn read_delegated_path<P: AsRef<Path>>(path: P) -> Result<Vec<Delegation>, ParseError> {
+    read_delegated(BufReader::new(File::open(path)?))
+}
+
+// The aggregated cidrs of one country, IPv4 first, as chnroutes lists them.
+pub fn country_cidrs(records: &[Delegation], country_code: &str) -> Vec<Cidr> {
+    aggregate(
+        records
+            .iter()
+            .filter(|r| r.country_code.eq_ignore_ascii_case(country_code))
+            .flat_map(|r| r.cidrs.iter().copied()),
+    )
+}
+
+// one trie per country, each cidr holding its country code.
+pub fn country_tries(records: &[Delegation]) -> BTreeMap<String, DualStackTrie> {
+    let mut tries: BTreeMap<String, DualStackTrie> = BTreeMap::new();
+    for record in records {
+        let trie = tries.entry(record.country_code.clone()).or_default();
+        for cidr in record.cidrs.iter() {
+            trie.put(*cidr, record.country_code.clone());
+        }
+    }
+    tries
+}
+
+#[cfg(feature = "pb")]
+pub fn to_geoip_database(records: &[Delegation]) -> GeoIpDatabase {
+    let mut db = GeoIpDatabase::new();
+    for record in records {
+        for cidr in record.cidrs.iter() {
+            db.put(&record.country_code, *cidr);
+        }
+    }
+    db
+}
+
+#[test]
+fn test_delegated() {
+    let text = "# apnic delegated stats\n\
+                2.3|apnic|20210101|5|19830613|20201231|+1000\n\
+                apnic|*|asn|*|3|summary\n\
+                apnic|*|ipv4|*|4|summary\n\
+                apnic|*|ipv6|*|1|summary\n\
+                apnic|JP|asn|173|1|20020801|allocated|A91A7381\n\
+                apnic|CN|ipv4|1.0.1.0|256|20110414|allocated|A92E1062\n\
+                apnic|CN|ipv4|1.0.2.0|512|20110414|allocated|A92E1062\n\
+                apnic|CN|ipv4|1.0.8.0|768|20110412|allocated|A92E1062\n\
+                apnic|AU|ipv4|1.0.0.0|256|20110811|assigned|A91872ED\n\
+                apnic||ipv4|1.0.4.0|1024||available\n\
+                apnic|CN|ipv6|2400:da00::|32|20100810|allocated|A92E1062\n";
+    let records = read_delegated(text.as_bytes()).unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[2].cidrs.len(), 2);
+    assert_eq!(records[3].status, "assigned");
+
+    let parse = |v: &[&str]| v.iter().map(|s| s.parse().unwrap()).collect::<Vec<Cidr>>();
+    assert_eq!(
+        country_cidrs(&records, "cn"),
+        parse(&[
+            "1.0.1.0/24",
+            "1.0.2.0/23",
+            "1.0.8.0/23",
+            "1.0.10.0/24",
+            "2400:da00::/32"
+        ])
+    );
+
+    let tries = country_tries(&records);
+    assert_eq!(tries.keys().collect::<Vec<_>>(), vec!["AU", "CN"]);
+    let cn = &tries["CN"];
+    assert!(cn.get("1.0.10.255".parse().unwrap()));
+    assert!(!cn.get("1.0.4.1".parse().unwrap()));
+    assert!(!cn.get("1.0.0.1".parse().unwrap()));
+    assert_eq!(cn.get_with_value("2400:da00::1".parse().unwrap()), "CN");
+    assert!(tries["AU"].get("1.0.0.1".parse().unwrap()));
+
+    #[cfg(feature = "pb")]
+    {
+        let db = to_geoip_database(&records);
+        assert_eq!(db.country_of("1.0.9.1".parse().unwrap()), Some("CN"));
+        assert_eq!(db.country_of("1.0.0.1".parse().unwrap()), Some("AU"));
+        assert_eq!(db.country_of("1.0.4.1".parse().unwrap()), None);
+    }
+
+    match read_delegated("apnic|CN|ipv4|1.0.1.0|x|20110414|allocated\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: invalid count \"x\""),
+        _ => panic!("expected an invalid count"),
+    }
+    match read_delegated("apnic|CN|ipv4|255.255.255.0|512|20110414|allocated\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: count 512 overflows"),
+        _ => panic!("expected an overflow"),
+    }
+    assert!(read_delegated("apnic|CN|ipv6|2400::|129|20110414|allocated\n".as_bytes()).is_err());
+    assert!(read_delegated_path("no/such/delegated").is_err());
+}
@@ -0,0 +1,161 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::{LPCTrie, Values};
+use crate::matcher::IpMatcher;
+use std::hash::Hash;
+use std::net::IpAddr;
+
+// entries of tbl24 with this bit set point at a tbl8 group.
+const TBL8_FLAG: u32 = 1 << 31;
+
+// A DIR-24-8 table: tbl24 is indexed by the top 24 bits of an address, and
+// prefixes longer than /24 get a group of 256 entries in tbl8 indexed by the
+// last byte. A lookup reads at most two entries. Entries hold 0 for no route,
+// value index + 1 for a route, or TBL8_FLAG | group for a /24 split further.
+// The table is frozen once compiled, tbl24 alone takes 64MB.
+pub struct Dir24Table<V = String> {
+    tbl24: Vec<u32>,
+    tbl8: Vec<u32>,
+    values: Vec<V>,
+}
+
+impl<V: Clone + Eq + Hash> Dir24Table<V> {
+    pub fn from_trie(trie: &LPCTrie<u32, V>) -> Dir24Table<V> {
+        let mut table = Dir24Table {
+            tbl24: vec![0; 1 << 24],
+            tbl8: vec![],
+            values: vec![],
+        };
+        let mut values = Values::new();
+        let mut prefixes: Vec<(u32, u8, &V)> = trie.iter().collect();
+        // shorter prefixes first, longer ones paint over them.
+        prefixes.sort_by_key(|p| p.1);
+        for (key, prefix, value) in prefixes {
+            let entry = values.intern_ref(value) as u32 + 1;
+            table.fill(key, prefix, entry);
+        }
+        table.values = values.into_vec();
+        table
+    }
+
+    fn fill(&mut self, key: u32, prefix: u8, entry: u32) {
+        let key = if prefix == 0 {
+            0
+        } else {
+            key & (u32::MAX << (32 - prefix))
+        };
+        if prefix <= 24 {
+            let first = (key >> 8) as usize;
+            let len = 1 << (24 - prefix);
+            self.tbl24[first..first + len]
+                .iter_mut()
+                .for_each(|e| *e = entry);
+            return;
+        }
+        let slot = &mut self.tbl24[(key >> 8) as usize];
+        if *slot & TBL8_FLAG == 0 {
+            // the group starts out with what the /24 held.
+            let group = (self.tbl8.len() >> 8) as u32;
+            self.tbl8.resize(self.tbl8.len() + 256, *slot);
+            *slot = TBL8_FLAG | group;
+        }
+        let first = (((*slot & !TBL8_FLAG) << 8) | (key & 0xff)) as usize;
+        let len = 1 << (32 - prefix);
+        self.tbl8[first..first + len]
+            .iter_mut()
+            .for_each(|e| *e = entry);
+    }
+
+    // index into values of the longest prefix covering key.
+    #[inline]
+    pub fn get_index(&self, key: u32) -> Option<u32> {
+        let mut entry = self.tbl24[(key >> 8) as usize];
+        if entry & TBL8_FLAG != 0 {
+            entry = self.tbl8[(((entry & !TBL8_FLAG) << 8) | (key & 0xff)) as usize];
+        }
+        entry.checked_sub(1)
+    }
+
+    pub fn get_value(&self, key: u32) -> Option<&V> {
+        self.get_index(key).map(|i| &self.values[i as usize])
+    }
+
+    pub fn get(&self, key: u32) -> bool {
+        self.get_index(key).is_some()
+    }
+
+    // the distinct values, in the order get_index numbers them.
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    // number of /24s split into a tbl8 group.
+    pub fn tbl8_groups(&self) -> usize {
+        self.tbl8.len() >> 8
+    }
+}
+
+// IPv6 addresses never match, the table holds IPv4 only.
+impl<V: Clone + Eq + Hash + Default> IpMatcher for Dir24Table<V> {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.get(ip.into()),
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        Dir24Table::from_trie(&LPCTrie::build_from(cidrs))
+    }
+}
+
+#[test]
+fn test_dir24_table() {
+    let ip = |s: &str| u32::from(s.parse::<std::net::Ipv4Addr>().unwrap());
+    let trie = crate::test_util::v4_trie(&[
+        ("0.0.0.0/0", "default"),
+        ("10.0.0.0/8", "A"),
+        ("10.1.0.0/16", "B"),
+        ("10.1.2.0/24", "A"),
+        ("10.1.2.128/25", "C"),
+        ("10.1.2.200/32", "D"),
+        ("10.1.3.7/32", "D"),
+        ("192.168.0.0/23", "B"),
+    ]);
+    let table = Dir24Table::from_trie(&trie);
+    assert_eq!(table.values().len(), 5);
+    assert_eq!(table.tbl8_groups(), 2);
+
+    for (addr, expected) in [
+        ("8.8.8.8", "default"),
+        ("10.0.0.1", "A"),
+        ("10.1.0.1", "B"),
+        ("10.1.2.1", "A"),
+        ("10.1.2.128", "C"),
+        ("10.1.2.199", "C"),
+        ("10.1.2.200", "D"),
+        ("10.1.2.201", "C"),
+        ("10.1.3.6", "B"),
+        ("10.1.3.7", "D"),
+        ("192.168.1.255", "B"),
+        ("192.168.2.0", "default"),
+    ]
+    .iter()
+    {
+        assert_eq!(table.get_value(ip(addr)).unwrap(), expected, "{}", addr);
+    }
+    let d = table.get_index(ip("10.1.3.7")).unwrap();
+    assert_eq!(table.values()[d as usize], "D");
+
+    // the same answers as the trie it was compiled from.
+    let mut key: u32 = 0x0a01_0000;
+    while key < 0x0a01_0400 {
+        assert_eq!(table.get_value(key), trie.get_value(key));
+        key += 3;
+    }
+
+    let table: Dir24Table = Dir24Table::build_from(vec!["1.0.0.0/24".parse().unwrap()]);
+    assert!(table.contains("1.0.0.255".parse().unwrap()));
+    assert!(!table.contains("1.0.1.0".parse().unwrap()));
+    assert!(!table.contains("::1".parse().unwrap()));
+    assert_eq!(table.tbl8_groups(), 0);
+}
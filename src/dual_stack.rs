@@ -0,0 +1,99 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::LPCTrie;
+use crate::matcher::IpMatcher;
+use deepsize::DeepSizeOf;
+use std::net::IpAddr;
+
+// A pair of tries so one structure can answer for both address families.
+#[derive(DeepSizeOf)]
+pub struct DualStackTrie {
+    v4: LPCTrie<u32>,
+    v6: LPCTrie<u128>,
+}
+
+impl Default for DualStackTrie {
+    fn default() -> Self {
+        DualStackTrie::new()
+    }
+}
+
+impl std::iter::FromIterator<(Cidr, String)> for DualStackTrie {
+    fn from_iter<I: IntoIterator<Item = (Cidr, String)>>(iter: I) -> Self {
+        let mut trie = DualStackTrie::new();
+        for (cidr, value) in iter {
+            trie.put(cidr, value);
+        }
+        trie
+    }
+}
+
+impl IpMatcher for DualStackTrie {
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.get(ip)
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        cidrs.into_iter().map(|c| (c, String::new())).collect()
+    }
+}
+
+impl DualStackTrie {
+    pub fn new() -> DualStackTrie {
+        DualStackTrie {
+            v4: LPCTrie::new(),
+            v6: LPCTrie::new(),
+        }
+    }
+
+    pub fn put(&mut self, cidr: Cidr, value: String) {
+        match cidr {
+            Cidr::V4(addr, prefix) => self.v4.put(addr, prefix, value),
+            Cidr::V6(addr, prefix) => self.v6.put(addr, prefix, value),
+        }
+    }
+
+    pub fn get(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.v4.get(ip.into()),
+            IpAddr::V6(ip) => self.v6.get(ip.into()),
+        }
+    }
+
+    pub fn get_with_value(&self, ip: IpAddr) -> &str {
+        match ip {
+            IpAddr::V4(ip) => self.v4.get_with_value(ip.into()),
+            IpAddr::V6(ip) => self.v6.get_with_value(ip.into()),
+        }
+    }
+
+    pub fn remove(&mut self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.v4.remove(ip.into()),
+            IpAddr::V6(ip) => self.v6.remove(ip.into()),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.v4.clear();
+        self.v6.clear();
+    }
+
+    pub fn empty(&self) -> bool {
+        self.v4.empty() && self.v6.empty()
+    }
+
+    // every stored cidr and its value, IPv4 first.
+    pub fn iter(&self) -> impl Iterator<Item = (Cidr, &str)> {
+        let v4 = self.v4.iter().map(|(k, p, v)| (Cidr::V4(k, p), v.as_str()));
+        let v6 = self.v6.iter().map(|(k, p, v)| (Cidr::V6(k, p), v.as_str()));
+        v4.chain(v6)
+    }
+
+    pub fn v4(&self) -> &LPCTrie<u32> {
+        &self.v4
+    }
+
+    pub fn v6(&self) -> &LPCTrie<u128> {
+        &self.v6
+    }
+}
@@ -0,0 +1,345 @@
+// The C interface. include/cidr_matcher.h is generated from this file by
+// cbindgen with cbindgen.toml, and tests/ffi.rs fails when the checked-in
+// copy differs. The /// comments below are the header's documentation,
+// the safety contract of every function among it.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::cidr::Cidr;
+use crate::geoip_db::{GeoIpDatabase, GeoIpError};
+use crate::lpc_trie::{LPCTrie, Values};
+use std::ffi::{CStr, CString};
+use std::net::Ipv6Addr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::{ptr, slice};
+
+/// a NULL handle or string, or a string that is not UTF-8
+pub const CIDR_MATCHER_ERR_ARGUMENT: c_int = -1;
+/// a cidr that does not parse, in the argument or in a geoip.dat
+pub const CIDR_MATCHER_ERR_CIDR: c_int = -2;
+/// the geoip.dat could not be read
+pub const CIDR_MATCHER_ERR_IO: c_int = -3;
+/// the geoip.dat is not a GeoIPList, or lists a code with and without
+/// reverse_match
+pub const CIDR_MATCHER_ERR_FORMAT: c_int = -4;
+/// a bug in the library, the call was abandoned
+pub const CIDR_MATCHER_ERR_PANIC: c_int = -5;
+
+// What a C handle points at: a trie per family holding ids of the values,
+// which are kept nul terminated so lookups can hand them out as they are.
+pub struct CidrMatcher {
+    v4: LPCTrie<u32, u32>,
+    v6: LPCTrie<u128, u32>,
+    values: Values<CString>,
+}
+
+impl CidrMatcher {
+    fn new() -> CidrMatcher {
+        CidrMatcher {
+            v4: LPCTrie::new(),
+            v6: LPCTrie::new(),
+            values: Values::new(),
+        }
+    }
+
+    fn value_id(&mut self, value: CString) -> u32 {
+        self.values.intern(value) as u32
+    }
+
+    // a prefix that is already there keeps its value unless replace is set.
+    fn put(&mut self, cidr: Cidr, id: u32, replace: bool) {
+        match cidr {
+            Cidr::V4(addr, prefix) => {
+                if replace {
+                    self.v4.remove_prefix(addr, prefix);
+                }
+                self.v4.put(addr, prefix, id);
+            }
+            Cidr::V6(addr, prefix) => {
+                if replace {
+                    self.v6.remove_prefix(addr, prefix);
+                }
+                self.v6.put(addr, prefix, id);
+            }
+        }
+    }
+
+    // every cidr of db under its country code, returns how many there were.
+    fn load(&mut self, db: Result<GeoIpDatabase, GeoIpError>) -> c_int {
+        let db = match db {
+            Ok(db) => db,
+            Err(GeoIpError::Io(_)) => return CIDR_MATCHER_ERR_IO,
+            Err(GeoIpError::Protobuf(_)) | Err(GeoIpError::ReverseMatchConflict { .. }) => {
+                return CIDR_MATCHER_ERR_FORMAT
+            }
+            Err(GeoIpError::InvalidCidr { .. }) => return CIDR_MATCHER_ERR_CIDR,
+        };
+        // every code is checked before anything is added.
+        let mut codes = vec![];
+        for (country_code, trie) in db.iter() {
+            match CString::new(country_code) {
+                Ok(code) => codes.push((code, trie)),
+                Err(_) => return CIDR_MATCHER_ERR_FORMAT,
+            }
+        }
+        let mut count = 0;
+        for (code, trie) in codes {
+            let id = self.value_id(code);
+            for (cidr, _) in trie.iter() {
+                self.put(cidr, id, false);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn lookup(&self, id: Option<&u32>) -> i32 {
+        match id {
+            Some(id) => *id as i32,
+            None => -1,
+        }
+    }
+}
+
+// runs an exported function's body, a panic is answered with err instead of
+// unwinding into C.
+fn guard<R>(err: R, body: impl FnOnce() -> R) -> R {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(err)
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+unsafe fn cidr_arg(s: *const c_char) -> Result<Cidr, c_int> {
+    match str_arg(s) {
+        Some(s) => s.parse().map_err(|_| CIDR_MATCHER_ERR_CIDR),
+        None => Err(CIDR_MATCHER_ERR_ARGUMENT),
+    }
+}
+
+/// an empty matcher, released with cidr_matcher_free.
+#[no_mangle]
+pub extern "C" fn cidr_matcher_new() -> *mut CidrMatcher {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(CidrMatcher::new()))
+    })
+}
+
+/// frees the handle and its values, NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_free(matcher: *mut CidrMatcher) {
+    guard((), || {
+        if !matcher.is_null() {
+            drop(Box::from_raw(matcher));
+        }
+    })
+}
+
+/// Adds every cidr of a geoip.dat under its upper case country code. A
+/// prefix that is already there keeps its value, so on a prefix listed by
+/// two codes the one loaded first wins. reverse_match is not applied.
+/// Returns the number of cidrs read or a CIDR_MATCHER_ERR_* code, nothing is
+/// added on error.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_load_geoip_file(
+    matcher: *mut CidrMatcher,
+    path: *const c_char,
+) -> c_int {
+    guard(CIDR_MATCHER_ERR_PANIC, || {
+        match (matcher.as_mut(), str_arg(path)) {
+            (Some(matcher), Some(path)) => matcher.load(GeoIpDatabase::from_path(Path::new(path))),
+            _ => CIDR_MATCHER_ERR_ARGUMENT,
+        }
+    })
+}
+
+/// The same for the len bytes of a geoip.dat at data, which may be NULL when
+/// len is 0.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_load_geoip_buffer(
+    matcher: *mut CidrMatcher,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(CIDR_MATCHER_ERR_PANIC, || match matcher.as_mut() {
+        Some(matcher) if !data.is_null() || len == 0 => {
+            let bytes = if len == 0 {
+                &[]
+            } else {
+                slice::from_raw_parts(data, len)
+            };
+            matcher.load(GeoIpDatabase::from_bytes(bytes))
+        }
+        _ => CIDR_MATCHER_ERR_ARGUMENT,
+    })
+}
+
+/// Sets the value of a cidr such as "1.0.1.0/24" or "240e::/20", replacing
+/// what the prefix held. Returns the id of value or a CIDR_MATCHER_ERR_* code.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_add(
+    matcher: *mut CidrMatcher,
+    cidr: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    guard(CIDR_MATCHER_ERR_PANIC, || {
+        let matcher = match matcher.as_mut() {
+            Some(matcher) if !value.is_null() => matcher,
+            _ => return CIDR_MATCHER_ERR_ARGUMENT,
+        };
+        let cidr = match cidr_arg(cidr) {
+            Ok(cidr) => cidr,
+            Err(e) => return e,
+        };
+        let id = matcher.value_id(CStr::from_ptr(value).to_owned());
+        matcher.put(cidr, id, true);
+        id as c_int
+    })
+}
+
+/// Drops exactly that prefix. Returns 1 if it was there, 0 if not, or a
+/// CIDR_MATCHER_ERR_* code.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_remove(
+    matcher: *mut CidrMatcher,
+    cidr: *const c_char,
+) -> c_int {
+    guard(CIDR_MATCHER_ERR_PANIC, || {
+        let matcher = match matcher.as_mut() {
+            Some(matcher) => matcher,
+            None => return CIDR_MATCHER_ERR_ARGUMENT,
+        };
+        let removed = match cidr_arg(cidr) {
+            Ok(Cidr::V4(addr, prefix)) => matcher.v4.remove_prefix(addr, prefix),
+            Ok(Cidr::V6(addr, prefix)) => matcher.v6.remove_prefix(addr, prefix),
+            Err(e) => return e,
+        };
+        removed as c_int
+    })
+}
+
+/// The value id of the longest prefix covering the address, -1 when none
+/// does. The address is in host order, 0x01020304 for 1.2.3.4.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_lookup_v4(matcher: *const CidrMatcher, addr: u32) -> i32 {
+    guard(-1, || match matcher.as_ref() {
+        Some(matcher) => matcher.lookup(matcher.v4.get_value(addr)),
+        None => -1,
+    })
+}
+
+/// The same for an IPv6 address, 16 bytes in network order.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_lookup_v6(
+    matcher: *const CidrMatcher,
+    addr: *const u8,
+) -> i32 {
+    guard(-1, || match matcher.as_ref() {
+        Some(matcher) if !addr.is_null() => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(slice::from_raw_parts(addr, 16));
+            matcher.lookup(matcher.v6.get_value(Ipv6Addr::from(octets).into()))
+        }
+        _ => -1,
+    })
+}
+
+/// The value of an id, owned by the handle, or NULL for an unknown id.
+#[no_mangle]
+pub unsafe extern "C" fn cidr_matcher_value(matcher: *const CidrMatcher, id: i32) -> *const c_char {
+    guard(ptr::null(), || match matcher.as_ref() {
+        Some(matcher) if id >= 0 => matcher
+            .values
+            .get(id as usize)
+            .map_or(ptr::null(), |v| v.as_ptr()),
+        _ => ptr::null(),
+    })
+}
+
+#[test]
+fn test_ffi() {
+    use crate::geoip_writer::GeoIpListBuilder;
+
+    let c = |s: &str| CString::new(s).unwrap();
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs(
+        "cn",
+        ["1.0.1.0/24", "240e::/20"]
+            .iter()
+            .map(|s| s.parse().unwrap()),
+    );
+    builder.add_cidrs(
+        "us",
+        ["8.8.8.0/24", "1.0.1.0/24"]
+            .iter()
+            .map(|s| s.parse().unwrap()),
+    );
+    let dat = builder.write_to_bytes().unwrap();
+
+    unsafe {
+        let m = cidr_matcher_new();
+        assert_eq!(
+            cidr_matcher_load_geoip_buffer(m, dat.as_ptr(), dat.len()),
+            4
+        );
+        assert_eq!(
+            cidr_matcher_load_geoip_buffer(m, dat.as_ptr(), 3),
+            CIDR_MATCHER_ERR_FORMAT
+        );
+        assert_eq!(guard(CIDR_MATCHER_ERR_PANIC, || panic!("bug")), -5);
+        // a code C cannot hold rejects the whole file.
+        let mut bad = GeoIpListBuilder::new();
+        bad.add_cidrs("jp", ["9.9.9.0/24".parse().unwrap()]);
+        bad.add_cidrs("x\0y", ["9.9.8.0/24".parse().unwrap()]);
+        let bad = bad.write_to_bytes().unwrap();
+        assert_eq!(
+            cidr_matcher_load_geoip_buffer(m, bad.as_ptr(), bad.len()),
+            CIDR_MATCHER_ERR_FORMAT
+        );
+        assert_eq!(cidr_matcher_lookup_v4(m, 0x0909_0909), -1);
+        assert!(cidr_matcher_value(m, 2).is_null());
+        let path = c("/nonexistent/geoip.dat");
+        assert_eq!(
+            cidr_matcher_load_geoip_file(m, path.as_ptr()),
+            CIDR_MATCHER_ERR_IO
+        );
+
+        // the code loaded first keeps a shared prefix.
+        let value = |id: i32| CStr::from_ptr(cidr_matcher_value(m, id)).to_str().unwrap();
+        assert_eq!(value(cidr_matcher_lookup_v4(m, 0x0100_0107)), "CN");
+        assert_eq!(value(cidr_matcher_lookup_v4(m, 0x0808_0808)), "US");
+        assert_eq!(cidr_matcher_lookup_v4(m, 0x0101_0101), -1);
+        let v6 = "240e:1::1".parse::<Ipv6Addr>().unwrap().octets();
+        assert_eq!(value(cidr_matcher_lookup_v6(m, v6.as_ptr())), "CN");
+
+        // add replaces, remove drops the exact prefix only.
+        let id = cidr_matcher_add(m, c("1.0.1.128/25").as_ptr(), c("lan").as_ptr());
+        assert_eq!(id, 2);
+        assert_eq!(
+            cidr_matcher_add(m, c("1.0.1.0/24").as_ptr(), c("US").as_ptr()),
+            1
+        );
+        assert_eq!(cidr_matcher_lookup_v4(m, 0x0100_0181), 2);
+        assert_eq!(value(cidr_matcher_lookup_v4(m, 0x0100_0101)), "US");
+        assert_eq!(cidr_matcher_remove(m, c("1.0.1.128/25").as_ptr()), 1);
+        assert_eq!(cidr_matcher_remove(m, c("1.0.1.128/25").as_ptr()), 0);
+        assert_eq!(cidr_matcher_lookup_v4(m, 0x0100_0181), 1);
+        assert_eq!(
+            cidr_matcher_add(m, c("1.0.1.0/33").as_ptr(), c("x").as_ptr()),
+            CIDR_MATCHER_ERR_CIDR
+        );
+        assert_eq!(
+            cidr_matcher_add(m, ptr::null(), c("x").as_ptr()),
+            CIDR_MATCHER_ERR_ARGUMENT
+        );
+        assert!(cidr_matcher_value(m, 3).is_null());
+        assert!(cidr_matcher_value(m, -1).is_null());
+        cidr_matcher_free(m);
+    }
+    assert_eq!(unsafe { cidr_matcher_lookup_v4(ptr::null(), 0) }, -1);
+}
@@ -0,0 +1,451 @@
+use crate::cidr::{Cidr, CidrError};
+use crate::dual_stack::DualStackTrie;
+use crate::geoip::{GeoIP, GeoIPList, CIDR};
+use deepsize::DeepSizeOf;
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, Message, ProtobufError};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum GeoIpError {
+    Io(io::Error),
+    Protobuf(ProtobufError),
+    InvalidCidr {
+        country_code: String,
+        error: CidrError,
+    },
+    // two entries of one country code disagree on reverse_match.
+    ReverseMatchConflict {
+        country_code: String,
+    },
+}
+
+impl fmt::Display for GeoIpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoIpError::Io(e) => write!(f, "read geoip data failed: {}", e),
+            GeoIpError::Protobuf(e) => write!(f, "geoip data has invalid format: {}", e),
+            GeoIpError::InvalidCidr {
+                country_code,
+                error,
+            } => write!(f, "{} has an invalid cidr: {}", country_code, error),
+            GeoIpError::ReverseMatchConflict { country_code } => write!(
+                f,
+                "{} is listed with and without reverse_match",
+                country_code
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GeoIpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GeoIpError::Io(e) => Some(e),
+            GeoIpError::Protobuf(e) => Some(e),
+            GeoIpError::InvalidCidr { error, .. } => Some(error),
+            GeoIpError::ReverseMatchConflict { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for GeoIpError {
+    fn from(e: io::Error) -> Self {
+        GeoIpError::Io(e)
+    }
+}
+
+impl From<ProtobufError> for GeoIpError {
+    fn from(e: ProtobufError) -> Self {
+        GeoIpError::Protobuf(e)
+    }
+}
+
+// the cidrs of an entry, an invalid one fails the whole entry.
+pub(crate) fn entry_cidrs(geoip: &GeoIP, country_code: &str) -> Result<Vec<Cidr>, GeoIpError> {
+    geoip
+        .cidr
+        .iter()
+        .map(|pair| {
+            Cidr::from_bytes(&pair.ip, pair.prefix).map_err(|error| GeoIpError::InvalidCidr {
+                country_code: country_code.to_string(),
+                error,
+            })
+        })
+        .collect()
+}
+
+#[derive(DeepSizeOf)]
+struct Country {
+    code: String,
+    trie: DualStackTrie,
+    reverse_match: bool,
+}
+
+// All country codes of a geoip.dat, each one in its own dual-stack trie.
+// Codes are kept upper case and in the order they first appear.
+#[derive(Default, DeepSizeOf)]
+pub struct GeoIpDatabase {
+    countries: Vec<Country>,
+    index: HashMap<String, usize>,
+}
+
+impl GeoIpDatabase {
+    pub fn new() -> GeoIpDatabase {
+        Default::default()
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        GeoIpDatabase::from_reader(&mut reader)
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<GeoIpDatabase, GeoIpError> {
+        let list: GeoIPList = Message::parse_from_reader(reader)?;
+        GeoIpDatabase::from_list(&list)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<GeoIpDatabase, GeoIpError> {
+        let list: GeoIPList = Message::parse_from_bytes(bytes)?;
+        GeoIpDatabase::from_list(&list)
+    }
+
+    pub fn from_list(list: &GeoIPList) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut db = GeoIpDatabase::new();
+        for geoip in list.entry.iter() {
+            db.add(geoip)?;
+        }
+        Ok(db)
+    }
+
+    // Like from_path, but only the listed country codes are loaded. The file is
+    // walked entry by entry and the cidr lists of other codes are never decoded.
+    pub fn from_path_with_codes<P: AsRef<Path>>(
+        path: P,
+        country_codes: &[&str],
+    ) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        GeoIpDatabase::from_reader_with_codes(&mut reader, country_codes)
+    }
+
+    pub fn from_reader_with_codes<R: Read>(
+        reader: &mut R,
+        country_codes: &[&str],
+    ) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut is = CodedInputStream::new(reader);
+        GeoIpDatabase::load_selected(&mut is, country_codes)
+    }
+
+    pub fn from_bytes_with_codes(
+        bytes: &[u8],
+        country_codes: &[&str],
+    ) -> Result<GeoIpDatabase, GeoIpError> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        GeoIpDatabase::load_selected(&mut is, country_codes)
+    }
+
+    fn load_selected(
+        is: &mut CodedInputStream,
+        country_codes: &[&str],
+    ) -> Result<GeoIpDatabase, GeoIpError> {
+        let wanted: HashSet<String> = country_codes.iter().map(|c| c.to_uppercase()).collect();
+        let mut db = GeoIpDatabase::new();
+        // GeoIPList { repeated GeoIP entry = 1; }
+        while !is.eof()? {
+            let (field, wire_type) = is.read_tag_unpack()?;
+            if field != 1 || wire_type != WireType::WireTypeLengthDelimited {
+                is.skip_field(wire_type)?;
+                continue;
+            }
+            let len = is.read_raw_varint64()?;
+            let old_limit = is.push_limit(len)?;
+            if let Some(geoip) = GeoIpDatabase::read_selected_entry(is, &wanted)? {
+                db.add(&geoip)?;
+            }
+            is.pop_limit(old_limit);
+        }
+        Ok(db)
+    }
+
+    // GeoIP { string country_code = 1; repeated CIDR cidr = 2; }
+    fn read_selected_entry(
+        is: &mut CodedInputStream,
+        wanted: &HashSet<String>,
+    ) -> Result<Option<GeoIP>, GeoIpError> {
+        let mut geoip = GeoIP::new();
+        let mut has_code = false;
+        // cidrs placed before the country code, kept raw until we know it is wanted.
+        let mut pending: Vec<Vec<u8>> = vec![];
+        while !is.eof()? {
+            let (field, wire_type) = is.read_tag_unpack()?;
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    geoip.country_code = is.read_string()?;
+                    has_code = true;
+                    if !wanted.contains(&geoip.country_code.to_uppercase()) {
+                        is.skip_raw_bytes(is.bytes_until_limit() as u32)?;
+                        return Ok(None);
+                    }
+                }
+                (2, WireType::WireTypeLengthDelimited) if has_code => {
+                    geoip.cidr.push(is.read_message()?);
+                }
+                (2, WireType::WireTypeLengthDelimited) => {
+                    let len = is.read_raw_varint32()?;
+                    pending.push(is.read_raw_bytes(len)?);
+                }
+                (3, WireType::WireTypeVarint) => geoip.reverse_match = is.read_bool()?,
+                _ => is.skip_field(wire_type)?,
+            }
+        }
+        if !wanted.contains(&geoip.country_code.to_uppercase()) {
+            return Ok(None);
+        }
+        for bytes in pending {
+            let pair: CIDR = Message::parse_from_bytes(&bytes)?;
+            geoip.cidr.push(pair);
+        }
+        Ok(Some(geoip))
+    }
+
+    // add every cidr of the entry, merging with a code that is already loaded.
+    // Both must agree on reverse_match, nothing is added when they do not.
+    pub fn add(&mut self, geoip: &GeoIP) -> Result<(), GeoIpError> {
+        let country_code = geoip.country_code.to_uppercase();
+        if let Some(c) = self.country(&country_code) {
+            if c.reverse_match != geoip.reverse_match {
+                return Err(GeoIpError::ReverseMatchConflict { country_code });
+            }
+        }
+        let cidrs = entry_cidrs(geoip, &country_code)?;
+        let country = self.country_mut(country_code.clone());
+        country.reverse_match = geoip.reverse_match;
+        for cidr in cidrs {
+            country.trie.put(cidr, country_code.clone());
+        }
+        Ok(())
+    }
+
+    pub fn put(&mut self, country_code: &str, cidr: Cidr) {
+        let country_code = country_code.to_uppercase();
+        let country = self.country_mut(country_code.clone());
+        country.trie.put(cidr, country_code);
+    }
+
+    fn country_mut(&mut self, country_code: String) -> &mut Country {
+        let idx = match self.index.get(&country_code) {
+            Some(&idx) => idx,
+            None => {
+                let idx = self.countries.len();
+                self.index.insert(country_code.clone(), idx);
+                self.countries.push(Country {
+                    code: country_code,
+                    trie: DualStackTrie::new(),
+                    reverse_match: false,
+                });
+                idx
+            }
+        };
+        &mut self.countries[idx]
+    }
+
+    fn country(&self, country_code: &str) -> Option<&Country> {
+        self.index
+            .get(&country_code.to_uppercase())
+            .map(|&idx| &self.countries[idx])
+    }
+
+    // the first loaded country code matching ip.
+    pub fn country_of(&self, ip: IpAddr) -> Option<&str> {
+        self.countries
+            .iter()
+            .find(|c| c.trie.get(ip) != c.reverse_match)
+            .map(|c| c.code.as_str())
+    }
+
+    // whether ip matches the code, honouring its reverse_match flag.
+    pub fn contains(&self, country_code: &str, ip: IpAddr) -> bool {
+        match self.country(country_code) {
+            Some(c) => c.trie.get(ip) != c.reverse_match,
+            None => false,
+        }
+    }
+
+    pub fn is_reverse_match(&self, country_code: &str) -> bool {
+        self.country(country_code).is_some_and(|c| c.reverse_match)
+    }
+
+    pub fn set_reverse_match(&mut self, country_code: &str, reverse_match: bool) {
+        if let Some(&idx) = self.index.get(&country_code.to_uppercase()) {
+            self.countries[idx].reverse_match = reverse_match;
+        }
+    }
+
+    // the cidrs loaded for the code, reverse_match is not applied.
+    pub fn get(&self, country_code: &str) -> Option<&DualStackTrie> {
+        self.country(country_code).map(|c| &c.trie)
+    }
+
+    pub fn country_codes(&self) -> impl Iterator<Item = &str> {
+        self.countries.iter().map(|c| c.code.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &DualStackTrie)> {
+        self.countries.iter().map(|c| (c.code.as_str(), &c.trie))
+    }
+
+    pub fn len(&self) -> usize {
+        self.countries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.countries.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn geoip_entry(country_code: &str, cidrs: &[&str]) -> GeoIP {
+    let mut geoip = GeoIP::new();
+    geoip.country_code = country_code.to_string();
+    for s in cidrs {
+        let cidr: Cidr = s.parse().unwrap();
+        let mut pair = crate::geoip::CIDR::new();
+        pair.ip = match cidr.addr() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        pair.prefix = cidr.prefix() as u32;
+        geoip.cidr.push(pair);
+    }
+    geoip
+}
+
+#[test]
+fn test_geoip_database() {
+    let mut list = GeoIPList::new();
+    list.entry.push(geoip_entry(
+        "cn",
+        &["1.0.1.0/24", "1.0.2.0/23", "2400:da00::/32"],
+    ));
+    list.entry
+        .push(geoip_entry("US", &["8.8.8.0/24", "2001:4860::/32"]));
+    list.entry
+        .push(geoip_entry("private", &["10.0.0.0/8", "fc00::/7"]));
+    let bytes = list.write_to_bytes().unwrap();
+    let db = GeoIpDatabase::from_bytes(&bytes).unwrap();
+
+    assert_eq!(db.len(), 3);
+    assert_eq!(
+        db.country_codes().collect::<Vec<_>>(),
+        vec!["CN", "US", "PRIVATE"]
+    );
+    assert_eq!(db.country_of("1.0.3.4".parse().unwrap()), Some("CN"));
+    assert_eq!(db.country_of("2400:da00::1".parse().unwrap()), Some("CN"));
+    assert_eq!(db.country_of("8.8.8.8".parse().unwrap()), Some("US"));
+    assert_eq!(db.country_of("10.1.1.1".parse().unwrap()), Some("PRIVATE"));
+    assert_eq!(db.country_of("9.9.9.9".parse().unwrap()), None);
+    assert!(db.contains("cn", "1.0.1.1".parse().unwrap()));
+    assert!(!db.contains("US", "1.0.1.1".parse().unwrap()));
+    assert!(!db.contains("JP", "1.0.1.1".parse().unwrap()));
+
+    let mut bad = GeoIPList::new();
+    let mut entry = geoip_entry("XX", &[]);
+    let mut pair = crate::geoip::CIDR::new();
+    pair.ip = vec![1, 2, 3];
+    pair.prefix = 8;
+    entry.cidr.push(pair);
+    bad.entry.push(entry);
+    match GeoIpDatabase::from_list(&bad) {
+        Err(GeoIpError::InvalidCidr {
+            country_code,
+            error: CidrError::InvalidLength(3),
+        }) => assert_eq!(country_code, "XX"),
+        _ => panic!("expected invalid cidr"),
+    }
+    assert!(GeoIpDatabase::from_bytes(&[0xff, 0xff]).is_err());
+    assert!(GeoIpDatabase::from_path("no/such/geoip.dat").is_err());
+}
+
+#[test]
+fn test_geoip_database_with_codes() {
+    let mut list = GeoIPList::new();
+    list.entry.push(geoip_entry("US", &["8.8.8.0/24"]));
+    list.entry
+        .push(geoip_entry("cn", &["1.0.1.0/24", "2400:da00::/32"]));
+    list.entry.push(geoip_entry("JP", &["1.0.16.0/20"]));
+    let mut bytes = list.write_to_bytes().unwrap();
+
+    // an entry with its cidr list written before the country code.
+    let cidr = geoip_entry("", &["10.0.0.0/8"]).cidr[0]
+        .write_to_bytes()
+        .unwrap();
+    let mut entry = vec![0x12, cidr.len() as u8];
+    entry.extend(&cidr);
+    entry.extend(&[0x0a, 7]);
+    entry.extend(b"private");
+    bytes.push(0x0a);
+    bytes.push(entry.len() as u8);
+    bytes.extend(&entry);
+
+    let db = GeoIpDatabase::from_bytes_with_codes(&bytes, &["CN", "private"]).unwrap();
+    assert_eq!(
+        db.country_codes().collect::<Vec<_>>(),
+        vec!["CN", "PRIVATE"]
+    );
+    assert!(db.contains("CN", "1.0.1.1".parse().unwrap()));
+    assert!(db.contains("CN", "2400:da00::1".parse().unwrap()));
+    assert!(db.contains("PRIVATE", "10.2.3.4".parse().unwrap()));
+    assert!(db.get("US").is_none());
+    assert_eq!(db.country_of("8.8.8.8".parse().unwrap()), None);
+
+    let full = GeoIpDatabase::from_reader_with_codes(&mut &bytes[..], &["US", "JP"]).unwrap();
+    assert_eq!(full.country_codes().collect::<Vec<_>>(), vec!["US", "JP"]);
+    assert!(GeoIpDatabase::from_bytes_with_codes(&bytes[..bytes.len() - 1], &["CN"]).is_err());
+}
+
+#[test]
+fn test_geoip_database_reverse_match() {
+    let mut list = GeoIPList::new();
+    let mut not_cn = geoip_entry("!cn", &["1.0.1.0/24", "2400:da00::/32"]);
+    not_cn.reverse_match = true;
+    list.entry.push(not_cn);
+    list.entry
+        .push(geoip_entry("cn", &["1.0.1.0/24", "2400:da00::/32"]));
+    let bytes = list.write_to_bytes().unwrap();
+
+    for db in [
+        GeoIpDatabase::from_bytes(&bytes).unwrap(),
+        GeoIpDatabase::from_bytes_with_codes(&bytes, &["!CN", "CN"]).unwrap(),
+    ]
+    .iter()
+    {
+        assert!(db.is_reverse_match("!cn"));
+        assert!(!db.is_reverse_match("cn"));
+        assert!(db.contains("!CN", "8.8.8.8".parse().unwrap()));
+        assert!(db.contains("!CN", "2001:4860::1".parse().unwrap()));
+        assert!(!db.contains("!CN", "1.0.1.1".parse().unwrap()));
+        assert!(!db.contains("!CN", "2400:da00::1".parse().unwrap()));
+        assert_eq!(db.country_of("1.0.1.1".parse().unwrap()), Some("CN"));
+        assert_eq!(db.country_of("8.8.8.8".parse().unwrap()), Some("!CN"));
+    }
+
+    // entries of one code merge only when they agree on reverse_match.
+    let mut db = GeoIpDatabase::from_bytes(&bytes).unwrap();
+    let mut more = geoip_entry("!CN", &["36.96.0.0/11"]);
+    more.reverse_match = true;
+    db.add(&more).unwrap();
+    assert!(!db.contains("!cn", "36.96.0.1".parse().unwrap()));
+    match db.add(&geoip_entry("!cn", &["8.8.8.0/24"])) {
+        Err(GeoIpError::ReverseMatchConflict { country_code }) => assert_eq!(country_code, "!CN"),
+        _ => panic!("conflicting reverse_match accepted"),
+    }
+    assert!(db.is_reverse_match("!cn"));
+    assert!(db.contains("!cn", "8.8.8.8".parse().unwrap()));
+    list.entry.push(geoip_entry("!cn", &["8.8.8.0/24"]));
+    assert!(GeoIpDatabase::from_bytes(&list.write_to_bytes().unwrap()).is_err());
+}
@@ -0,0 +1,241 @@
+use crate::cidr::{aggregate, Cidr};
+use crate::dual_stack::DualStackTrie;
+use crate::geoip::{GeoIP, GeoIPList, CIDR};
+use crate::geoip_db::{GeoIpDatabase, GeoIpError};
+use crate::lpc_trie::LPCTrie;
+use protobuf::Message;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+// Collects cidrs per country code and writes them out as a GeoIPList.
+// The output is deterministic: codes are sorted, cidrs are sorted IPv4 first
+// by address then prefix, and duplicates are dropped.
+#[derive(Default)]
+pub struct GeoIpListBuilder {
+    countries: BTreeMap<String, Vec<Cidr>>,
+    reverse_match: BTreeSet<String>,
+    aggregate: bool,
+}
+
+impl GeoIpListBuilder {
+    pub fn new() -> GeoIpListBuilder {
+        Default::default()
+    }
+
+    // merge overlapping and adjacent cidrs of each code before writing.
+    pub fn aggregate(mut self, aggregate: bool) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn add_cidrs<I: IntoIterator<Item = Cidr>>(&mut self, country_code: &str, cidrs: I) {
+        self.countries
+            .entry(country_code.to_uppercase())
+            .or_default()
+            .extend(cidrs);
+    }
+
+    pub fn set_reverse_match(&mut self, country_code: &str, reverse_match: bool) {
+        let country_code = country_code.to_uppercase();
+        self.countries.entry(country_code.clone()).or_default();
+        if reverse_match {
+            self.reverse_match.insert(country_code);
+        } else {
+            self.reverse_match.remove(&country_code);
+        }
+    }
+
+    pub fn add_v4_trie(&mut self, country_code: &str, trie: &LPCTrie<u32>) {
+        self.add_cidrs(country_code, trie.iter().map(|(k, p, _)| Cidr::V4(k, p)));
+    }
+
+    pub fn add_v6_trie(&mut self, country_code: &str, trie: &LPCTrie<u128>) {
+        self.add_cidrs(country_code, trie.iter().map(|(k, p, _)| Cidr::V6(k, p)));
+    }
+
+    pub fn add_dual_stack(&mut self, country_code: &str, trie: &DualStackTrie) {
+        self.add_cidrs(country_code, trie.iter().map(|(cidr, _)| cidr));
+    }
+
+    // every code of the database, merged with codes that are already added.
+    // Both must agree on reverse_match, nothing is added when one does not.
+    pub fn add_database(&mut self, db: &GeoIpDatabase) -> Result<(), GeoIpError> {
+        for country_code in db.country_codes() {
+            if self.countries.contains_key(country_code)
+                && self.reverse_match.contains(country_code) != db.is_reverse_match(country_code)
+            {
+                return Err(GeoIpError::ReverseMatchConflict {
+                    country_code: country_code.to_string(),
+                });
+            }
+        }
+        for (country_code, trie) in db.iter() {
+            self.add_dual_stack(country_code, trie);
+            if db.is_reverse_match(country_code) {
+                self.set_reverse_match(country_code, true);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> GeoIPList {
+        let mut list = GeoIPList::new();
+        for (country_code, cidrs) in self.countries.iter() {
+            let cidrs = if self.aggregate {
+                aggregate(cidrs.iter().copied())
+            } else {
+                let mut cidrs = cidrs.clone();
+                cidrs.sort_unstable();
+                cidrs.dedup();
+                cidrs
+            };
+            let mut geoip = GeoIP::new();
+            geoip.country_code = country_code.clone();
+            geoip.reverse_match = self.reverse_match.contains(country_code);
+            for cidr in cidrs {
+                let mut pair = CIDR::new();
+                pair.ip = match cidr.addr() {
+                    IpAddr::V4(ip) => ip.octets().to_vec(),
+                    IpAddr::V6(ip) => ip.octets().to_vec(),
+                };
+                pair.prefix = cidr.prefix() as u32;
+                geoip.cidr.push(pair);
+            }
+            list.entry.push(geoip);
+        }
+        list
+    }
+
+    pub fn write_to_bytes(&self) -> Result<Vec<u8>, GeoIpError> {
+        Ok(self.build().write_to_bytes()?)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), GeoIpError> {
+        self.build().write_to_writer(writer)?;
+        Ok(())
+    }
+
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), GeoIpError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_geoip_list_builder() {
+    use crate::geoip_db::geoip_entry;
+
+    let mut list = GeoIPList::new();
+    list.entry.push(geoip_entry(
+        "cn",
+        &["1.0.2.0/24", "1.0.1.0/24", "2400:da00::/32", "1.0.3.0/24"],
+    ));
+    list.entry.push(geoip_entry("private", &["10.0.0.0/8"]));
+    let db = GeoIpDatabase::from_list(&list).unwrap();
+
+    let mut corp = LPCTrie::<u32>::new();
+    corp.put(0xac10_0000, 12, "CORP".to_string());
+    let mut corp6 = LPCTrie::<u128>::new();
+    corp6.put(0xfd00 << 112, 8, "CORP".to_string());
+
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_v6_trie("corp", &corp6);
+    builder.add_v4_trie("corp", &corp);
+    builder.add_database(&db).unwrap();
+    builder.add_cidrs("private", vec!["10.0.0.0/8".parse().unwrap()]);
+
+    let out = builder.build();
+    let codes: Vec<_> = out.entry.iter().map(|e| e.country_code.as_str()).collect();
+    assert_eq!(codes, vec!["CN", "CORP", "PRIVATE"]);
+    assert_eq!(out.entry[0].cidr.len(), 4);
+    assert_eq!(out.entry[0].cidr[0].ip, vec![1, 0, 1, 0]);
+    assert_eq!(out.entry[1].cidr[0].ip, vec![172, 16, 0, 0]);
+    assert_eq!(out.entry[1].cidr[1].ip.len(), 16);
+    assert_eq!(out.entry[2].cidr.len(), 1);
+
+    let builder = builder.aggregate(true);
+    let out = builder.build();
+    assert_eq!(out.entry[0].cidr.len(), 3);
+    assert_eq!(out.entry[0].cidr[0].prefix, 24);
+    assert_eq!(out.entry[0].cidr[1].ip, vec![1, 0, 2, 0]);
+    assert_eq!(out.entry[0].cidr[1].prefix, 23);
+
+    let bytes = builder.write_to_bytes().unwrap();
+    assert_eq!(bytes, builder.write_to_bytes().unwrap());
+    let reloaded = GeoIpDatabase::from_bytes(&bytes).unwrap();
+    assert!(reloaded.contains("cn", "1.0.3.255".parse().unwrap()));
+    assert!(reloaded.contains("corp", "172.31.0.1".parse().unwrap()));
+    assert!(reloaded.contains("CORP", "fd12::1".parse().unwrap()));
+    assert!(!reloaded.contains("CORP", "1.0.1.1".parse().unwrap()));
+}
+
+#[test]
+fn test_geoip_list_builder_reverse_match() {
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs("private", vec!["10.0.0.0/8".parse().unwrap()]);
+    builder.add_cidrs("!cn", vec!["1.0.1.0/24".parse().unwrap()]);
+    builder.set_reverse_match("!cn", true);
+    builder.set_reverse_match("private", true);
+    builder.set_reverse_match("private", false);
+
+    let out = builder.build();
+    assert!(out.entry[0].reverse_match);
+    assert!(!out.entry[1].reverse_match);
+
+    let reloaded = GeoIpDatabase::from_bytes(&builder.write_to_bytes().unwrap()).unwrap();
+    assert!(reloaded.is_reverse_match("!CN"));
+    assert!(!reloaded.contains("!cn", "1.0.1.1".parse().unwrap()));
+    assert!(reloaded.contains("!cn", "8.8.8.8".parse().unwrap()));
+    assert!(!reloaded.is_reverse_match("private"));
+    assert!(reloaded.contains("private", "10.1.2.3".parse().unwrap()));
+}
+
+#[test]
+fn test_geoip_list_builder_reverse_match_conflict() {
+    use crate::geoip_db::geoip_entry;
+
+    let mut list = GeoIPList::new();
+    let mut not_cn = geoip_entry("!cn", &["1.0.1.0/24"]);
+    not_cn.reverse_match = true;
+    list.entry.push(not_cn);
+    list.entry.push(geoip_entry("private", &["10.0.0.0/8"]));
+    let db = GeoIpDatabase::from_list(&list).unwrap();
+
+    // the database inverts a code added plainly.
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs("!CN", vec!["8.8.8.0/24".parse().unwrap()]);
+    match builder.add_database(&db) {
+        Err(GeoIpError::ReverseMatchConflict { country_code }) => assert_eq!(country_code, "!CN"),
+        _ => panic!("expected a reverse_match conflict"),
+    }
+    // nothing was merged, private included.
+    assert_eq!(builder.build().entry.len(), 1);
+    assert!(!builder.build().entry[0].reverse_match);
+
+    // a code added inverted, plain in the database.
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs("private", vec!["192.168.0.0/16".parse().unwrap()]);
+    builder.set_reverse_match("private", true);
+    match builder.add_database(&db) {
+        Err(GeoIpError::ReverseMatchConflict { country_code }) => {
+            assert_eq!(country_code, "PRIVATE")
+        }
+        _ => panic!("expected a reverse_match conflict"),
+    }
+    assert!(builder.build().entry[0].reverse_match);
+
+    // agreeing flags merge.
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs("!cn", vec!["8.8.8.0/24".parse().unwrap()]);
+    builder.set_reverse_match("!cn", true);
+    builder.add_database(&db).unwrap();
+    let out = builder.build();
+    assert_eq!(out.entry.len(), 2);
+    assert!(out.entry[0].reverse_match);
+    assert_eq!(out.entry[0].cidr.len(), 2);
+}
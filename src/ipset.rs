@@ -0,0 +1,838 @@
+use crate::cidr::{aggregate, difference, union, Cidr};
+use crate::dual_stack::DualStackTrie;
+use crate::text_list::ParseError;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+// Address family of a set, ipset and nft sets hold only one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetFamily {
+    Inet,
+    Inet6,
+}
+
+impl SetFamily {
+    fn of(cidr: &Cidr) -> SetFamily {
+        if cidr.is_ipv4() {
+            SetFamily::Inet
+        } else {
+            SetFamily::Inet6
+        }
+    }
+
+    fn ipset_name(self) -> &'static str {
+        match self {
+            SetFamily::Inet => "inet",
+            SetFamily::Inet6 => "inet6",
+        }
+    }
+
+    fn nft_type(self) -> &'static str {
+        match self {
+            SetFamily::Inet => "ipv4_addr",
+            SetFamily::Inet6 => "ipv6_addr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetEntry {
+    pub cidr: Cidr,
+    // per element timeout in seconds, 0 keeps the element forever.
+    pub timeout: Option<u32>,
+}
+
+// A hash:net ipset or an interval nft set, keeping the timeouts so a set read
+// from the kernel can be written back as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpSet {
+    pub name: String,
+    pub family: SetFamily,
+    // default timeout of the set in seconds, enables per element timeouts.
+    pub timeout: Option<u32>,
+    pub entries: Vec<SetEntry>,
+}
+
+impl IpSet {
+    pub fn new(name: &str, family: SetFamily) -> IpSet {
+        IpSet {
+            name: name.to_string(),
+            family,
+            timeout: None,
+            entries: vec![],
+        }
+    }
+
+    // cidrs of the other family are ignored, returns whether it was added.
+    pub fn add(&mut self, cidr: Cidr, timeout: Option<u32>) -> bool {
+        if SetFamily::of(&cidr) != self.family {
+            return false;
+        }
+        self.entries.push(SetEntry { cidr, timeout });
+        true
+    }
+
+    // the set's family half of a trie, with adjacent and nested cidrs merged
+    // when aggregate is set.
+    pub fn from_trie(
+        name: &str,
+        family: SetFamily,
+        trie: &DualStackTrie,
+        aggregate: bool,
+    ) -> IpSet {
+        let mut set = IpSet::new(name, family);
+        for (cidr, _) in trie.iter() {
+            set.add(cidr, None);
+        }
+        if aggregate {
+            set.aggregate();
+        }
+        set
+    }
+
+    // a trie holding the set name for every entry.
+    pub fn to_dual_stack(&self) -> DualStackTrie {
+        self.entries
+            .iter()
+            .map(|e| (e.cidr, self.name.clone()))
+            .collect()
+    }
+
+    // how long an entry lives, None when it never expires.
+    fn lifetime(&self, timeout: Option<u32>) -> Option<u32> {
+        match timeout.or(self.timeout) {
+            Some(0) | None => None,
+            t => t,
+        }
+    }
+
+    // the entries as disjoint cidrs, merged where they share a timeout.
+    // Where entries overlap the longer lifetime wins.
+    fn disjoint_entries(&self) -> Vec<SetEntry> {
+        let mut groups: BTreeMap<Option<u32>, Vec<Cidr>> = BTreeMap::new();
+        for entry in self.entries.iter() {
+            groups.entry(entry.timeout).or_default().push(entry.cidr);
+        }
+        let mut groups: Vec<(Option<u32>, Vec<Cidr>)> = groups.into_iter().collect();
+        // longest first, entries that never expire before all others.
+        groups.sort_by_key(|(timeout, _)| match self.lifetime(*timeout) {
+            None => (0, 0),
+            Some(t) => (1, u32::MAX - t),
+        });
+        let mut taken: Vec<Cidr> = vec![];
+        let mut entries = vec![];
+        for (timeout, cidrs) in groups {
+            let cidrs = difference(aggregate(cidrs), taken.iter().copied());
+            taken = union(taken, cidrs.iter().copied());
+            entries.extend(cidrs.into_iter().map(|cidr| SetEntry { cidr, timeout }));
+        }
+        entries.sort_by_key(|e| e.cidr);
+        entries
+    }
+
+    // merge adjacent and overlapping entries, see disjoint_entries.
+    pub fn aggregate(&mut self) {
+        self.entries = self.disjoint_entries();
+    }
+
+    // `ipset restore` input.
+    pub fn write_ipset<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let maxelem = self.entries.len().next_power_of_two().max(65536);
+        write!(
+            w,
+            "create {} hash:net family {} hashsize 1024 maxelem {}",
+            self.name,
+            self.family.ipset_name(),
+            maxelem
+        )?;
+        // per element timeouts need one on the set, 0 keeps the others forever.
+        let has_timeouts = self.entries.iter().any(|e| e.timeout.is_some());
+        match self.timeout {
+            Some(timeout) => write!(w, " timeout {}", timeout)?,
+            None if has_timeouts => write!(w, " timeout 0")?,
+            None => {}
+        }
+        writeln!(w)?;
+        for entry in self.entries.iter() {
+            write!(w, "add {} {}", self.name, entry.cidr)?;
+            if let Some(timeout) = entry.timeout {
+                write!(w, " timeout {}", timeout)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    // `nft -f` input adding the set to an existing table. Interval sets take
+    // no overlapping elements, so they are written aggregated.
+    pub fn write_nft<W: Write>(&self, w: &mut W, family: &str, table: &str) -> io::Result<()> {
+        let timeouts = self.timeout.is_some() || self.entries.iter().any(|e| e.timeout.is_some());
+        write!(
+            w,
+            "add set {} {} {} {{ type {}; flags interval{};",
+            family,
+            table,
+            self.name,
+            self.family.nft_type(),
+            if timeouts { ",timeout" } else { "" }
+        )?;
+        if let Some(timeout) = self.timeout {
+            write!(w, " timeout {}s;", timeout)?;
+        }
+        writeln!(w, " }}")?;
+        let entries = self.disjoint_entries();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        writeln!(w, "add element {} {} {} {{", family, table, self.name)?;
+        for (idx, entry) in entries.iter().enumerate() {
+            write!(w, "\t{}", entry.cidr)?;
+            if let Some(timeout) = entry.timeout {
+                write!(w, " timeout {}s", timeout)?;
+            }
+            let last = idx + 1 == entries.len();
+            writeln!(w, "{}", if last { "" } else { "," })?;
+        }
+        writeln!(w, "}}")
+    }
+}
+
+fn parse_seconds(value: &str, line: usize) -> Result<u32, ParseError> {
+    value
+        .parse()
+        .map_err(|_| ParseError::at(line, format!("invalid timeout {:?}", value)))
+}
+
+fn find_set(sets: &[IpSet], name: &str, line: usize) -> Result<usize, ParseError> {
+    match sets.iter().position(|s| s.name == name) {
+        Some(set) => Ok(set),
+        None => Err(ParseError::at(line, format!("unknown set {}", name))),
+    }
+}
+
+fn check_family(set: &IpSet, cidr: Cidr, line: usize) -> Result<(), ParseError> {
+    if SetFamily::of(&cidr) == set.family {
+        Ok(())
+    } else {
+        Err(ParseError::at(
+            line,
+            format!("{} does not match the family of set {}", cidr, set.name),
+        ))
+    }
+}
+
+fn add_entry(
+    set: &mut IpSet,
+    cidr: Cidr,
+    timeout: Option<u32>,
+    line: usize,
+) -> Result<(), ParseError> {
+    check_family(set, cidr, line)?;
+    set.add(cidr, timeout);
+    Ok(())
+}
+
+// The entries of a set less its nomatch exceptions. hash:net answers with
+// the most specific element, so an exception only cuts into the entries it
+// is at least as specific as. More specific entries inside it stay.
+fn remove_nomatch(set: &mut IpSet, nomatch: &[Cidr]) {
+    let entries = std::mem::take(&mut set.entries);
+    for entry in entries {
+        let cidr = entry.cidr;
+        let exceptions = nomatch
+            .iter()
+            .filter(|n| n.prefix() >= cidr.prefix() && cidr.contains(n.addr()))
+            .copied();
+        for cidr in difference(vec![cidr], exceptions) {
+            set.entries.push(SetEntry {
+                cidr,
+                timeout: entry.timeout,
+            });
+        }
+    }
+}
+
+// Read `ipset save` output. Only hash:net and hash:ip sets are supported,
+// they are written back as hash:net. nomatch elements are taken out of the
+// entries they except, see remove_nomatch. Set options besides family and
+// timeout are ignored.
+pub fn read_ipset<R: BufRead>(reader: R) -> Result<Vec<IpSet>, ParseError> {
+    let mut sets: Vec<IpSet> = vec![];
+    // the nomatch elements of each set, by position in sets.
+    let mut nomatch: Vec<Vec<Cidr>> = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let option = |name: &str| {
+            words
+                .iter()
+                .position(|w| *w == name)
+                .and_then(|i| words.get(i + 1).copied())
+        };
+        match words.as_slice() {
+            [] => continue,
+            [w, ..] if w.starts_with('#') => continue,
+            ["create", name, kind, ..] => {
+                if *kind != "hash:net" && *kind != "hash:ip" {
+                    return Err(ParseError::at(
+                        line_no,
+                        format!("unsupported set type {}", kind),
+                    ));
+                }
+                let family = match option("family") {
+                    None | Some("inet") => SetFamily::Inet,
+                    Some("inet6") => SetFamily::Inet6,
+                    Some(f) => {
+                        return Err(ParseError::at(line_no, format!("unknown family {}", f)))
+                    }
+                };
+                let mut set = IpSet::new(name, family);
+                set.timeout = option("timeout")
+                    .map(|t| parse_seconds(t, line_no))
+                    .transpose()?;
+                sets.push(set);
+                nomatch.push(vec![]);
+            }
+            ["add", name, cidr, ..] => {
+                let cidr: Cidr = cidr.parse().map_err(|e| ParseError::at(line_no, e))?;
+                let timeout = option("timeout")
+                    .map(|t| parse_seconds(t, line_no))
+                    .transpose()?;
+                let set = find_set(&sets, name, line_no)?;
+                if words.contains(&"nomatch") {
+                    check_family(&sets[set], cidr, line_no)?;
+                    nomatch[set].push(cidr);
+                } else {
+                    add_entry(&mut sets[set], cidr, timeout, line_no)?;
+                }
+            }
+            _ => {
+                return Err(ParseError::at(
+                    line_no,
+                    format!("unknown command {:?}", line.trim()),
+                ))
+            }
+        }
+    }
+    for (set, nomatch) in sets.iter_mut().zip(nomatch.iter()) {
+        if !nomatch.is_empty() {
+            remove_nomatch(set, nomatch);
+        }
+    }
+    Ok(sets)
+}
+
+// nft durations such as "1d2h3m4s" or "500ms", rounded down to seconds.
+fn parse_duration(value: &str, line: usize) -> Result<u32, ParseError> {
+    let invalid = || ParseError::at(line, format!("invalid timeout {:?}", value));
+    let mut seconds = 0u64;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        seconds += match &rest[..unit] {
+            "d" => n * 86400,
+            "h" => n * 3600,
+            "m" => n * 60,
+            "s" | "" => n,
+            "ms" => n / 1000,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit..];
+    }
+    if seconds > u32::MAX as u64 {
+        return Err(invalid());
+    }
+    Ok(seconds as u32)
+}
+
+// nft tokens with their line, newlines end statements like ";" does.
+fn tokenize(text: &str) -> Vec<(usize, String)> {
+    let mut tokens = vec![];
+    for (idx, line) in text.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '#' => break,
+                '{' | '}' | ',' | ';' | '=' => tokens.push((idx + 1, c.to_string())),
+                '"' => {
+                    let mut word = String::from('"');
+                    for c in chars.by_ref() {
+                        word.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                    tokens.push((idx + 1, word));
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut word = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || "{},;=\"#".contains(c) {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push((idx + 1, word));
+                }
+            }
+        }
+        tokens.push((idx + 1, ";".to_string()));
+    }
+    tokens
+}
+
+struct NftParser {
+    tokens: Vec<(usize, String)>,
+    pos: usize,
+    sets: Vec<IpSet>,
+}
+
+impl NftParser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos).or_else(|| self.tokens.last()) {
+            Some((line, _)) => *line,
+            None => 1,
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|(_, t)| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, ParseError> {
+        match self.tokens.get(self.pos) {
+            Some((_, t)) => {
+                self.pos += 1;
+                Ok(t.clone())
+            }
+            None => Err(ParseError::at(self.line(), "unexpected end of input")),
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        let line = self.line();
+        match self.next()? {
+            t if t == token => Ok(()),
+            t => Err(ParseError::at(
+                line,
+                format!("expected {:?}, found {:?}", token, t),
+            )),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(";") {
+            self.pos += 1;
+        }
+    }
+
+    // skip the rest of a statement, including any block it opens.
+    fn skip_statement(&mut self) -> Result<(), ParseError> {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None => return Ok(()),
+                Some(";") if depth == 0 => return Ok(()),
+                Some("}") if depth == 0 => return Ok(()),
+                Some("{") => depth += 1,
+                Some("}") => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn parse(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                None => return Ok(()),
+                Some("table") => {
+                    self.pos += 1;
+                    // "table [family] name {", the family may be left out.
+                    while self.peek() != Some("{") {
+                        self.next()?;
+                    }
+                    self.pos += 1;
+                    self.parse_table()?;
+                }
+                Some("add") | Some("create") => {
+                    self.pos += 1;
+                    match self.next()?.as_str() {
+                        "set" => {
+                            let name = self.table_object()?;
+                            self.expect("{")?;
+                            self.parse_set(&name)?;
+                        }
+                        "element" => {
+                            let line = self.line();
+                            let name = self.table_object()?;
+                            self.expect("{")?;
+                            let set = self.sets.iter().position(|s| s.name == name);
+                            let set = set.ok_or_else(|| {
+                                ParseError::at(line, format!("unknown set {}", name))
+                            })?;
+                            self.parse_elements(set)?;
+                        }
+                        _ => self.skip_statement()?,
+                    }
+                }
+                Some("}") => return Err(ParseError::at(self.line(), "unexpected }")),
+                Some(_) => self.skip_statement()?,
+            }
+        }
+    }
+
+    // "[family] table name", returns the name.
+    fn table_object(&mut self) -> Result<String, ParseError> {
+        let mut words = vec![];
+        while self.peek().is_some() && self.peek() != Some("{") {
+            words.push(self.next()?);
+        }
+        match words.last() {
+            Some(name) if words.len() >= 2 => Ok(name.clone()),
+            _ => Err(ParseError::at(
+                self.line(),
+                "expected a table and a set name",
+            )),
+        }
+    }
+
+    fn parse_table(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Some("}") => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some("set") => {
+                    self.pos += 1;
+                    let name = self.next()?;
+                    self.expect("{")?;
+                    self.parse_set(&name)?;
+                }
+                Some(_) => self.skip_statement()?,
+                None => return Err(ParseError::at(self.line(), "unterminated table")),
+            }
+        }
+    }
+
+    // the body of a set after its "{".
+    fn parse_set(&mut self, name: &str) -> Result<(), ParseError> {
+        let start = self.line();
+        let mut family = None;
+        let mut timeout = None;
+        let mut elements = None;
+        loop {
+            self.skip_newlines();
+            let line = self.line();
+            match self.next()?.as_str() {
+                "}" => break,
+                "type" | "typeof" => {
+                    family = match self.next()?.as_str() {
+                        "ipv4_addr" | "ip" => Some(SetFamily::Inet),
+                        "ipv6_addr" | "ip6" => Some(SetFamily::Inet6),
+                        t => {
+                            return Err(ParseError::at(line, format!("unsupported set type {}", t)))
+                        }
+                    };
+                    self.skip_statement()?;
+                }
+                "timeout" => timeout = Some(parse_duration(&self.next()?, line)?),
+                "elements" => {
+                    self.expect("=")?;
+                    self.expect("{")?;
+                    elements = Some(self.pos);
+                    self.pos -= 1;
+                    self.skip_statement()?;
+                }
+                _ => self.skip_statement()?,
+            }
+        }
+        let family =
+            family.ok_or_else(|| ParseError::at(start, format!("set {} has no type", name)))?;
+        let mut set = IpSet::new(name, family);
+        set.timeout = timeout;
+        self.sets.push(set);
+        if let Some(pos) = elements {
+            let end = self.pos;
+            self.pos = pos;
+            self.parse_elements(self.sets.len() - 1)?;
+            self.pos = end;
+        }
+        Ok(())
+    }
+
+    // comma separated elements after a "{", up to the closing "}".
+    fn parse_elements(&mut self, set: usize) -> Result<(), ParseError> {
+        loop {
+            self.skip_newlines();
+            let line = self.line();
+            let element = self.next()?;
+            if element == "}" {
+                return Ok(());
+            }
+            let cidrs = match element.find('-') {
+                // an interval, "10.0.0.1-10.0.0.5".
+                Some(idx) => {
+                    let parse = |s: &str| {
+                        s.parse()
+                            .map_err(|_| ParseError::at(line, format!("invalid address {:?}", s)))
+                    };
+                    Cidr::range(parse(&element[..idx])?, parse(&element[idx + 1..])?)
+                        .map_err(|e| ParseError::at(line, e))?
+                }
+                None => vec![element.parse().map_err(|e| ParseError::at(line, e))?],
+            };
+            let mut timeout = None;
+            loop {
+                self.skip_newlines();
+                let line = self.line();
+                match self.next()?.as_str() {
+                    "," => break,
+                    "}" => {
+                        self.pos -= 1;
+                        break;
+                    }
+                    "timeout" => timeout = Some(parse_duration(&self.next()?, line)?),
+                    "expires" | "comment" => {
+                        self.next()?;
+                    }
+                    t => return Err(ParseError::at(line, format!("unexpected {:?}", t))),
+                }
+            }
+            for cidr in cidrs {
+                add_entry(&mut self.sets[set], cidr, timeout, line)?;
+            }
+        }
+    }
+}
+
+// Read the sets of `nft list ruleset` or `nft -f` input, either set blocks in
+// a table or "add set" and "add element" commands. Intervals are split into
+// cidrs, the rest of the ruleset is skipped.
+pub fn read_nft<R: BufRead>(mut reader: R) -> Result<Vec<IpSet>, ParseError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let mut parser = NftParser {
+        tokens: tokenize(&text),
+        pos: 0,
+        sets: vec![],
+    };
+    parser.parse()?;
+    Ok(parser.sets)
+}
+
+#[test]
+fn test_ipset() {
+    let save = "create chnroute hash:net family inet hashsize 1024 maxelem 65536 timeout 300\n\
+                add chnroute 1.0.1.0/24\n\
+                add chnroute 1.0.0.0/24 timeout 0\n\
+                add chnroute 1.0.2.0/23 timeout 120\n\
+                create chnroute6 hash:net family inet6 hashsize 1024 maxelem 65536\n\
+                add chnroute6 2400:da00::/32\n";
+    let sets = read_ipset(save.as_bytes()).unwrap();
+    assert_eq!(sets.len(), 2);
+    assert_eq!(sets[0].timeout, Some(300));
+    assert_eq!(sets[0].entries[1].timeout, Some(0));
+    assert_eq!(sets[1].family, SetFamily::Inet6);
+    let trie = sets[0].to_dual_stack();
+    assert_eq!(trie.get_with_value("1.0.3.1".parse().unwrap()), "chnroute");
+
+    let mut out = vec![];
+    sets[0].write_ipset(&mut out).unwrap();
+    assert_eq!(read_ipset(out.as_slice()).unwrap()[0], sets[0]);
+
+    let mut set = sets[0].clone();
+    set.add("1.0.0.0/24".parse().unwrap(), None);
+    assert!(!set.add("2400::/16".parse().unwrap(), None));
+    set.aggregate();
+    let mut out = vec![];
+    set.write_ipset(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "create chnroute hash:net family inet hashsize 1024 maxelem 65536 timeout 300\n\
+         add chnroute 1.0.0.0/24 timeout 0\n\
+         add chnroute 1.0.1.0/24\n\
+         add chnroute 1.0.2.0/23 timeout 120\n"
+    );
+
+    // the longer timeout wins, sets without one get "timeout 0".
+    let mut set = IpSet::new("s", SetFamily::Inet);
+    set.add("10.0.0.0/8".parse().unwrap(), Some(60));
+    set.add("10.1.0.0/16".parse().unwrap(), Some(600));
+    set.add("10.1.2.0/24".parse().unwrap(), None);
+    set.add("10.1.3.0/24".parse().unwrap(), Some(60));
+    set.aggregate();
+    let cidrs: Vec<(String, Option<u32>)> = set
+        .entries
+        .iter()
+        .map(|e| (e.cidr.to_string(), e.timeout))
+        .collect();
+    assert_eq!(cidrs.len(), 17);
+    assert_eq!(cidrs[0], ("10.0.0.0/16".to_string(), Some(60)));
+    assert!(cidrs.contains(&("10.1.2.0/24".to_string(), None)));
+    assert!(cidrs.contains(&("10.1.0.0/23".to_string(), Some(600))));
+    assert!(cidrs.contains(&("10.1.3.0/24".to_string(), Some(600))));
+    let mut out = vec![];
+    set.write_ipset(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text.starts_with("create s hash:net family inet hashsize 1024 maxelem 65536 timeout 0\n")
+    );
+    assert_eq!(read_ipset(text.as_bytes()).unwrap()[0].entries, set.entries);
+
+    // nested prefixes are not written as overlapping intervals.
+    let mut set = IpSet::new("s", SetFamily::Inet);
+    set.add("10.0.0.0/8".parse().unwrap(), None);
+    set.add("10.1.0.0/16".parse().unwrap(), None);
+    let mut out = vec![];
+    set.write_nft(&mut out, "ip", "t").unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "add set ip t s { type ipv4_addr; flags interval; }\n\
+         add element ip t s {\n\
+         \t10.0.0.0/8\n\
+         }\n"
+    );
+
+    let nft = "table inet filter {\n\
+               \tset chnroute {\n\
+               \t\ttype ipv4_addr\n\
+               \t\tflags interval,timeout\n\
+               \t\ttimeout 1h\n\
+               \t\telements = { 1.0.1.0/24, 1.0.2.0/23 timeout 1m30s expires 10s,\n\
+               \t\t\t     10.0.0.1-10.0.0.6 comment \"lab\" }\n\
+               \t}\n\
+               \tchain output {\n\
+               \t\ttype filter hook output priority 0; policy accept;\n\
+               \t\tip daddr @chnroute accept\n\
+               \t}\n\
+               }\n\
+               add set inet filter chnroute6 { type ipv6_addr; flags interval; }\n\
+               add element inet filter chnroute6 { 2400:da00::/32 }\n";
+    let sets = read_nft(nft.as_bytes()).unwrap();
+    assert_eq!(sets.len(), 2);
+    assert_eq!(sets[0].timeout, Some(3600));
+    assert_eq!(sets[0].entries.len(), 6);
+    assert_eq!(sets[0].entries[1].timeout, Some(90));
+    assert_eq!(sets[0].entries[5].cidr, "10.0.0.6/32".parse().unwrap());
+    assert_eq!(sets[1].entries[0].cidr, "2400:da00::/32".parse().unwrap());
+
+    let mut out = vec![];
+    sets[0].write_nft(&mut out, "inet", "filter").unwrap();
+    sets[1].write_nft(&mut out, "inet", "filter").unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with(
+        "add set inet filter chnroute { type ipv4_addr; flags interval,timeout; timeout 3600s; }\n\
+         add element inet filter chnroute {\n\
+         \t1.0.1.0/24,\n\
+         \t1.0.2.0/23 timeout 90s,\n"
+    ));
+    assert_eq!(read_nft(text.as_bytes()).unwrap(), sets);
+
+    let mut trie = DualStackTrie::new();
+    trie.put("1.0.1.0/24".parse().unwrap(), "CN".to_string());
+    trie.put("2400:da00::/32".parse().unwrap(), "CN".to_string());
+    let set = IpSet::from_trie("cn6", SetFamily::Inet6, &trie, true);
+    let mut out = vec![];
+    set.write_nft(&mut out, "ip6", "proxy").unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "add set ip6 proxy cn6 { type ipv6_addr; flags interval; }\n\
+         add element ip6 proxy cn6 {\n\
+         \t2400:da00::/32\n\
+         }\n"
+    );
+
+    let mut trie = DualStackTrie::new();
+    for cidr in ["1.0.0.0/24", "1.0.1.0/24", "1.0.1.128/25"].iter() {
+        trie.put(cidr.parse().unwrap(), "CN".to_string());
+    }
+    let write = |set: IpSet| {
+        let mut out = vec![];
+        set.write_ipset(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(
+        write(IpSet::from_trie("cn", SetFamily::Inet, &trie, false)),
+        "create cn hash:net family inet hashsize 1024 maxelem 65536\n\
+         add cn 1.0.0.0/24\n\
+         add cn 1.0.1.0/24\n\
+         add cn 1.0.1.128/25\n"
+    );
+    assert_eq!(
+        write(IpSet::from_trie("cn", SetFamily::Inet, &trie, true)),
+        "create cn hash:net family inet hashsize 1024 maxelem 65536\n\
+         add cn 1.0.0.0/23\n"
+    );
+
+    // nomatch excepts 10.1.0.0/16 from 10.0.0.0/8, the more specific
+    // 10.1.2.0/24 is matched again.
+    let save = "create s hash:net family inet hashsize 1024 maxelem 65536 timeout 300\n\
+                add s 10.0.0.0/8 timeout 60\n\
+                add s 10.1.0.0/16 nomatch\n\
+                add s 10.1.2.0/24\n";
+    let sets = read_ipset(save.as_bytes()).unwrap();
+    let trie = sets[0].to_dual_stack();
+    assert!(trie.get("10.0.0.1".parse().unwrap()));
+    assert!(!trie.get("10.1.0.1".parse().unwrap()));
+    assert!(!trie.get("10.1.3.1".parse().unwrap()));
+    assert!(trie.get("10.1.2.1".parse().unwrap()));
+    assert!(trie.get("10.2.0.1".parse().unwrap()));
+    assert!(sets[0]
+        .entries
+        .iter()
+        .all(|e| e.timeout == Some(60) || e.cidr == "10.1.2.0/24".parse().unwrap()));
+    match read_ipset("create s hash:net\nadd s 2400::/16 nomatch\n".as_bytes()) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "line 2: 2400::/16 does not match the family of set s"
+        ),
+        _ => panic!("expected a family mismatch"),
+    }
+
+    match read_ipset("add missing 1.0.0.0/24\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: unknown set missing"),
+        _ => panic!("expected an unknown set"),
+    }
+    match read_ipset("create s hash:net family inet6\nadd s 1.0.0.0/24\n".as_bytes()) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "line 2: 1.0.0.0/24 does not match the family of set s"
+        ),
+        _ => panic!("expected a family mismatch"),
+    }
+    assert!(read_ipset("create s list:set\n".as_bytes()).is_err());
+    match read_nft(
+        "table ip t {\n set s {\n type ipv4_addr\n elements = { 1.0.0.0/33 }\n }\n}\n".as_bytes(),
+    ) {
+        Err(e) => assert_eq!(e.to_string(), "line 4: invalid prefix length 33"),
+        _ => panic!("expected an invalid element"),
+    }
+    match read_nft("}\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: unexpected }"),
+        _ => panic!("expected an unbalanced brace"),
+    }
+    match read_nft("add rule ip t c ip daddr 1.0.0.0/24 accept }\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: unexpected }"),
+        _ => panic!("expected an unbalanced brace"),
+    }
+}
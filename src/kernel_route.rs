@@ -0,0 +1,542 @@
+use crate::cidr::Cidr;
+use crate::multi_table::MultiTable;
+use crate::text_list::ParseError;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// The kernel route types, as `ip route` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteKind {
+    Unicast,
+    Local,
+    Broadcast,
+    Anycast,
+    Multicast,
+    Blackhole,
+    Unreachable,
+    Prohibit,
+    Throw,
+    Nat,
+}
+
+impl RouteKind {
+    fn from_name(name: &str) -> Option<RouteKind> {
+        Some(match name {
+            "unicast" => RouteKind::Unicast,
+            "local" => RouteKind::Local,
+            "broadcast" => RouteKind::Broadcast,
+            "anycast" => RouteKind::Anycast,
+            "multicast" => RouteKind::Multicast,
+            "blackhole" => RouteKind::Blackhole,
+            "unreachable" => RouteKind::Unreachable,
+            "prohibit" => RouteKind::Prohibit,
+            "throw" => RouteKind::Throw,
+            "nat" => RouteKind::Nat,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelRoute {
+    pub kind: RouteKind,
+    pub gateway: Option<IpAddr>,
+    pub device: Option<String>,
+    pub metric: u32,
+    pub table: String,
+    // preferred source address, "src" of `ip route`.
+    pub source: Option<IpAddr>,
+}
+
+impl KernelRoute {
+    fn new(kind: RouteKind) -> KernelRoute {
+        KernelRoute {
+            kind,
+            gateway: None,
+            device: None,
+            metric: 0,
+            table: "main".to_string(),
+            source: None,
+        }
+    }
+}
+
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+const RTF_REJECT: u32 = 0x0200;
+const RTF_LOCAL: u32 = 0x8000_0000;
+
+fn hex_u32(value: &str, line: usize) -> Result<u32, ParseError> {
+    u32::from_str_radix(value, 16)
+        .map_err(|_| ParseError::at(line, format!("invalid hex {:?}", value)))
+}
+
+fn device(name: &str) -> Option<String> {
+    match name {
+        "*" | "" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+// Read /proc/net/route, the IPv4 main table. The kernel prints addresses in
+// host byte order, this reads the little endian layout of x86 and arm hosts.
+// Routes that are not up are skipped. The procfs flags cannot tell the
+// reject types apart, so they become Unreachable, and a route without a
+// device and without the reject flag is a Blackhole.
+pub fn read_proc_route<R: BufRead>(reader: R) -> Result<Vec<(Cidr, KernelRoute)>, ParseError> {
+    let mut routes = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0] == "Iface" {
+            continue;
+        }
+        if fields.len() < 8 {
+            return Err(ParseError::at(line_no, "expected at least 8 fields"));
+        }
+        let addr = |v: &str| -> Result<Ipv4Addr, ParseError> {
+            Ok(Ipv4Addr::from(hex_u32(v, line_no)?.to_le_bytes()))
+        };
+        let flags = hex_u32(fields[3], line_no)?;
+        if flags & RTF_UP == 0 {
+            continue;
+        }
+        let mask = u32::from(addr(fields[7])?);
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            return Err(ParseError::at(
+                line_no,
+                format!("invalid netmask {}", Ipv4Addr::from(mask)),
+            ));
+        }
+        let cidr = Cidr::new(IpAddr::V4(addr(fields[1])?), mask.leading_ones() as u8)
+            .map_err(|e| ParseError::at(line_no, e))?;
+        let kind = if flags & RTF_REJECT != 0 {
+            RouteKind::Unreachable
+        } else if fields[0] == "*" {
+            RouteKind::Blackhole
+        } else {
+            RouteKind::Unicast
+        };
+        let mut route = KernelRoute::new(kind);
+        if flags & RTF_GATEWAY != 0 {
+            route.gateway = Some(IpAddr::V4(addr(fields[2])?));
+        }
+        route.device = device(fields[0]);
+        route.metric = fields[6]
+            .parse()
+            .map_err(|_| ParseError::at(line_no, format!("invalid metric {:?}", fields[6])))?;
+        routes.push((cidr, route));
+    }
+    Ok(routes)
+}
+
+// Read /proc/net/ipv6_route. It lists every table without naming them, the
+// routes flagged local are put in "local" and the rest in "main".
+pub fn read_proc_ipv6_route<R: BufRead>(reader: R) -> Result<Vec<(Cidr, KernelRoute)>, ParseError> {
+    let mut routes = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() < 10 {
+            return Err(ParseError::at(line_no, "expected 10 fields"));
+        }
+        let addr = |v: &str| -> Result<Ipv6Addr, ParseError> {
+            u128::from_str_radix(v, 16)
+                .map(Ipv6Addr::from)
+                .map_err(|_| ParseError::at(line_no, format!("invalid hex {:?}", v)))
+        };
+        let flags = hex_u32(fields[8], line_no)?;
+        // reject routes, such as the default unreachable one, are not up.
+        if flags & (RTF_UP | RTF_REJECT) == 0 {
+            continue;
+        }
+        let prefix = hex_u32(fields[1], line_no)?;
+        if prefix > 128 {
+            return Err(ParseError::at(
+                line_no,
+                format!("invalid prefix length {}", prefix),
+            ));
+        }
+        let cidr = Cidr::new(IpAddr::V6(addr(fields[0])?), prefix as u8)
+            .map_err(|e| ParseError::at(line_no, e))?;
+        let kind = if flags & RTF_REJECT != 0 {
+            RouteKind::Unreachable
+        } else if flags & RTF_LOCAL != 0 {
+            RouteKind::Local
+        } else {
+            RouteKind::Unicast
+        };
+        let mut route = KernelRoute::new(kind);
+        if kind == RouteKind::Local {
+            route.table = "local".to_string();
+        }
+        if flags & RTF_GATEWAY != 0 {
+            route.gateway = Some(IpAddr::V6(addr(fields[4])?));
+        }
+        route.device = device(fields[9]);
+        route.metric = hex_u32(fields[5], line_no)?;
+        routes.push((cidr, route));
+    }
+    Ok(routes)
+}
+
+// `ip route` flags which take no value.
+const IP_ROUTE_FLAGS: &[&str] = &[
+    "onlink",
+    "linkdown",
+    "dead",
+    "offload",
+    "trap",
+    "pervasive",
+    "notify",
+    "rt_offload",
+    "rt_trap",
+    "rt_offload_failed",
+];
+
+// Fill route from the attributes of an `ip route` line or nexthop.
+fn parse_attributes(
+    words: &[&str],
+    route: &mut KernelRoute,
+    line: usize,
+) -> Result<(), ParseError> {
+    let parse_addr = |v: &str| -> Result<IpAddr, ParseError> {
+        v.parse()
+            .map_err(|_| ParseError::at(line, format!("invalid address {:?}", v)))
+    };
+    let mut iter = words.iter().copied();
+    while let Some(word) = iter.next() {
+        if IP_ROUTE_FLAGS.contains(&word) {
+            continue;
+        }
+        let mut value = iter
+            .next()
+            .ok_or_else(|| ParseError::at(line, format!("missing value of {}", word)))?;
+        match word {
+            "via" => {
+                // "via inet6 fe80::1" for an IPv4 route through an IPv6 gateway.
+                if value == "inet" || value == "inet6" {
+                    value = iter.next().unwrap_or_default();
+                }
+                route.gateway = Some(parse_addr(value)?);
+            }
+            "dev" => route.device = Some(value.to_string()),
+            "metric" => {
+                route.metric = value
+                    .parse()
+                    .map_err(|_| ParseError::at(line, format!("invalid metric {:?}", value)))?
+            }
+            "table" => route.table = value.to_string(),
+            "src" => route.source = Some(parse_addr(value)?),
+            _ => {
+                // "mtu lock 1400" and the like.
+                if value == "lock" {
+                    iter.next();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The family of a "default" destination from the markers `ip` prints: the
+// "pref" attribute is IPv6 only, and a gateway of the other family is written
+// "via inet6 <gw>" on an IPv4 route and "via inet <gw>" on an IPv6 one. None
+// when the line has neither.
+fn default_is_ipv6(attributes: &[&str]) -> Option<bool> {
+    if let Some(idx) = attributes.iter().position(|w| *w == "via") {
+        match attributes.get(idx + 1) {
+            Some(&"inet6") => return Some(false),
+            Some(&"inet") => return Some(true),
+            _ => {}
+        }
+    }
+    if attributes.contains(&"pref") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+// Read `ip route show table all` output, or any `ip [-4|-6] route show`. A
+// "default" without a family marker (see default_is_ipv6) takes the family
+// of the route lines around it, `ip` lists one family after the other, and
+// is IPv4 when there are none. Multipath routes give one entry per nexthop.
+pub fn read_ip_route<R: BufRead>(reader: R) -> Result<Vec<(Cidr, KernelRoute)>, ParseError> {
+    let mut routes: Vec<(Cidr, KernelRoute)> = vec![];
+    // per route whether it is a default of unknown family.
+    let mut unknown: Vec<bool> = vec![];
+    // the multipath route the following nexthop lines belong to, and whether
+    // its first nexthop was seen.
+    let mut multipath: Option<(Cidr, KernelRoute, bool)> = None;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words[0] == "nexthop" {
+            let (cidr, route, seen) = match multipath.as_mut() {
+                Some(m) => m,
+                None => return Err(ParseError::at(line_no, "nexthop without a route")),
+            };
+            let mut hop = route.clone();
+            parse_attributes(&words[1..], &mut hop, line_no)?;
+            if *seen {
+                routes.push((*cidr, hop));
+                unknown.push(unknown[unknown.len() - 1]);
+            } else {
+                *routes.last_mut().unwrap() = (*cidr, hop);
+                *seen = true;
+            }
+            continue;
+        }
+        let (kind, rest) = match RouteKind::from_name(words[0]) {
+            Some(kind) => (kind, &words[1..]),
+            None => (RouteKind::Unicast, &words[..]),
+        };
+        let (dest, attributes) = match rest.split_first() {
+            Some((dest, attributes)) => (*dest, attributes),
+            None => return Err(ParseError::at(line_no, "missing destination")),
+        };
+        let mut route = KernelRoute::new(kind);
+        parse_attributes(attributes, &mut route, line_no)?;
+        let cidr: Cidr = if dest == "default" {
+            let ipv6 = default_is_ipv6(attributes);
+            unknown.push(ipv6.is_none());
+            if ipv6 == Some(true) {
+                Cidr::V6(0, 0)
+            } else {
+                Cidr::V4(0, 0)
+            }
+        } else {
+            unknown.push(false);
+            dest.parse().map_err(|e| ParseError::at(line_no, e))?
+        };
+        multipath =
+            if route.gateway.is_none() && route.device.is_none() && kind == RouteKind::Unicast {
+                Some((cidr, route.clone(), false))
+            } else {
+                None
+            };
+        routes.push((cidr, route));
+    }
+    for idx in 0..routes.len() {
+        if !unknown[idx] {
+            continue;
+        }
+        // the closest known route before, or else after, this one.
+        let known = (0..idx)
+            .rev()
+            .chain(idx + 1..routes.len())
+            .find(|i| !unknown[*i]);
+        if let Some(i) = known {
+            if !routes[i].0.is_ipv4() {
+                routes[idx].0 = Cidr::V6(0, 0);
+            }
+        }
+    }
+    Ok(routes)
+}
+
+// Kernel routing tables, answering which route a destination would take.
+pub struct RoutingTable {
+    tables: MultiTable<KernelRoute>,
+}
+
+impl RoutingTable {
+    // Routes of the same prefix and table keep the lowest metric, the first
+    // one read on a tie, as the kernel does.
+    pub fn from_routes<I: IntoIterator<Item = (Cidr, KernelRoute)>>(routes: I) -> RoutingTable {
+        let mut best: BTreeMap<(String, Cidr), KernelRoute> = BTreeMap::new();
+        for (cidr, route) in routes {
+            let key = (route.table.clone(), cidr);
+            match best.get(&key) {
+                Some(r) if r.metric <= route.metric => {}
+                _ => {
+                    best.insert(key, route);
+                }
+            }
+        }
+        let mut tables = MultiTable::new();
+        for ((name, cidr), route) in best {
+            tables.put(&name, cidr, route);
+        }
+        RoutingTable { tables }
+    }
+
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.tables.table_names()
+    }
+
+    // longest prefix match in one table.
+    pub fn lookup_table(&self, table: &str, ip: IpAddr) -> Option<&KernelRoute> {
+        self.tables.lookup_table(table, ip)
+    }
+
+    // The route the kernel would pick with the default rules: the local, main
+    // and default tables in turn, a throw route moving on to the next one.
+    pub fn lookup(&self, ip: IpAddr) -> Option<&KernelRoute> {
+        for table in self.tables.chain() {
+            match self.lookup_table(table, ip) {
+                Some(route) if route.kind == RouteKind::Throw => {}
+                Some(route) => return Some(route),
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_kernel_route() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    let routes = read_proc_route(&include_bytes!("../tests/fixtures/proc_net_route")[..]).unwrap();
+    assert_eq!(routes.len(), 9);
+    assert_eq!(routes[3].0, "10.0.0.5/32".parse().unwrap());
+    let table = RoutingTable::from_routes(routes);
+    let route = table.lookup(ip("8.8.8.8")).unwrap();
+    assert_eq!(route.gateway, Some(ip("192.168.1.1")));
+    assert_eq!(route.device.as_deref(), Some("eth0"));
+    assert_eq!(route.metric, 100);
+    assert_eq!(
+        table.lookup(ip("10.1.2.3")).unwrap().device.as_deref(),
+        Some("tun0")
+    );
+    assert_eq!(table.lookup(ip("10.0.0.5")).unwrap().gateway, None);
+    assert_eq!(
+        table.lookup(ip("10.10.0.1")).unwrap().kind,
+        RouteKind::Blackhole
+    );
+    assert_eq!(
+        table.lookup(ip("10.11.0.1")).unwrap().kind,
+        RouteKind::Unreachable
+    );
+    assert_eq!(
+        table.lookup(ip("192.168.2.9")).unwrap().device.as_deref(),
+        Some("wlan0")
+    );
+    // 192.168.0.0/16 on eth1 is down.
+    assert_eq!(
+        table.lookup(ip("192.168.3.1")).unwrap().gateway,
+        Some(ip("192.168.1.1"))
+    );
+
+    let routes =
+        read_proc_ipv6_route(&include_bytes!("../tests/fixtures/proc_net_ipv6_route")[..]).unwrap();
+    assert_eq!(routes.len(), 7);
+    let table = RoutingTable::from_routes(routes);
+    let route = table.lookup(ip("2001:db8::10")).unwrap();
+    assert_eq!(
+        (route.kind, route.table.as_str()),
+        (RouteKind::Local, "local")
+    );
+    assert_eq!(table.lookup(ip("2001:db8::11")).unwrap().metric, 100);
+    let route = table.lookup(ip("2606:4700::1111")).unwrap();
+    assert_eq!(route.gateway, Some(ip("fe80::2")));
+    assert_eq!(route.device.as_deref(), Some("wlan0"));
+
+    let routes =
+        read_ip_route(&include_bytes!("../tests/fixtures/ip_route_show_table_all")[..]).unwrap();
+    assert_eq!(routes.len(), 28);
+    let multipath: Vec<_> = routes
+        .iter()
+        .filter(|(c, _)| *c == "172.16.0.0/12".parse().unwrap())
+        .map(|(_, r)| (r.gateway.unwrap(), r.metric))
+        .collect();
+    assert_eq!(
+        multipath,
+        vec![(ip("192.168.1.2"), 10), (ip("192.168.2.2"), 10)]
+    );
+    let table = RoutingTable::from_routes(routes);
+    assert_eq!(
+        table.table_names().collect::<Vec<_>>(),
+        vec!["100", "local", "main", "vpn"]
+    );
+    let route = table.lookup(ip("1.1.1.1")).unwrap();
+    assert_eq!(route.gateway, Some(ip("192.168.1.1")));
+    assert_eq!(route.source, Some(ip("192.168.1.10")));
+    assert_eq!(
+        table.lookup(ip("192.168.1.10")).unwrap().kind,
+        RouteKind::Local
+    );
+    assert_eq!(
+        table.lookup(ip("192.168.1.255")).unwrap().kind,
+        RouteKind::Broadcast
+    );
+    assert_eq!(
+        table.lookup(ip("127.3.4.5")).unwrap().device.as_deref(),
+        Some("lo")
+    );
+    assert_eq!(
+        table.lookup(ip("172.20.0.1")).unwrap().device.as_deref(),
+        Some("eth0")
+    );
+    assert_eq!(
+        table.lookup(ip("10.12.0.1")).unwrap().kind,
+        RouteKind::Prohibit
+    );
+    // thrown out of main, and there is no default table.
+    assert_eq!(table.lookup(ip("10.13.0.1")), None);
+    assert_eq!(
+        table.lookup_table("100", ip("10.13.0.1")).unwrap().gateway,
+        Some(ip("192.168.1.254"))
+    );
+    assert_eq!(
+        table
+            .lookup_table("vpn", ip("9.9.9.9"))
+            .unwrap()
+            .device
+            .as_deref(),
+        Some("tun0")
+    );
+    let route = table.lookup(ip("2606:4700::1111")).unwrap();
+    assert_eq!((route.gateway, route.metric), (Some(ip("fe80::2")), 600));
+    assert_eq!(table.lookup(ip("::1")).unwrap().kind, RouteKind::Local);
+    assert_eq!(
+        table.lookup(ip("ff02::1")).unwrap().kind,
+        RouteKind::Multicast
+    );
+
+    match read_ip_route("10.0.0.0/8 via 10.0.0.300\n".as_bytes()) {
+        Err(e) => assert_eq!(e.to_string(), "line 1: invalid address \"10.0.0.300\""),
+        _ => panic!("expected an invalid gateway"),
+    }
+    assert!(read_ip_route("\tnexthop via 10.0.0.1\n".as_bytes()).is_err());
+
+    // the family of a default route does not follow its gateway.
+    let cidrs = |text: &str| -> Vec<String> {
+        read_ip_route(text.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|(c, _)| c.to_string())
+            .collect()
+    };
+    assert_eq!(
+        cidrs("default via inet6 fe80::1 dev eth0 proto bgp\n"),
+        vec!["0.0.0.0/0"]
+    );
+    assert_eq!(
+        cidrs("default via fe80::1 dev eth0 proto bgp\n10.0.0.0/8 dev eth0\n"),
+        vec!["0.0.0.0/0", "10.0.0.0/8"]
+    );
+    assert_eq!(
+        cidrs("2001:db8::/64 dev eth0\ndefault via 2001:db8::1 dev eth0\n"),
+        vec!["2001:db8::/64", "::/0"]
+    );
+    assert_eq!(
+        cidrs("default dev eth0 metric 1024 pref medium\n"),
+        vec!["::/0"]
+    );
+    assert!(
+        read_proc_route("eth0\t0000000A\t00000000\t0001\t0\t0\t0\t00FF00FF\n".as_bytes()).is_err()
+    );
+}
@@ -1,8 +1,36 @@
 pub mod bit_vec;
+pub mod cidr;
 #[cfg(feature = "bs-matcher")]
 pub mod cidr_bs;
+pub mod csv_import;
+pub mod delegated;
+pub mod dir24;
+pub mod dual_stack;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 #[cfg(feature = "pb")]
 pub mod geoip;
+#[cfg(feature = "pb")]
+pub mod geoip_db;
+#[cfg(feature = "pb")]
+pub mod geoip_writer;
+pub mod ipset;
+pub mod kernel_route;
 pub mod lpc_trie;
+pub mod matcher;
+pub mod mmdb;
+pub mod mrt;
+pub mod multi_table;
+pub mod policy;
+pub mod poptrie;
+pub mod prefilter;
+#[cfg(feature = "python")]
+mod python;
+pub mod range_map;
+pub mod route_export;
+pub mod route_table;
 #[cfg(all(test, feature = "pb"))]
 mod test;
+#[cfg(test)]
+mod test_util;
+pub mod text_list;
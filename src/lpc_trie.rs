@@ -1,5 +1,11 @@
 use crate::bit_vec::BitVec;
+use crate::cidr::{complement_ranges, host_mask, range_to_prefixes, Cidr};
+use crate::matcher::IpMatcher;
 use deepsize::DeepSizeOf;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::net::IpAddr;
 
 #[derive(Debug, DeepSizeOf)]
 struct InternalNode<T> {
@@ -202,6 +208,83 @@ struct Leaf<T> {
     key: T,
     prefix: u8,
     value: usize,
+    // other prefixes of the same key, the chain is kept shortest first.
+    next: Option<Box<Leaf<T>>>,
+}
+
+impl<T: BitVec> Leaf<T> {
+    fn new(key: T, prefix: u8, value: usize) -> Leaf<T> {
+        Leaf {
+            key,
+            prefix,
+            value,
+            next: None,
+        }
+    }
+
+    #[inline]
+    fn matches(&self, key: T) -> bool {
+        // full length prefix
+        if self.prefix == (std::mem::size_of::<T>() * 8) as u8 {
+            self.key == key
+        } else {
+            self.key.sub_equal(0, self.prefix as u32, &key)
+        }
+    }
+
+    // the longest prefix of the chain covering key.
+    fn longest_match(&self, key: T) -> Option<&Leaf<T>> {
+        let mut best = None;
+        let mut l = Some(self);
+        while let Some(leaf) = l {
+            if leaf.matches(key) {
+                best = Some(leaf);
+            }
+            l = leaf.next.as_deref();
+        }
+        best
+    }
+
+    // add prefix to the chain, false when it is already there.
+    fn insert(&mut self, prefix: u8, value: usize) -> bool {
+        if prefix == self.prefix {
+            return false;
+        }
+        if prefix < self.prefix {
+            let old = std::mem::replace(self, Leaf::new(self.key, prefix, value));
+            self.next = Some(Box::new(old));
+            return true;
+        }
+        match &mut self.next {
+            Some(next) => next.insert(prefix, value),
+            None => {
+                self.next = Some(Box::new(Leaf::new(self.key, prefix, value)));
+                true
+            }
+        }
+    }
+
+    fn has_prefix(&self, prefix: u8) -> bool {
+        let mut l = Some(self);
+        while let Some(leaf) = l {
+            if leaf.prefix == prefix {
+                return true;
+            }
+            l = leaf.next.as_deref();
+        }
+        false
+    }
+
+    fn unlink(chain: Option<Box<Leaf<T>>>, prefix: u8) -> Option<Box<Leaf<T>>> {
+        match chain {
+            Some(mut l) if l.prefix == prefix => l.next.take(),
+            Some(mut l) => {
+                l.next = Leaf::unlink(l.next.take(), prefix);
+                Some(l)
+            }
+            None => None,
+        }
+    }
 }
 
 #[derive(Debug, DeepSizeOf)]
@@ -271,26 +354,159 @@ impl<T: BitVec> TrieNode<T> {
                 key: l.key,
                 prefix: l.prefix,
                 value: std::mem::take(&mut l.value),
+                next: l.next.take(),
             })),
         }
     }
 }
 
-#[derive(DeepSizeOf)]
-pub struct LPCTrie<T> {
+// key with the bits past prefix cleared, the form leaves store keys in.
+fn mask<T: BitVec>(key: T, prefix: u8) -> T {
+    let host = host_mask(T::bits().saturating_sub(prefix as u32));
+    T::from_u128(key.to_u128() & !host)
+}
+
+// Distinct values, each stored once and numbered in the order they came.
+// index maps value hashes to their positions in vec rather than holding a
+// second copy of each value. LPCTrie keeps its leaf values here, and the
+// tables compiled from a trie or holding ids in tries of their own reuse it.
+pub(crate) struct Values<V> {
+    vec: Vec<V>,
+    index: HashMap<u64, Vec<usize>>,
+    hasher: RandomState,
+}
+
+impl<V: DeepSizeOf> DeepSizeOf for Values<V> {
+    fn deep_size_of_children(&self, context: &mut deepsize::Context) -> usize {
+        self.vec.deep_size_of_children(context) + self.index.deep_size_of_children(context)
+    }
+}
+
+impl<V: Eq + Hash> Values<V> {
+    pub(crate) fn new() -> Values<V> {
+        Values {
+            vec: vec![],
+            index: HashMap::new(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    // the position of value, pushing it when it is new.
+    pub(crate) fn intern(&mut self, value: V) -> usize {
+        match self.find(&value) {
+            (_, Some(pos)) => pos,
+            (hash, None) => self.push(hash, value),
+        }
+    }
+
+    // the same for a borrowed value, cloned only when it is new.
+    pub(crate) fn intern_ref(&mut self, value: &V) -> usize
+    where
+        V: Clone,
+    {
+        match self.find(value) {
+            (_, Some(pos)) => pos,
+            (hash, None) => self.push(hash, value.clone()),
+        }
+    }
+
+    // the hash of value and its position if it is there.
+    fn find(&self, value: &V) -> (u64, Option<usize>) {
+        let hash = self.hasher.hash_one(value);
+        let pos = self
+            .index
+            .get(&hash)
+            .and_then(|positions| positions.iter().copied().find(|p| self.vec[*p] == *value));
+        (hash, pos)
+    }
+
+    fn push(&mut self, hash: u64, value: V) -> usize {
+        self.index.entry(hash).or_default().push(self.vec.len());
+        self.vec.push(value);
+        self.vec.len() - 1
+    }
+
+    pub(crate) fn get(&self, pos: usize) -> Option<&V> {
+        self.vec.get(pos)
+    }
+
+    pub(crate) fn as_slice(&self) -> &[V] {
+        &self.vec
+    }
+
+    // the values alone, for tables that are frozen once built.
+    pub(crate) fn into_vec(self) -> Vec<V> {
+        self.vec
+    }
+}
+
+impl<V> std::ops::Index<usize> for Values<V> {
+    type Output = V;
+
+    fn index(&self, pos: usize) -> &V {
+        &self.vec[pos]
+    }
+}
+
+// Leaves hold a position in values, so routes sharing a value (a country
+// code, a next hop) store it once.
+pub struct LPCTrie<T, V = String> {
     trie: TrieNode<T>,
     size: u32,
     key_found: bool,
-    vec: Vec<String>,
+    values: Values<V>,
 }
 
-impl<T: BitVec> LPCTrie<T> {
-    pub fn new() -> LPCTrie<T> {
+impl<T: DeepSizeOf, V: DeepSizeOf + Eq + Hash> DeepSizeOf for LPCTrie<T, V> {
+    fn deep_size_of_children(&self, context: &mut deepsize::Context) -> usize {
+        self.trie.deep_size_of_children(context) + self.values.deep_size_of_children(context)
+    }
+}
+
+impl<T: BitVec> LPCTrie<T, String> {
+    pub fn get_with_value(&self, key: T) -> &str {
+        match self.get_value(key) {
+            Some(v) => v.as_str(),
+            None => "",
+        }
+    }
+}
+
+// LPCTrie<u32> matches IPv4 addresses only, LPCTrie<u128> IPv6 ones. Built
+// tries hold V::default() for every cidr.
+impl<T: BitVec, V: Clone + Eq + Hash + Default> IpMatcher for LPCTrie<T, V> {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) if T::bits() == 32 => self.get(T::from_u128(u32::from(ip) as u128)),
+            IpAddr::V6(ip) if T::bits() == 128 => self.get(T::from_u128(ip.into())),
+            _ => false,
+        }
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        let mut trie = LPCTrie::new();
+        for cidr in cidrs {
+            match cidr {
+                Cidr::V4(addr, prefix) if T::bits() == 32 => {
+                    trie.put(T::from_u128(addr as u128), prefix, V::default())
+                }
+                Cidr::V6(addr, prefix) if T::bits() == 128 => {
+                    trie.put(T::from_u128(addr), prefix, V::default())
+                }
+                _ => {}
+            }
+        }
+        trie
+    }
+}
+
+impl<T: BitVec, V: Clone + Eq + Hash> LPCTrie<T, V> {
+    pub fn new() -> LPCTrie<T, V> {
         LPCTrie {
             trie: Default::default(),
             size: 0,
             key_found: false,
-            vec: vec![],
+            values: Values::new(),
         }
     }
 
@@ -299,16 +515,12 @@ impl<T: BitVec> LPCTrie<T> {
         self.size = 0;
     }
 
-    pub fn put(&mut self, key: T, prefix: u8, value: String) {
+    // key may have bits set past prefix, they are cleared.
+    pub fn put(&mut self, key: T, prefix: u8, value: V) {
+        let key = mask(key, prefix);
         self.key_found = false;
         let mut trie = std::mem::take(&mut self.trie);
-        let pos = if let Some(p) = self.vec.iter().position(|x| x == &value) {
-            p
-        } else {
-            let len = self.vec.len();
-            self.vec.push(value);
-            len
-        };
+        let pos = self.values.intern(value);
         let trie = self.insert_impl(key, prefix, pos, &mut trie, 0);
         self.trie = trie;
         if !self.key_found {
@@ -316,97 +528,141 @@ impl<T: BitVec> LPCTrie<T> {
         }
     }
 
-    pub fn get_with_value(&self, key: T) -> &str {
-        let mut t: Option<&TrieNode<T>> = Some(&self.trie);
+    // value of the longest prefix covering key.
+    pub fn get_value(&self, key: T) -> Option<&V> {
+        self.find_leaf(key).map(|l| &self.values[l.value])
+    }
+
+    pub fn get(&self, key: T) -> bool {
+        self.find_leaf(key).is_some()
+    }
+
+    fn find_leaf(&self, key: T) -> Option<&Leaf<T>> {
+        let mut t: &TrieNode<T> = &self.trie;
         loop {
-            if let Some(node) = t {
-                match node {
-                    TrieNode::NODE(n) => {
-                        t = Some(n.get_child(key.extract_bits(n.pos, n.bits).safe_to_usize()));
-                    }
-                    TrieNode::LEAF(l) => {
-                        // full length prefix
-                        return if l.prefix == (std::mem::size_of::<T>() * 8) as u8 && l.key == key {
-                            self.vec[l.value].as_str()
-                        } else if l.key.sub_equal(0, l.prefix as u32, &key) {
-                            self.vec[l.value].as_str()
-                        } else {
-                            ""
-                        };
-                    }
-                    TrieNode::NONE => return "",
+            match t {
+                TrieNode::NODE(n) => {
+                    t = n.get_child(key.extract_bits(n.pos, n.bits).safe_to_usize());
                 }
-            } else {
-                return "";
+                TrieNode::LEAF(l) => match l.longest_match(key) {
+                    Some(l) => return Some(l),
+                    None => break,
+                },
+                TrieNode::NONE => break,
             }
         }
+        // A shorter prefix covering key sits in a slot whose index has key's
+        // trailing bits cleared, since put stores keys masked to their
+        // prefix, so look there before giving up.
+        Self::backtrack(&self.trie, key)
     }
-    pub fn get(&self, key: T) -> bool {
-        let mut t: Option<&TrieNode<T>> = Some(&self.trie);
-        loop {
-            if let Some(node) = t {
-                match node {
-                    TrieNode::NODE(n) => {
-                        t = Some(n.get_child(key.extract_bits(n.pos, n.bits).safe_to_usize()));
-                    }
-                    TrieNode::LEAF(l) => {
-                        // full length prefix
-                        return if l.prefix == (std::mem::size_of::<T>() * 8) as u8 {
-                            l.key == key
-                        } else {
-                            l.key.sub_equal(0, l.prefix as u32, &key)
-                        };
+
+    fn backtrack(node: &TrieNode<T>, key: T) -> Option<&Leaf<T>> {
+        match node {
+            TrieNode::NODE(n) => {
+                let mut idx = key.extract_bits(n.pos, n.bits).safe_to_usize();
+                if let Some(l) = Self::backtrack(n.get_child(idx), key) {
+                    return Some(l);
+                }
+                while idx != 0 {
+                    // longest prefix first: clear the lowest set bit each round.
+                    idx &= idx - 1;
+                    let mut t = n.get_child(idx);
+                    // below a cleared slot only the all zero path may cover key.
+                    while let TrieNode::NODE(c) = t {
+                        t = c.get_child(0);
                     }
-                    TrieNode::NONE => {
-                        return false;
+                    if let TrieNode::LEAF(l) = t {
+                        if let Some(l) = l.longest_match(key) {
+                            return Some(l);
+                        }
                     }
                 }
-            } else {
-                return false;
+                None
             }
+            TrieNode::LEAF(l) => l.longest_match(key),
+            TrieNode::NONE => None,
         }
     }
 
+    // drop the longest prefix covering key, the one get_value finds.
     pub fn remove(&mut self, key: T) -> bool {
+        match self.find_leaf(key) {
+            Some(l) => {
+                let (key, prefix) = (l.key, l.prefix);
+                self.remove_prefix(key, prefix)
+            }
+            None => false,
+        }
+    }
+
+    // drop exactly key/prefix, shorter and longer prefixes of key stay.
+    pub fn remove_prefix(&mut self, key: T, prefix: u8) -> bool {
+        let key = mask(key, prefix);
         self.key_found = false;
         let mut trie = std::mem::take(&mut self.trie);
-        let trie = self.remove_impl(key, &mut trie);
-        self.trie = trie;
+        self.trie = self.remove_impl(key, prefix, &mut trie);
         if self.key_found {
             self.size -= 1;
-            return true;
-        } else {
-            return false;
         }
+        self.key_found
     }
 
     pub fn empty(&self) -> bool {
         self.size == 0
     }
 
-    fn remove_impl(&mut self, key: T, trie: &mut TrieNode<T>) -> TrieNode<T> {
-        return match trie {
+    // A trie covering exactly the addresses this one does not, every entry
+    // holding value. Used for reverse (geoip:!cn style) matching.
+    pub fn complement(&self, value: V) -> LPCTrie<T, V> {
+        let bits = T::bits();
+        let ranges = self
+            .iter()
+            .map(|(key, prefix, _)| {
+                let host = host_mask(bits - prefix as u32);
+                let first = key.to_u128() & !host & host_mask(bits);
+                (first, first | host)
+            })
+            .collect();
+        let mut trie = LPCTrie::new();
+        for (first, last) in complement_ranges(ranges, bits) {
+            for (key, prefix) in range_to_prefixes(first, last, bits) {
+                trie.put(T::from_u128(key), prefix, value.clone());
+            }
+        }
+        trie
+    }
+
+    // every stored (key, prefix, value) in ascending key order.
+    pub fn iter(&self) -> Iter<'_, T, V> {
+        Iter {
+            trie: self,
+            stack: vec![&self.trie],
+            chain: None,
+        }
+    }
+
+    fn remove_impl(&mut self, key: T, prefix: u8, trie: &mut TrieNode<T>) -> TrieNode<T> {
+        match trie {
             TrieNode::NODE(inode) => {
                 let bitpat = key.extract_bits(inode.pos, inode.bits).safe_to_usize();
-                let mut n = self.remove_impl(key, inode.get_mut_child(bitpat));
+                // work on a clone so put_child still sees the old child.
+                let mut child = inode.get_mut_child(bitpat).lightweight_clone();
+                let mut n = self.remove_impl(key, prefix, &mut child);
                 inode.put_child(bitpat, &mut n);
                 inode.resize()
             }
-            TrieNode::LEAF(l) => {
-                let result = if l.prefix == (std::mem::size_of::<T>() * 8) as u8 {
-                    l.key == key
-                } else {
-                    l.key.sub_equal(0, l.prefix as u32, &key)
-                };
-                if result {
-                    self.key_found = true;
-                    TrieNode::NONE
-                } else {
-                    std::mem::take(trie)
+            TrieNode::LEAF(l) if l.key == key && l.has_prefix(prefix) => {
+                self.key_found = true;
+                match std::mem::take(trie) {
+                    TrieNode::LEAF(l) => {
+                        Leaf::unlink(Some(l), prefix).map_or(TrieNode::NONE, TrieNode::LEAF)
+                    }
+                    node => node,
                 }
             }
-            TrieNode::NONE => TrieNode::NONE,
-        };
+            _ => std::mem::take(trie),
+        }
     }
 
     fn insert_impl(
@@ -434,13 +690,14 @@ impl<T: BitVec> LPCTrie<T> {
                 inode.resize()
             }
             TrieNode::LEAF(l) if key == l.key => {
-                self.key_found = true;
+                // the same prefix again keeps the first value.
+                self.key_found = !l.insert(prefix, value);
                 std::mem::take(trie)
             }
             TrieNode::NODE(_) | TrieNode::LEAF(_) => {
                 let new_pos = key.mismatch(pos, &trie.key());
                 let mut node = InternalNode::new(trie.key(), new_pos, 1);
-                let mut leaf = TrieNode::LEAF(Box::new(Leaf { key, prefix, value }));
+                let mut leaf = TrieNode::LEAF(Box::new(Leaf::new(key, prefix, value)));
                 if key.extract_bits(new_pos, 1).is_empty() {
                     node.put_child(0, &mut leaf);
                     node.put_child(1, trie);
@@ -450,11 +707,35 @@ impl<T: BitVec> LPCTrie<T> {
                 }
                 node.resize()
             }
-            TrieNode::NONE => TrieNode::LEAF(Box::new(Leaf { key, prefix, value })),
+            TrieNode::NONE => TrieNode::LEAF(Box::new(Leaf::new(key, prefix, value))),
         };
     }
 }
 
+pub struct Iter<'a, T, V> {
+    trie: &'a LPCTrie<T, V>,
+    stack: Vec<&'a TrieNode<T>>,
+    chain: Option<&'a Leaf<T>>,
+}
+
+impl<'a, T: BitVec, V> Iterator for Iter<'a, T, V> {
+    type Item = (T, u8, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut leaf = self.chain;
+        while leaf.is_none() {
+            match self.stack.pop()? {
+                TrieNode::NODE(n) => self.stack.extend(n.child.iter().rev()),
+                TrieNode::LEAF(l) => leaf = Some(l),
+                TrieNode::NONE => {}
+            }
+        }
+        let l = leaf?;
+        self.chain = l.next.as_deref();
+        Some((l.key, l.prefix, &self.trie.values[l.value]))
+    }
+}
+
 #[test]
 fn test_lpc_trie() {
     let mut trie = LPCTrie::new();
@@ -590,3 +871,190 @@ fn test_lpc_trie() {
     assert_eq!(trie.get(u128::from_bit_str("10011110")), true);
     assert_eq!(trie.get(u128::from_bit_str("10011100")), false);
 }
+
+#[test]
+fn test_lpc_nested_prefixes() {
+    // addresses inside a short prefix whose slot is shared with longer ones.
+    let mut trie = LPCTrie::<u32>::new();
+    let prefixes = [
+        (0x0000_0000, 4),
+        (0x1200_0000, 7),
+        (0x1400_0000, 6),
+        (0x1800_0000, 5),
+        (0x2000_0000, 3),
+        (0xe000_0000, 5),
+        (0xea00_0000, 7),
+        (0xf000_0000, 4),
+    ];
+    for (key, prefix) in prefixes.iter() {
+        trie.put(*key, *prefix, format!("{:x}/{}", key, prefix));
+    }
+    for (key, prefix) in prefixes.iter() {
+        let last = key | (u32::MAX >> prefix);
+        assert_eq!(trie.get_with_value(last), format!("{:x}/{}", key, prefix));
+    }
+    assert_eq!(trie.get(0x1000_0000), false);
+    assert_eq!(trie.get(0xe800_0000), false);
+}
+
+#[test]
+fn test_lpc_unmasked_keys() {
+    // the prefixes of test_lpc_nested_prefixes, put with their host bits set.
+    let mut trie = LPCTrie::<u32>::new();
+    let prefixes = [
+        (0x0000_0000, 4),
+        (0x1200_0000, 7),
+        (0x1400_0000, 6),
+        (0x1800_0000, 5),
+        (0x2000_0000, 3),
+        (0xe000_0000, 5),
+        (0xea00_0000, 7),
+        (0xf000_0000, 4),
+    ];
+    for (key, prefix) in prefixes.iter() {
+        trie.put(
+            key | (u32::MAX >> prefix),
+            *prefix,
+            format!("{:x}/{}", key, prefix),
+        );
+    }
+    trie.put(0x1201_0203, 7, "again".to_string());
+    assert_eq!(trie.size, prefixes.len() as u32);
+    for (key, prefix) in prefixes.iter() {
+        assert_eq!(trie.get_with_value(*key), format!("{:x}/{}", key, prefix));
+    }
+    let mut keys: Vec<(u32, u8)> = trie.iter().map(|(key, prefix, _)| (key, prefix)).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, prefixes.to_vec());
+    assert!(trie.remove_prefix(0xeaff_ffff, 7));
+    assert!(!trie.get(0xea00_0000));
+    assert!(!trie.remove_prefix(0xea00_0000, 7));
+}
+
+#[test]
+fn test_lpc_trie_iter() {
+    let mut trie = LPCTrie::new();
+    let bitvecs: Vec<u64> = vec![
+        u64::from_bit_str("00010000"),
+        u64::from_bit_str("01000010"),
+        u64::from_bit_str("00001010"),
+        u64::from_bit_str("00101011"),
+        u64::from_bit_str("10101101"),
+        u64::from_bit_str("10110110"),
+        u64::from_bit_str("11011011"),
+        u64::from_bit_str("01101110"),
+        u64::from_bit_str("10111010"),
+        u64::from_bit_str("11101001"),
+        u64::from_bit_str("10100111"),
+        u64::from_bit_str("10011110"),
+    ];
+    for bv in bitvecs.iter() {
+        trie.put(*bv, 64, "fake".to_string());
+    }
+    let mut sorted = bitvecs.clone();
+    sorted.sort_unstable();
+    assert_eq!(trie.iter().map(|(k, _, _)| k).collect::<Vec<_>>(), sorted);
+    assert!(trie.iter().all(|(_, p, v)| p == 64 && v == "fake"));
+}
+
+#[test]
+fn test_lpc_complement() {
+    let mut trie = LPCTrie::new();
+    let bitvecs: Vec<u128> = vec![
+        u128::from_bit_str("00010000"),
+        u128::from_bit_str("01000010"),
+        u128::from_bit_str("00001010"),
+        u128::from_bit_str("00101011"),
+        u128::from_bit_str("10101101"),
+        u128::from_bit_str("10110110"),
+        u128::from_bit_str("11011011"),
+        u128::from_bit_str("01101110"),
+        u128::from_bit_str("10111010"),
+        u128::from_bit_str("11101001"),
+        u128::from_bit_str("10100111"),
+        u128::from_bit_str("10011110"),
+    ];
+    for bv in bitvecs {
+        trie.put(bv, 7, "fake".to_string());
+    }
+    let complement = trie.complement("!fake".to_string());
+    assert_eq!(complement.get(u128::from_bit_str("00110000")), true);
+    assert_eq!(complement.get(u128::from_bit_str("10011100")), true);
+    assert_eq!(complement.get(u128::from_bit_str("10011110")), false);
+    assert_eq!(complement.get(u128::from_bit_str("0000101")), false);
+    assert_eq!(complement.get_with_value(u128::MAX), "!fake");
+    assert_eq!(complement.complement("fake".to_string()).iter().count(), 12);
+
+    let mut trie = LPCTrie::<u32>::new();
+    trie.put(0x0a00_0000, 8, "private".to_string());
+    let complement = trie.complement("public".to_string());
+    assert_eq!(complement.get(0x0a01_0203), false);
+    assert_eq!(complement.get(0x0b00_0000), true);
+    assert_eq!(complement.get(0), true);
+    assert_eq!(complement.get(u32::MAX), true);
+    assert_eq!(
+        LPCTrie::<u32>::new()
+            .complement("all".to_string())
+            .get(0x0808_0808),
+        true
+    );
+}
+
+#[test]
+fn test_lpc_shared_keys() {
+    // prefixes sharing a key are chained on one leaf.
+    let mut trie = LPCTrie::<u32, u32>::new();
+    trie.put(0x0a00_0000, 8, 8);
+    trie.put(0x0a00_0000, 24, 24);
+    trie.put(0x0a00_0000, 16, 16);
+    trie.put(0x0a00_0000, 16, 99);
+    trie.put(0x0b00_0000, 8, 11);
+    trie.put(0x0a80_0000, 9, 9);
+    assert_eq!(trie.get_value(0x0a00_0001), Some(&24));
+    assert_eq!(trie.get_value(0x0a00_0101), Some(&16));
+    assert_eq!(trie.get_value(0x0a01_0000), Some(&8));
+    assert_eq!(trie.get_value(0x0a80_0001), Some(&9));
+    assert_eq!(
+        trie.iter().map(|(_, p, v)| (p, *v)).collect::<Vec<_>>(),
+        vec![(8, 8), (16, 16), (24, 24), (9, 9), (8, 11)]
+    );
+}
+
+#[test]
+fn test_lpc_interned_values() {
+    let mut trie = LPCTrie::<u32>::new();
+    trie.put(0x0100_0000, 24, "CN".to_string());
+    trie.put(0x0800_0000, 8, "US".to_string());
+    trie.put(0x0200_0000, 16, "CN".to_string());
+    assert_eq!(trie.values.as_slice(), ["CN".to_string(), "US".to_string()]);
+    assert_eq!(
+        trie.values.index.values().map(|p| p.len()).sum::<usize>(),
+        2
+    );
+    assert_eq!(trie.get_with_value(0x0200_0101), "CN");
+}
+
+#[test]
+fn test_lpc_remove_prefix() {
+    let mut trie = LPCTrie::<u32, u32>::new();
+    trie.put(0x0a00_0000, 8, 8);
+    trie.put(0x0a00_0000, 24, 24);
+    trie.put(0x0a00_0000, 16, 16);
+    trie.put(0x0b00_0000, 8, 11);
+    trie.put(0x0a80_0000, 9, 9);
+    // misses must leave the node counters intact.
+    for _ in 0..4 {
+        assert_eq!(trie.remove(0x0c00_0000), false);
+    }
+    // remove drops the prefix get_value finds, remove_prefix an exact one.
+    assert_eq!(trie.remove(0x0a00_0001), true);
+    assert_eq!(trie.get_value(0x0a00_0001), Some(&16));
+    assert_eq!(trie.remove_prefix(0x0a00_0000, 24), false);
+    assert_eq!(trie.remove_prefix(0x0a00_0000, 8), true);
+    assert_eq!(trie.get_value(0x0a01_0000), None);
+    assert_eq!(trie.remove(0x0a80_ffff), true);
+    assert_eq!(trie.remove(0x0a00_0001), true);
+    assert_eq!(trie.remove(0x0a00_0001), false);
+    assert_eq!(trie.remove(0x0b00_0001), true);
+    assert_eq!(trie.empty(), true);
+}
@@ -0,0 +1,57 @@
+use crate::cidr::Cidr;
+use std::net::IpAddr;
+
+// A set of cidrs answering whether an address falls in one of them, so
+// callers can pick a lookup engine per rule set.
+pub trait IpMatcher {
+    fn contains(&self, ip: IpAddr) -> bool;
+
+    // cidrs of a family the matcher does not hold are left out.
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self
+    where
+        Self: Sized;
+}
+
+#[test]
+fn test_ip_matcher() {
+    use crate::dir24::Dir24Table;
+    use crate::dual_stack::DualStackTrie;
+    use crate::lpc_trie::LPCTrie;
+    use crate::poptrie::Poptrie;
+    use crate::prefilter::{Prefilter, PrefilterWithStats};
+    use crate::range_map::IpRangeMap;
+
+    fn check<M: IpMatcher>(ipv4: bool, ipv6: bool) {
+        let cidrs = ["1.0.1.0/24", "1.0.2.0/23", "10.0.0.0/8", "2400:da00::/32"];
+        let matcher = M::build_from(cidrs.iter().map(|s| s.parse().unwrap()));
+        for (ip, expected) in [
+            ("1.0.1.7", ipv4),
+            ("1.0.3.255", ipv4),
+            ("10.255.0.1", ipv4),
+            ("1.0.4.0", false),
+            ("8.8.8.8", false),
+            ("2400:da00::1", ipv6),
+            ("2400:db00::1", false),
+            ("::1", false),
+        ]
+        .iter()
+        {
+            assert_eq!(matcher.contains(ip.parse().unwrap()), *expected, "{}", ip);
+        }
+    }
+
+    check::<LPCTrie<u32>>(true, false);
+    check::<LPCTrie<u128, u8>>(false, true);
+    check::<DualStackTrie>(true, true);
+    check::<Prefilter<DualStackTrie>>(true, true);
+    check::<PrefilterWithStats<DualStackTrie>>(true, true);
+    check::<Dir24Table>(true, false);
+    check::<Poptrie<u32>>(true, false);
+    check::<Poptrie<u128, ()>>(false, true);
+    check::<IpRangeMap<u32>>(true, false);
+    check::<IpRangeMap<u128, ()>>(false, true);
+    #[cfg(feature = "bs-matcher")]
+    check::<crate::cidr_bs::GeoIPMatcher>(true, true);
+    #[cfg(feature = "bs-matcher")]
+    check::<crate::cidr_bs::STreeMatcher>(true, true);
+}
@@ -0,0 +1,686 @@
+use crate::cidr::Cidr;
+use crate::dual_stack::DualStackTrie;
+#[cfg(feature = "pb")]
+use crate::geoip_db::GeoIpDatabase;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+// the search tree and the data section are separated by 16 zero bytes.
+const DATA_SECTION_SEPARATOR: usize = 16;
+
+#[derive(Debug)]
+pub enum MmdbError {
+    Io(io::Error),
+    InvalidFormat(String),
+}
+
+impl fmt::Display for MmdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MmdbError::Io(e) => write!(f, "read mmdb failed: {}", e),
+            MmdbError::InvalidFormat(s) => write!(f, "invalid mmdb: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for MmdbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MmdbError::Io(e) => Some(e),
+            MmdbError::InvalidFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MmdbError {
+    fn from(e: io::Error) -> Self {
+        MmdbError::Io(e)
+    }
+}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, MmdbError> {
+    Err(MmdbError::InvalidFormat(message.into()))
+}
+
+// A value of the MMDB data section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmdbValue {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Map(Vec<(String, MmdbValue)>),
+    Array(Vec<MmdbValue>),
+    Bool(bool),
+    Float(f32),
+}
+
+impl MmdbValue {
+    pub fn get(&self, key: &str) -> Option<&MmdbValue> {
+        match self {
+            MmdbValue::Map(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MmdbValue::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_uint(&self) -> Option<u128> {
+        match self {
+            MmdbValue::Uint(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    // country.iso_code, falling back to registered_country for networks such
+    // as anycast ranges which have no located country.
+    pub fn country_iso_code(&self) -> Option<&str> {
+        ["country", "registered_country"]
+            .iter()
+            .filter_map(|k| self.get(k)?.get("iso_code")?.as_str())
+            .next()
+    }
+}
+
+// Decoder of the MMDB data format, pointers are relative to the start of buf.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], MmdbError> {
+        match self.buf.get(offset..offset + len) {
+            Some(b) => Ok(b),
+            None => invalid(format!("data at {} runs past the section end", offset)),
+        }
+    }
+
+    fn uint(&self, offset: usize, len: usize) -> Result<u128, MmdbError> {
+        if len > 16 {
+            return invalid(format!("integer of {} bytes", len));
+        }
+        Ok(self
+            .bytes(offset, len)?
+            .iter()
+            .fold(0u128, |v, b| v << 8 | *b as u128))
+    }
+
+    // decode the value at offset, returns it with the offset right after it.
+    fn decode(&self, offset: usize) -> Result<(MmdbValue, usize), MmdbError> {
+        self.decode_depth(offset, 0)
+    }
+
+    fn decode_depth(&self, offset: usize, depth: u32) -> Result<(MmdbValue, usize), MmdbError> {
+        if depth > 64 {
+            return invalid("data nested too deep");
+        }
+        let ctrl = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut kind = ctrl >> 5;
+        if kind == 0 {
+            kind = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+        let size = (ctrl & 0x1f) as usize;
+        if kind == 1 {
+            // pointer: 2 size bits pick the length, 3 value bits are kept for
+            // the shorter forms.
+            let len = (size >> 3) + 1;
+            let value = self.uint(offset, len)? as usize;
+            let target = match len {
+                1 => (size & 7) << 8 | value,
+                2 => ((size & 7) << 16 | value) + 2048,
+                3 => ((size & 7) << 24 | value) + 526_336,
+                _ => value,
+            };
+            let (value, _) = self.decode_depth(target, depth + 1)?;
+            return Ok((value, offset + len));
+        }
+        let (size, mut offset) = match size {
+            29 => (29 + self.uint(offset, 1)? as usize, offset + 1),
+            30 => (285 + self.uint(offset, 2)? as usize, offset + 2),
+            31 => (65_821 + self.uint(offset, 3)? as usize, offset + 3),
+            _ => (size, offset),
+        };
+        let value = match kind {
+            2 => match std::str::from_utf8(self.bytes(offset, size)?) {
+                Ok(s) => MmdbValue::String(s.to_string()),
+                Err(_) => return invalid(format!("invalid utf-8 string at {}", offset)),
+            },
+            3 if size == 8 => MmdbValue::Double(f64::from_bits(self.uint(offset, 8)? as u64)),
+            4 => MmdbValue::Bytes(self.bytes(offset, size)?.to_vec()),
+            5 | 6 | 9 | 10 => MmdbValue::Uint(self.uint(offset, size)?),
+            8 if size <= 4 => MmdbValue::Int(self.uint(offset, size)? as u32 as i32),
+            7 => {
+                let mut pairs = Vec::with_capacity(size);
+                for _ in 0..size {
+                    let (key, next) = self.decode_depth(offset, depth + 1)?;
+                    let (value, next) = self.decode_depth(next, depth + 1)?;
+                    match key {
+                        MmdbValue::String(key) => pairs.push((key, value)),
+                        _ => return invalid(format!("map key at {} is not a string", offset)),
+                    }
+                    offset = next;
+                }
+                return Ok((MmdbValue::Map(pairs), offset));
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size);
+                for _ in 0..size {
+                    let (value, next) = self.decode_depth(offset, depth + 1)?;
+                    items.push(value);
+                    offset = next;
+                }
+                return Ok((MmdbValue::Array(items), offset));
+            }
+            14 => return Ok((MmdbValue::Bool(size != 0), offset)),
+            15 if size == 4 => MmdbValue::Float(f32::from_bits(self.uint(offset, 4)? as u32)),
+            _ => {
+                return invalid(format!(
+                    "unsupported type {} of size {} at {}",
+                    kind, size, offset
+                ))
+            }
+        };
+        Ok((value, offset + size))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MmdbMetadata {
+    pub node_count: usize,
+    pub record_size: usize,
+    pub ip_version: u16,
+    pub database_type: String,
+}
+
+// Reader of MaxMind DB files such as GeoLite2-Country.mmdb.
+pub struct MmdbReader {
+    buf: Vec<u8>,
+    metadata: MmdbMetadata,
+    data_start: usize,
+    data_end: usize,
+    // node of ::/96 where the IPv4 space starts in an IPv6 tree.
+    ipv4_start: usize,
+}
+
+impl MmdbReader {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MmdbReader, MmdbError> {
+        MmdbReader::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(buf: Vec<u8>) -> Result<MmdbReader, MmdbError> {
+        let marker = match buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+        {
+            Some(pos) => pos,
+            None => return invalid("metadata marker not found"),
+        };
+        let metadata_start = marker + METADATA_MARKER.len();
+        let decoder = Decoder {
+            buf: &buf[metadata_start..],
+        };
+        let (meta, _) = decoder.decode(0)?;
+        let field = |key: &str| match meta.get(key).and_then(MmdbValue::as_uint) {
+            Some(v) => Ok(v as usize),
+            None => invalid(format!("metadata has no {}", key)),
+        };
+        let metadata = MmdbMetadata {
+            node_count: field("node_count")?,
+            record_size: field("record_size")?,
+            ip_version: field("ip_version")? as u16,
+            database_type: meta
+                .get("database_type")
+                .and_then(MmdbValue::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        };
+        if ![24, 28, 32].contains(&metadata.record_size) {
+            return invalid(format!("unsupported record size {}", metadata.record_size));
+        }
+        if metadata.ip_version != 4 && metadata.ip_version != 6 {
+            return invalid(format!("unsupported ip version {}", metadata.ip_version));
+        }
+        // node_count is untrusted, a huge one must not wrap past the check.
+        let data_start = metadata
+            .node_count
+            .checked_mul(metadata.record_size)
+            .and_then(|bits| (bits / 4).checked_add(DATA_SECTION_SEPARATOR));
+        let data_start = match data_start {
+            Some(start) if start <= marker => start,
+            _ => return invalid("search tree runs past the metadata"),
+        };
+        let mut reader = MmdbReader {
+            buf,
+            metadata,
+            data_start,
+            data_end: marker,
+            ipv4_start: 0,
+        };
+        if reader.metadata.ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= reader.metadata.node_count {
+                    break;
+                }
+                node = reader.read_record(node, 0)?;
+            }
+            reader.ipv4_start = node;
+        }
+        Ok(reader)
+    }
+
+    pub fn metadata(&self) -> &MmdbMetadata {
+        &self.metadata
+    }
+
+    fn read_record(&self, node: usize, bit: usize) -> Result<usize, MmdbError> {
+        let size = self.metadata.record_size;
+        let base = node * size / 4;
+        let b = match self.buf.get(base..base + size / 4) {
+            Some(b) => b,
+            None => return invalid(format!("node {} is out of the search tree", node)),
+        };
+        let be = |b: &[u8]| b.iter().fold(0usize, |v, x| v << 8 | *x as usize);
+        Ok(match (size, bit) {
+            (24, 0) => be(&b[0..3]),
+            (24, _) => be(&b[3..6]),
+            // the middle byte holds the high nibble of both records.
+            (28, 0) => (b[3] as usize >> 4) << 24 | be(&b[0..3]),
+            (28, _) => (b[3] as usize & 0x0f) << 24 | be(&b[4..7]),
+            (_, 0) => be(&b[0..4]),
+            (_, _) => be(&b[4..8]),
+        })
+    }
+
+    // offset into the data section of a record pointing past the tree.
+    fn data_offset(&self, record: usize) -> Result<usize, MmdbError> {
+        let offset = record.wrapping_sub(self.metadata.node_count + DATA_SECTION_SEPARATOR);
+        if record < self.metadata.node_count + DATA_SECTION_SEPARATOR
+            || self.data_start + offset >= self.data_end
+        {
+            return invalid(format!("record {} points past the data section", record));
+        }
+        Ok(offset)
+    }
+
+    pub fn decode(&self, offset: usize) -> Result<MmdbValue, MmdbError> {
+        let decoder = Decoder {
+            buf: &self.buf[self.data_start..self.data_end],
+        };
+        Ok(decoder.decode(offset)?.0)
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Result<Option<MmdbValue>, MmdbError> {
+        let (mut node, key, bits) = match (ip, self.metadata.ip_version) {
+            (IpAddr::V4(ip), 4) => (0, u32::from(ip) as u128, 32),
+            (IpAddr::V4(ip), _) => (self.ipv4_start, u32::from(ip) as u128, 32),
+            (IpAddr::V6(_), 4) => return Ok(None),
+            (IpAddr::V6(ip), _) => (0, u128::from(ip), 128),
+        };
+        let node_count = self.metadata.node_count;
+        for i in 0..bits {
+            if node >= node_count {
+                break;
+            }
+            node = self.read_record(node, (key >> (bits - 1 - i)) as usize & 1)?;
+        }
+        match node {
+            n if n == node_count => Ok(None),
+            n if n > node_count => Ok(Some(self.decode(self.data_offset(n)?)?)),
+            _ => invalid("search tree is deeper than the address"),
+        }
+    }
+
+    pub fn lookup_country(&self, ip: IpAddr) -> Result<Option<String>, MmdbError> {
+        Ok(self
+            .lookup(ip)?
+            .and_then(|v| v.country_iso_code().map(|s| s.to_string())))
+    }
+
+    // every network of the tree with the data offset of its record. IPv4 is
+    // taken from ::/96 and its aliases such as ::ffff:0:0/96 are skipped.
+    pub fn networks(&self) -> Result<Vec<(Cidr, usize)>, MmdbError> {
+        let mut networks = vec![];
+        if self.metadata.ip_version == 4 {
+            self.walk(0, 32, None, &mut networks)?;
+        } else {
+            if self.ipv4_start < self.metadata.node_count {
+                self.walk(self.ipv4_start, 32, None, &mut networks)?;
+            }
+            self.walk(0, 128, Some(self.ipv4_start), &mut networks)?;
+        }
+        Ok(networks)
+    }
+
+    fn walk(
+        &self,
+        root: usize,
+        width: u32,
+        skip: Option<usize>,
+        networks: &mut Vec<(Cidr, usize)>,
+    ) -> Result<(), MmdbError> {
+        let node_count = self.metadata.node_count;
+        // (record, prefix length, network), popped in address order.
+        let mut stack = vec![(root, 0u32, 0u128)];
+        while let Some((record, len, key)) = stack.pop() {
+            if record > node_count {
+                let cidr = if width == 32 {
+                    Cidr::V4(key as u32, len as u8)
+                } else {
+                    Cidr::V6(key, len as u8)
+                };
+                networks.push((cidr, self.data_offset(record)?));
+                continue;
+            }
+            if len >= width {
+                return invalid("search tree is deeper than the address");
+            }
+            for bit in (0..2usize).rev() {
+                let child = self.read_record(record, bit)?;
+                if child != node_count && Some(child) != skip {
+                    stack.push((child, len + 1, key | (bit as u128) << (width - 1 - len)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // every network with the iso code of its country, networks without one
+    // are left out.
+    pub fn country_networks(&self) -> Result<Vec<(Cidr, String)>, MmdbError> {
+        let mut codes: HashMap<usize, Option<String>> = HashMap::new();
+        let mut result = vec![];
+        for (cidr, offset) in self.networks()? {
+            let code = match codes.entry(offset) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let value = self.decode(offset)?;
+                    e.insert(value.country_iso_code().map(|s| s.to_uppercase()))
+                }
+            };
+            if let Some(code) = code {
+                result.push((cidr, code.clone()));
+            }
+        }
+        Ok(result)
+    }
+
+    // one trie for every country, the value is the iso code.
+    pub fn to_dual_stack(&self) -> Result<DualStackTrie, MmdbError> {
+        let mut trie = DualStackTrie::new();
+        for (cidr, code) in self.country_networks()? {
+            trie.put(cidr, code);
+        }
+        Ok(trie)
+    }
+
+    #[cfg(feature = "pb")]
+    pub fn to_geoip_database(&self) -> Result<GeoIpDatabase, MmdbError> {
+        let mut db = GeoIpDatabase::new();
+        for (cidr, code) in self.country_networks()? {
+            db.put(&code, cidr);
+        }
+        Ok(db)
+    }
+}
+
+// Builds a small MaxMind DB: an IPv6 tree with 28 bit records, IPv4 under
+// ::/96 aliased at ::ffff:0:0/96, and a data section using pointers.
+#[cfg(test)]
+fn test_mmdb() -> Vec<u8> {
+    #[derive(Clone, Copy)]
+    enum Rec {
+        Empty,
+        Node(usize),
+        Data(usize),
+    }
+    fn ctrl(out: &mut Vec<u8>, kind: u8, size: usize) {
+        let short = if size < 29 { size as u8 } else { 29 };
+        if kind <= 7 {
+            out.push(kind << 5 | short);
+        } else {
+            out.push(short);
+            out.push(kind - 7);
+        }
+        if size >= 29 {
+            out.push((size - 29) as u8);
+        }
+    }
+    fn string(out: &mut Vec<u8>, s: &str) {
+        ctrl(out, 2, s.len());
+        out.extend(s.as_bytes());
+    }
+    fn uint(out: &mut Vec<u8>, kind: u8, v: u64) {
+        let bytes: Vec<u8> = v
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        ctrl(out, kind, bytes.len());
+        out.extend(bytes);
+    }
+    fn pointer(out: &mut Vec<u8>, target: usize) {
+        out.push(1 << 5 | (target >> 8) as u8);
+        out.push(target as u8);
+    }
+
+    let mut data = vec![];
+    let cn = data.len();
+    ctrl(&mut data, 7, 2);
+    let country_key = data.len();
+    string(&mut data, "country");
+    ctrl(&mut data, 7, 2);
+    let iso_code_key = data.len();
+    string(&mut data, "iso_code");
+    string(&mut data, "CN");
+    string(&mut data, "names");
+    ctrl(&mut data, 7, 1);
+    string(&mut data, "en");
+    string(&mut data, "China");
+    string(&mut data, "continent");
+    ctrl(&mut data, 7, 1);
+    string(&mut data, "code");
+    string(&mut data, "AS");
+    let us = data.len();
+    ctrl(&mut data, 7, 1);
+    pointer(&mut data, country_key);
+    ctrl(&mut data, 7, 1);
+    pointer(&mut data, iso_code_key);
+    string(&mut data, "US");
+    let anycast = data.len();
+    ctrl(&mut data, 7, 1);
+    string(&mut data, "registered_country");
+    ctrl(&mut data, 7, 1);
+    pointer(&mut data, iso_code_key);
+    string(&mut data, "US");
+    let unknown = data.len();
+    ctrl(&mut data, 7, 1);
+    string(&mut data, "traits");
+    ctrl(&mut data, 7, 1);
+    string(&mut data, "is_anycast");
+    ctrl(&mut data, 14, 1);
+
+    fn insert(nodes: &mut Vec<[Rec; 2]>, key: u128, len: u32, rec: Rec) {
+        let mut node = 0;
+        for i in 0..len - 1 {
+            let bit = (key >> (127 - i)) as usize & 1;
+            node = match nodes[node][bit] {
+                Rec::Node(n) => n,
+                _ => {
+                    nodes.push([Rec::Empty; 2]);
+                    nodes[node][bit] = Rec::Node(nodes.len() - 1);
+                    nodes.len() - 1
+                }
+            };
+        }
+        nodes[node][(key >> (128 - len)) as usize & 1] = rec;
+    }
+    let mut nodes: Vec<[Rec; 2]> = vec![[Rec::Empty; 2]];
+    let networks = [
+        ("1.0.1.0/24", cn),
+        ("1.0.2.0/23", cn),
+        ("2400:da00::/32", cn),
+        ("8.8.8.0/24", us),
+        ("2001:4860::/32", us),
+        ("1.1.1.0/24", anycast),
+        ("9.9.9.0/24", unknown),
+    ];
+    for (cidr, offset) in networks.iter() {
+        match cidr.parse().unwrap() {
+            Cidr::V4(key, len) => {
+                insert(&mut nodes, key as u128, len as u32 + 96, Rec::Data(*offset))
+            }
+            Cidr::V6(key, len) => insert(&mut nodes, key, len as u32, Rec::Data(*offset)),
+        }
+    }
+    let ipv4_start = {
+        let mut node = 0;
+        for _ in 0..96 {
+            if let Rec::Node(n) = nodes[node][0] {
+                node = n;
+            }
+        }
+        node
+    };
+    insert(&mut nodes, 0xffff << 32, 96, Rec::Node(ipv4_start));
+
+    let node_count = nodes.len();
+    let value = |rec: Rec| match rec {
+        Rec::Empty => node_count,
+        Rec::Node(n) => n,
+        Rec::Data(offset) => node_count + 16 + offset,
+    };
+    let mut out = vec![];
+    for [left, right] in nodes.iter() {
+        let (l, r) = (value(*left), value(*right));
+        out.extend(&(l as u32).to_be_bytes()[1..]);
+        out.push(((l >> 24) as u8) << 4 | (r >> 24) as u8);
+        out.extend(&(r as u32).to_be_bytes()[1..]);
+    }
+    out.extend(&[0u8; 16]);
+    out.extend(&data);
+    out.extend(METADATA_MARKER);
+    ctrl(&mut out, 7, 9);
+    string(&mut out, "node_count");
+    uint(&mut out, 6, node_count as u64);
+    string(&mut out, "record_size");
+    uint(&mut out, 5, 28);
+    string(&mut out, "ip_version");
+    uint(&mut out, 5, 6);
+    string(&mut out, "database_type");
+    string(&mut out, "CIDRMatcher-Test-Country");
+    string(&mut out, "binary_format_major_version");
+    uint(&mut out, 5, 2);
+    string(&mut out, "binary_format_minor_version");
+    uint(&mut out, 5, 0);
+    string(&mut out, "build_epoch");
+    uint(&mut out, 9, 1_600_000_000);
+    string(&mut out, "languages");
+    ctrl(&mut out, 11, 1);
+    string(&mut out, "en");
+    string(&mut out, "description");
+    ctrl(&mut out, 7, 1);
+    string(&mut out, "en");
+    string(
+        &mut out,
+        "a small country database for the mmdb reader tests",
+    );
+    out
+}
+
+#[test]
+fn test_mmdb_reader() {
+    let reader = MmdbReader::from_bytes(test_mmdb()).unwrap();
+    assert_eq!(reader.metadata().record_size, 28);
+    assert_eq!(reader.metadata().ip_version, 6);
+    assert_eq!(reader.metadata().database_type, "CIDRMatcher-Test-Country");
+
+    let country = |ip: &str| reader.lookup_country(ip.parse().unwrap()).unwrap();
+    assert_eq!(country("1.0.1.1"), Some("CN".to_string()));
+    assert_eq!(country("1.0.3.255"), Some("CN".to_string()));
+    assert_eq!(country("2400:da00::1"), Some("CN".to_string()));
+    assert_eq!(country("8.8.8.8"), Some("US".to_string()));
+    assert_eq!(country("::ffff:8.8.8.8"), Some("US".to_string()));
+    assert_eq!(country("2001:4860:4860::8888"), Some("US".to_string()));
+    assert_eq!(country("1.1.1.1"), Some("US".to_string()));
+    assert_eq!(country("9.9.9.9"), None);
+    assert_eq!(country("10.0.0.1"), None);
+    assert_eq!(country("2001:db8::1"), None);
+    let value = reader.lookup("1.0.1.1".parse().unwrap()).unwrap().unwrap();
+    assert_eq!(
+        value
+            .get("country")
+            .unwrap()
+            .get("names")
+            .unwrap()
+            .get("en"),
+        Some(&MmdbValue::String("China".to_string()))
+    );
+
+    // the ::ffff:0:0/96 alias must not report the IPv4 networks twice.
+    assert_eq!(reader.networks().unwrap().len(), 7);
+    let networks = reader.country_networks().unwrap();
+    assert_eq!(networks.len(), 6);
+    assert_eq!(
+        networks[0],
+        ("1.0.1.0/24".parse().unwrap(), "CN".to_string())
+    );
+
+    let trie = reader.to_dual_stack().unwrap();
+    assert_eq!(trie.get_with_value("1.0.2.9".parse().unwrap()), "CN");
+    assert_eq!(trie.get_with_value("2001:4860::1".parse().unwrap()), "US");
+    assert!(!trie.get("9.9.9.9".parse().unwrap()));
+
+    assert!(MmdbReader::from_bytes(b"not a database".to_vec()).is_err());
+    let mut truncated = test_mmdb();
+    truncated.drain(10..40);
+    assert!(MmdbReader::from_bytes(truncated)
+        .and_then(|r| r.networks())
+        .is_err());
+
+    // node_count as a uint64 of all ones.
+    let mut huge = test_mmdb();
+    let at = huge.windows(10).rposition(|w| w == b"node_count").unwrap() + 10;
+    let len = (huge[at] & 0x1f) as usize;
+    huge.splice(
+        at..at + 1 + len,
+        [8, 2, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+            .iter()
+            .copied(),
+    );
+    match MmdbReader::from_bytes(huge) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "invalid mmdb: search tree runs past the metadata"
+        ),
+        _ => panic!("expected an oversized search tree"),
+    }
+}
+
+#[cfg(feature = "pb")]
+#[test]
+fn test_mmdb_to_geoip_database() {
+    let db = MmdbReader::from_bytes(test_mmdb())
+        .unwrap()
+        .to_geoip_database()
+        .unwrap();
+    assert_eq!(db.country_codes().collect::<Vec<_>>(), vec!["CN", "US"]);
+    assert!(db.contains("cn", "1.0.2.1".parse().unwrap()));
+    assert!(db.contains("us", "1.1.1.1".parse().unwrap()));
+    assert_eq!(db.country_of("9.9.9.9".parse().unwrap()), None);
+}
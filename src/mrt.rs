@@ -0,0 +1,463 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::LPCTrie;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+// MRT types and TABLE_DUMP_V2 subtypes of RFC 6396.
+const TABLE_DUMP_V2: u16 = 13;
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+
+const ATTR_EXTENDED_LENGTH: u8 = 0x10;
+const ATTR_AS_PATH: u8 = 2;
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+
+#[derive(Debug)]
+pub enum MrtError {
+    Io(io::Error),
+    InvalidFormat(String),
+}
+
+impl fmt::Display for MrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MrtError::Io(e) => write!(f, "read mrt failed: {}", e),
+            MrtError::InvalidFormat(s) => write!(f, "invalid mrt: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for MrtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MrtError::Io(e) => Some(e),
+            MrtError::InvalidFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MrtError {
+    fn from(e: io::Error) -> Self {
+        MrtError::Io(e)
+    }
+}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, MrtError> {
+    Err(MrtError::InvalidFormat(message.into()))
+}
+
+// big-endian fields of one record body.
+struct Bytes<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8], MrtError> {
+        if self.buf.len() < n {
+            return invalid(format!("truncated {}", what));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, MrtError> {
+        Ok(self.take(1, what)?[0])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, MrtError> {
+        let b = self.take(2, what)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, MrtError> {
+        let b = self.take(4, what)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn ip(&mut self, ipv6: bool, what: &str) -> Result<IpAddr, MrtError> {
+        if ipv6 {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(self.take(16, what)?);
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        } else {
+            Ok(IpAddr::V4(Ipv4Addr::from(self.u32(what)?)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub bgp_id: Ipv4Addr,
+    pub addr: IpAddr,
+    pub asn: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsSegment {
+    Sequence(Vec<u32>),
+    Set(Vec<u32>),
+}
+
+// One path of a RIB record, as the peer at peer_index announced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RibEntry {
+    pub peer_index: u16,
+    pub originated: u32,
+    pub as_path: Vec<AsSegment>,
+}
+
+impl RibEntry {
+    // the last AS of the path. An aggregate ending in a set of several ASes
+    // has no single origin.
+    pub fn origin_as(&self) -> Option<u32> {
+        match self.as_path.last()? {
+            AsSegment::Sequence(asns) => asns.last().copied(),
+            AsSegment::Set(asns) if asns.len() == 1 => Some(asns[0]),
+            AsSegment::Set(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RibRecord {
+    pub sequence: u32,
+    pub cidr: Cidr,
+    pub entries: Vec<RibEntry>,
+}
+
+impl RibRecord {
+    // the origin most peers agree on, the first one seen on a tie.
+    pub fn origin_as(&self) -> Option<u32> {
+        let mut counts: Vec<(u32, usize)> = vec![];
+        for asn in self.entries.iter().filter_map(RibEntry::origin_as) {
+            match counts.iter_mut().find(|(a, _)| *a == asn) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((asn, 1)),
+            }
+        }
+        let mut best: Option<(u32, usize)> = None;
+        for (asn, n) in counts {
+            if !matches!(best, Some((_, m)) if m >= n) {
+                best = Some((asn, n));
+            }
+        }
+        best.map(|(asn, _)| asn)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MrtRecord {
+    PeerIndexTable {
+        collector: Ipv4Addr,
+        view: String,
+        peers: Vec<Peer>,
+    },
+    Rib(RibRecord),
+    // records of other types are passed over with their type and subtype.
+    Other {
+        kind: u16,
+        subtype: u16,
+    },
+}
+
+fn parse_peer_index_table(mut b: Bytes<'_>) -> Result<MrtRecord, MrtError> {
+    let collector = Ipv4Addr::from(b.u32("collector id")?);
+    let len = b.u16("view name")? as usize;
+    let view = String::from_utf8_lossy(b.take(len, "view name")?).into_owned();
+    let count = b.u16("peer count")?;
+    let mut peers = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        // bit 0 of the peer type is an IPv6 address, bit 1 a 4-byte AS.
+        let kind = b.u8("peer type")?;
+        let bgp_id = Ipv4Addr::from(b.u32("peer bgp id")?);
+        let addr = b.ip(kind & 1 != 0, "peer address")?;
+        let asn = if kind & 2 != 0 {
+            b.u32("peer as")?
+        } else {
+            b.u16("peer as")? as u32
+        };
+        peers.push(Peer { bgp_id, addr, asn });
+    }
+    Ok(MrtRecord::PeerIndexTable {
+        collector,
+        view,
+        peers,
+    })
+}
+
+// AS_PATH in TABLE_DUMP_V2 always carries 4-byte ASes. The confederation
+// segments are local to the announcing AS and left out.
+fn parse_as_path(mut b: Bytes<'_>) -> Result<Vec<AsSegment>, MrtError> {
+    let mut segments = vec![];
+    while !b.buf.is_empty() {
+        let kind = b.u8("as path segment")?;
+        let count = b.u8("as path segment")?;
+        let mut asns = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            asns.push(b.u32("as path segment")?);
+        }
+        match kind {
+            AS_SET => segments.push(AsSegment::Set(asns)),
+            AS_SEQUENCE => segments.push(AsSegment::Sequence(asns)),
+            _ => {}
+        }
+    }
+    Ok(segments)
+}
+
+fn parse_rib(mut b: Bytes<'_>, ipv6: bool) -> Result<MrtRecord, MrtError> {
+    let sequence = b.u32("sequence number")?;
+    let prefix = b.u8("prefix length")?;
+    if prefix > if ipv6 { 128 } else { 32 } {
+        return invalid(format!("prefix length {} of record {}", prefix, sequence));
+    }
+    let mut octets = [0u8; 16];
+    let len = (prefix as usize).div_ceil(8);
+    octets[..len].copy_from_slice(b.take(len, "prefix")?);
+    let cidr = if ipv6 {
+        Cidr::new(IpAddr::V6(Ipv6Addr::from(octets)), prefix)
+    } else {
+        let v4 = [octets[0], octets[1], octets[2], octets[3]];
+        Cidr::new(IpAddr::V4(Ipv4Addr::from(v4)), prefix)
+    }
+    .map_err(|e| MrtError::InvalidFormat(e.to_string()))?;
+
+    let count = b.u16("entry count")?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let peer_index = b.u16("peer index")?;
+        let originated = b.u32("originated time")?;
+        let len = b.u16("attribute length")? as usize;
+        let mut attrs = Bytes {
+            buf: b.take(len, "attributes")?,
+        };
+        let mut as_path = vec![];
+        while !attrs.buf.is_empty() {
+            let flags = attrs.u8("attribute")?;
+            let kind = attrs.u8("attribute")?;
+            let len = if flags & ATTR_EXTENDED_LENGTH != 0 {
+                attrs.u16("attribute")? as usize
+            } else {
+                attrs.u8("attribute")? as usize
+            };
+            let value = attrs.take(len, "attribute")?;
+            if kind == ATTR_AS_PATH {
+                as_path = parse_as_path(Bytes { buf: value })?;
+            }
+        }
+        entries.push(RibEntry {
+            peer_index,
+            originated,
+            as_path,
+        });
+    }
+    Ok(MrtRecord::Rib(RibRecord {
+        sequence,
+        cidr,
+        entries,
+    }))
+}
+
+// Streams the records of an MRT file, e.g. a RouteViews or RIPE RIS
+// bview/rib dump. Compressed dumps have to be decompressed by the caller.
+pub struct MrtReader<R> {
+    reader: R,
+}
+
+impl MrtReader<BufReader<File>> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, MrtError> {
+        Ok(MrtReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> MrtReader<R> {
+    pub fn new(reader: R) -> MrtReader<R> {
+        MrtReader { reader }
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<MrtRecord>, MrtError> {
+        let mut header = [0u8; 12];
+        let mut read = 0;
+        while read < header.len() {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return invalid("truncated record header"),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let kind = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let mut body = vec![];
+        self.reader
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut body)?;
+        if body.len() < len as usize {
+            return invalid(format!("truncated record of type {}", kind));
+        }
+        let b = Bytes { buf: &body };
+        let record = match (kind, subtype) {
+            (TABLE_DUMP_V2, PEER_INDEX_TABLE) => parse_peer_index_table(b)?,
+            (TABLE_DUMP_V2, RIB_IPV4_UNICAST) => parse_rib(b, false)?,
+            (TABLE_DUMP_V2, RIB_IPV6_UNICAST) => parse_rib(b, true)?,
+            _ => MrtRecord::Other { kind, subtype },
+        };
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Iterator for MrtReader<R> {
+    type Item = Result<MrtRecord, MrtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+// Origin AS of every prefix of a RIB dump.
+pub struct AsnTrie {
+    v4: LPCTrie<u32, u32>,
+    v6: LPCTrie<u128, u32>,
+}
+
+impl Default for AsnTrie {
+    fn default() -> Self {
+        AsnTrie::new()
+    }
+}
+
+impl AsnTrie {
+    pub fn new() -> AsnTrie {
+        AsnTrie {
+            v4: LPCTrie::new(),
+            v6: LPCTrie::new(),
+        }
+    }
+
+    // prefixes without a single origin AS are left out.
+    pub fn from_reader<R: Read>(reader: R) -> Result<AsnTrie, MrtError> {
+        let mut trie = AsnTrie::new();
+        for record in MrtReader::new(reader) {
+            if let MrtRecord::Rib(rib) = record? {
+                if let Some(asn) = rib.origin_as() {
+                    trie.put(rib.cidr, asn);
+                }
+            }
+        }
+        Ok(trie)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<AsnTrie, MrtError> {
+        AsnTrie::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn put(&mut self, cidr: Cidr, asn: u32) {
+        match cidr {
+            Cidr::V4(addr, prefix) => self.v4.put(addr, prefix, asn),
+            Cidr::V6(addr, prefix) => self.v6.put(addr, prefix, asn),
+        }
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        match ip {
+            IpAddr::V4(v4) => self.v4.get_value(u32::from(v4)).copied(),
+            IpAddr::V6(v6) => self.v6.get_value(u128::from(v6)).copied(),
+        }
+    }
+
+    pub fn v4(&self) -> &LPCTrie<u32, u32> {
+        &self.v4
+    }
+
+    pub fn v6(&self) -> &LPCTrie<u128, u32> {
+        &self.v6
+    }
+}
+
+#[test]
+fn test_mrt() {
+    let dump: &[u8] = include_bytes!("../tests/fixtures/rib_table_dump_v2.mrt");
+    let records = MrtReader::new(dump).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(records.len(), 11);
+    match &records[0] {
+        MrtRecord::PeerIndexTable {
+            collector,
+            view,
+            peers,
+        } => {
+            assert_eq!(*collector, Ipv4Addr::new(10, 0, 0, 1));
+            assert_eq!(view, "test");
+            assert_eq!(peers.len(), 3);
+            assert_eq!(peers[1].addr, "2001:db8::1".parse::<IpAddr>().unwrap());
+            assert_eq!(peers[1].asn, 4_200_000_000);
+            assert_eq!(peers[2].asn, 64501);
+        }
+        r => panic!("expected a peer index table, got {:?}", r),
+    }
+    assert_eq!(
+        records[7],
+        MrtRecord::Other {
+            kind: 16,
+            subtype: 5
+        }
+    );
+
+    let origins: Vec<(String, Option<u32>)> = records
+        .iter()
+        .filter_map(|r| match r {
+            MrtRecord::Rib(rib) => Some((rib.cidr.to_string(), rib.origin_as())),
+            _ => None,
+        })
+        .collect();
+    let expected = [
+        ("1.0.0.0/24", Some(13335)),
+        ("1.0.4.0/22", Some(38803)),
+        ("8.8.8.0/24", Some(15169)),
+        ("10.0.0.0/8", None),
+        ("10.1.0.0/16", Some(64513)),
+        ("192.0.2.0/24", Some(64497)),
+        ("2001:4860::/32", Some(15169)),
+        ("2400:cb00::/32", Some(13335)),
+        ("2400:cb00:2048::/48", Some(209242)),
+    ];
+    assert_eq!(
+        origins,
+        expected
+            .iter()
+            .map(|(c, a)| (c.to_string(), *a))
+            .collect::<Vec<_>>()
+    );
+    if let MrtRecord::Rib(rib) = &records[2] {
+        assert_eq!(
+            rib.entries[0].as_path,
+            vec![AsSegment::Sequence(vec![64500, 4826, 38803])]
+        );
+    }
+
+    let trie = AsnTrie::from_reader(dump).unwrap();
+    let lookup = |ip: &str| trie.lookup(ip.parse().unwrap());
+    assert_eq!(lookup("1.0.0.1"), Some(13335));
+    assert_eq!(lookup("1.0.7.255"), Some(38803));
+    assert_eq!(lookup("1.0.8.1"), None);
+    assert_eq!(lookup("10.1.2.3"), Some(64513));
+    assert_eq!(lookup("10.2.0.1"), None);
+    assert_eq!(lookup("2400:cb00:2048::1"), Some(209242));
+    assert_eq!(lookup("2400:cb00:1::1"), Some(13335));
+    assert_eq!(lookup("2001:4860:4860::8888"), Some(15169));
+
+    match AsnTrie::from_reader(&dump[..dump.len() - 3]) {
+        Err(e) => assert_eq!(e.to_string(), "invalid mrt: truncated record of type 13"),
+        _ => panic!("expected a truncated record"),
+    }
+    assert!(MrtReader::new(&dump[..5]).next_record().is_err());
+    assert!(AsnTrie::from_path("no/such/rib.mrt").is_err());
+}
@@ -0,0 +1,269 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::{LPCTrie, Values};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::net::IpAddr;
+
+// the tries of one table hold positions in the shared values, never the
+// values themselves.
+struct Table {
+    v4: LPCTrie<u32, u32>,
+    v6: LPCTrie<u128, u32>,
+}
+
+// Routing tables addressed by name or number, like `ip route ... table main`
+// and `table 100`. A value is stored once, in values, however many tables
+// hold it.
+pub struct MultiTable<V = String> {
+    tables: BTreeMap<String, Table>,
+    values: Values<V>,
+    // numbers with a name, as /etc/iproute2/rt_tables gives them.
+    aliases: HashMap<u32, String>,
+    chain: Vec<String>,
+}
+
+impl<V: Clone + Eq + Hash> Default for MultiTable<V> {
+    fn default() -> Self {
+        MultiTable::new()
+    }
+}
+
+impl<V: Clone + Eq + Hash> MultiTable<V> {
+    // the reserved tables of rt_tables, and the chain of the default rules.
+    pub fn new() -> MultiTable<V> {
+        let mut aliases = HashMap::new();
+        aliases.insert(253, "default".to_string());
+        aliases.insert(254, "main".to_string());
+        aliases.insert(255, "local".to_string());
+        MultiTable {
+            tables: BTreeMap::new(),
+            values: Values::new(),
+            aliases,
+            chain: vec![
+                "local".to_string(),
+                "main".to_string(),
+                "default".to_string(),
+            ],
+        }
+    }
+
+    // give table number id a name, routes put under either reach the same
+    // table. Routes already put under the number, or under its previous name,
+    // move to the name. Where the name has a route for the same prefix, that
+    // one stays.
+    pub fn add_alias(&mut self, id: u32, name: &str) {
+        let old = self.name(&id.to_string()).to_string();
+        self.aliases.insert(id, name.to_string());
+        if old == name {
+            return;
+        }
+        let moved = match self.tables.remove(&old) {
+            Some(moved) => moved,
+            None => return,
+        };
+        let table = match self.tables.get_mut(name) {
+            Some(table) => table,
+            None => {
+                self.tables.insert(name.to_string(), moved);
+                return;
+            }
+        };
+        // put keeps the value of a prefix that is already there.
+        for (addr, prefix, handle) in moved.v4.iter() {
+            table.v4.put(addr, prefix, *handle);
+        }
+        for (addr, prefix, handle) in moved.v6.iter() {
+            table.v6.put(addr, prefix, *handle);
+        }
+    }
+
+    fn name<'a>(&'a self, table: &'a str) -> &'a str {
+        match table
+            .parse::<u32>()
+            .ok()
+            .and_then(|id| self.aliases.get(&id))
+        {
+            Some(name) => name,
+            None => table,
+        }
+    }
+
+    fn table(&self, table: &str) -> Option<&Table> {
+        self.tables.get(self.name(table))
+    }
+
+    // put value for cidr into table, replacing what the prefix held.
+    pub fn put(&mut self, table: &str, cidr: Cidr, value: V) {
+        let handle = self.values.intern(value) as u32;
+        let name = self.name(table).to_string();
+        let table = self.tables.entry(name).or_insert_with(|| Table {
+            v4: LPCTrie::new(),
+            v6: LPCTrie::new(),
+        });
+        match cidr {
+            Cidr::V4(addr, prefix) => {
+                table.v4.remove_prefix(addr, prefix);
+                table.v4.put(addr, prefix, handle);
+            }
+            Cidr::V6(addr, prefix) => {
+                table.v6.remove_prefix(addr, prefix);
+                table.v6.put(addr, prefix, handle);
+            }
+        }
+    }
+
+    // Removed values stay in the shared values, other tables may hold them.
+    pub fn remove(&mut self, table: &str, cidr: Cidr) -> bool {
+        let name = self.name(table).to_string();
+        match (self.tables.get_mut(&name), cidr) {
+            (Some(t), Cidr::V4(addr, prefix)) => t.v4.remove_prefix(addr, prefix),
+            (Some(t), Cidr::V6(addr, prefix)) => t.v6.remove_prefix(addr, prefix),
+            (None, _) => false,
+        }
+    }
+
+    // longest prefix match in one table.
+    pub fn lookup_table(&self, table: &str, ip: IpAddr) -> Option<&V> {
+        let table = self.table(table)?;
+        let handle = match ip {
+            IpAddr::V4(ip) => table.v4.get_value(ip.into()),
+            IpAddr::V6(ip) => table.v6.get_value(ip.into()),
+        }?;
+        Some(&self.values[*handle as usize])
+    }
+
+    // the first table of chain with a route for ip, and that route.
+    pub fn lookup_chain<'a, S: AsRef<str>>(
+        &'a self,
+        chain: &'a [S],
+        ip: IpAddr,
+    ) -> Option<(&'a str, &'a V)> {
+        chain.iter().find_map(|table| {
+            let table = table.as_ref();
+            self.lookup_table(table, ip).map(|v| (table, v))
+        })
+    }
+
+    // lookup_chain with the chain set by set_chain, local, main and default
+    // unless changed.
+    pub fn lookup(&self, ip: IpAddr) -> Option<(&str, &V)> {
+        self.lookup_chain(&self.chain, ip)
+    }
+
+    pub fn set_chain<I, S>(&mut self, chain: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.chain = chain.into_iter().map(Into::into).collect();
+    }
+
+    pub fn chain(&self) -> &[String] {
+        &self.chain
+    }
+
+    // names of the tables holding routes, numbered ones without an alias by
+    // their number.
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.tables.keys().map(|k| k.as_str())
+    }
+
+    pub fn values(&self) -> &[V] {
+        self.values.as_slice()
+    }
+
+    // every prefix of table with its value.
+    pub fn iter<'a>(&'a self, table: &str) -> impl Iterator<Item = (Cidr, &'a V)> + 'a {
+        let table = self.table(table);
+        let v4 = table.into_iter().flat_map(|t| {
+            t.v4.iter()
+                .map(|(addr, prefix, h)| (Cidr::V4(addr, prefix), *h))
+        });
+        let v6 = table.into_iter().flat_map(|t| {
+            t.v6.iter()
+                .map(|(addr, prefix, h)| (Cidr::V6(addr, prefix), *h))
+        });
+        v4.chain(v6)
+            .map(move |(cidr, h)| (cidr, &self.values[h as usize]))
+    }
+}
+
+#[test]
+fn test_multi_table() {
+    let cidr = |s: &str| s.parse::<Cidr>().unwrap();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    let mut tables: MultiTable = MultiTable::new();
+    tables.put("main", cidr("0.0.0.0/0"), "wan".to_string());
+    tables.put("254", cidr("10.0.0.0/8"), "lan".to_string());
+    tables.put("local", cidr("127.0.0.0/8"), "lo".to_string());
+    tables.add_alias(200, "vpn");
+    tables.put("200", cidr("0.0.0.0/0"), "tun0".to_string());
+    tables.put("100", cidr("10.0.0.0/8"), "wan".to_string());
+    tables.put("100", cidr("2001:db8::/32"), "lan".to_string());
+    assert_eq!(
+        tables.table_names().collect::<Vec<_>>(),
+        vec!["100", "local", "main", "vpn"]
+    );
+    // seven routes share the four values.
+    assert_eq!(tables.values().len(), 4);
+
+    assert_eq!(tables.lookup_table("main", ip("10.1.1.1")).unwrap(), "lan");
+    assert_eq!(tables.lookup_table("254", ip("8.8.8.8")).unwrap(), "wan");
+    assert_eq!(tables.lookup_table("vpn", ip("8.8.8.8")).unwrap(), "tun0");
+    assert_eq!(
+        tables.lookup_table("100", ip("2001:db8::1")).unwrap(),
+        "lan"
+    );
+    assert_eq!(tables.lookup_table("100", ip("8.8.8.8")), None);
+    assert_eq!(tables.lookup_table("300", ip("8.8.8.8")), None);
+
+    assert_eq!(
+        tables.lookup(ip("127.0.0.1")),
+        Some(("local", &"lo".to_string()))
+    );
+    assert_eq!(
+        tables.lookup(ip("8.8.8.8")),
+        Some(("main", &"wan".to_string()))
+    );
+    // a source network routed through 100, falling through to vpn.
+    let chain = ["100", "vpn"];
+    assert_eq!(
+        tables.lookup_chain(&chain, ip("10.0.0.1")),
+        Some(("100", &"wan".to_string()))
+    );
+    assert_eq!(
+        tables.lookup_chain(&chain, ip("8.8.8.8")),
+        Some(("vpn", &"tun0".to_string()))
+    );
+    tables.set_chain(vec!["vpn"]);
+    assert_eq!(tables.lookup(ip("10.0.0.1")).unwrap().1, "tun0");
+
+    // put replaces, remove drops only the prefix of that table.
+    tables.put("main", cidr("10.0.0.0/8"), "tun0".to_string());
+    assert_eq!(tables.lookup_table("main", ip("10.1.1.1")).unwrap(), "tun0");
+    assert!(tables.remove("254", cidr("10.0.0.0/8")));
+    assert!(!tables.remove("main", cidr("10.0.0.0/8")));
+    assert_eq!(tables.lookup_table("main", ip("10.1.1.1")).unwrap(), "wan");
+    assert_eq!(tables.lookup_table("100", ip("10.1.1.1")).unwrap(), "wan");
+    assert_eq!(
+        tables
+            .iter("100")
+            .map(|(c, v)| format!("{} {}", c, v))
+            .collect::<Vec<_>>(),
+        vec!["10.0.0.0/8 wan", "2001:db8::/32 lan"]
+    );
+    assert_eq!(tables.iter("none").count(), 0);
+
+    // routes put before the alias move under the name.
+    let mut tables: MultiTable = MultiTable::new();
+    tables.put("200", cidr("0.0.0.0/0"), "tun0".to_string());
+    tables.put("200", cidr("10.0.0.0/8"), "tun0".to_string());
+    tables.put("vpn", cidr("10.0.0.0/8"), "tun1".to_string());
+    tables.add_alias(200, "vpn");
+    assert_eq!(tables.table_names().collect::<Vec<_>>(), vec!["vpn"]);
+    assert_eq!(tables.lookup_table("200", ip("8.8.8.8")).unwrap(), "tun0");
+    assert_eq!(tables.lookup_table("vpn", ip("10.0.0.1")).unwrap(), "tun1");
+    tables.add_alias(200, "wg");
+    assert_eq!(tables.lookup_table("wg", ip("8.8.8.8")).unwrap(), "tun0");
+}
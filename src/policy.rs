@@ -0,0 +1,302 @@
+use crate::cidr::Cidr;
+use crate::multi_table::MultiTable;
+use std::hash::Hash;
+use std::net::IpAddr;
+
+// What a rule looks at in a packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketMeta {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub mark: u32,
+    // inbound interface, None for locally generated packets.
+    pub iif: Option<String>,
+}
+
+impl PacketMeta {
+    pub fn new(src: IpAddr, dst: IpAddr) -> PacketMeta {
+        PacketMeta {
+            src,
+            dst,
+            mark: 0,
+            iif: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    // look the destination up in a table, moving on to the next rule when
+    // the table has no route for it.
+    Lookup(String),
+    Blackhole,
+    Unreachable,
+    Prohibit,
+}
+
+// One `ip rule`. Selectors left None match every packet, as "from all" does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub priority: u32,
+    pub from: Option<Cidr>,
+    pub to: Option<Cidr>,
+    // (mark, mask), the rule matches when mark & mask equals the packet
+    // mark & mask.
+    pub fwmark: Option<(u32, u32)>,
+    pub iif: Option<String>,
+    // "not", the rule applies to the packets the selectors do not match.
+    pub invert: bool,
+    pub action: RuleAction,
+}
+
+impl Rule {
+    pub fn new(priority: u32, action: RuleAction) -> Rule {
+        Rule {
+            priority,
+            from: None,
+            to: None,
+            fwmark: None,
+            iif: None,
+            invert: false,
+            action,
+        }
+    }
+
+    pub fn matches(&self, packet: &PacketMeta) -> bool {
+        // the kernel keeps one rule list per family, a rule with an IPv4
+        // prefix is not in the IPv6 list, "not" or not.
+        let family = |c: &Cidr, ip: IpAddr| c.is_ipv4() == ip.is_ipv4();
+        if !self.from.iter().all(|c| family(c, packet.src))
+            || !self.to.iter().all(|c| family(c, packet.dst))
+        {
+            return false;
+        }
+        let selected = self.from.iter().all(|c| c.contains(packet.src))
+            && self.to.iter().all(|c| c.contains(packet.dst))
+            && self
+                .fwmark
+                .iter()
+                .all(|(mark, mask)| packet.mark & mask == mark & mask)
+            && self.iif.iter().all(|iif| packet.iif.as_ref() == Some(iif));
+        selected != self.invert
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleOutcome {
+    NotMatched,
+    // a lookup rule whose table has no route for the destination.
+    NoRoute,
+    Applied,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub priority: u32,
+    pub outcome: RuleOutcome,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict<'a, V> {
+    Route { table: &'a str, value: &'a V },
+    Blackhole,
+    Unreachable,
+    Prohibit,
+    // no rule gave a route, the kernel answers "network unreachable".
+    NoRoute,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Resolution<'a, V> {
+    pub verdict: Verdict<'a, V>,
+    // the rules considered, in order, up to the one deciding.
+    pub trace: Vec<TraceEntry>,
+}
+
+// An ordered rule list over a set of tables, the `ip rule` and `ip route`
+// state of a router.
+pub struct PolicyRouter<V = String> {
+    rules: Vec<Rule>,
+    tables: MultiTable<V>,
+}
+
+impl<V: Clone + Eq + Hash> Default for PolicyRouter<V> {
+    fn default() -> Self {
+        PolicyRouter::new()
+    }
+}
+
+impl<V: Clone + Eq + Hash> PolicyRouter<V> {
+    // no rules, not even the default ones.
+    pub fn new() -> PolicyRouter<V> {
+        PolicyRouter::with_tables(MultiTable::new())
+    }
+
+    pub fn with_tables(tables: MultiTable<V>) -> PolicyRouter<V> {
+        PolicyRouter {
+            rules: vec![],
+            tables,
+        }
+    }
+
+    // the rules a fresh kernel starts with.
+    pub fn add_default_rules(&mut self) {
+        self.add_rule(Rule::new(0, RuleAction::Lookup("local".to_string())));
+        self.add_rule(Rule::new(32766, RuleAction::Lookup("main".to_string())));
+        self.add_rule(Rule::new(32767, RuleAction::Lookup("default".to_string())));
+    }
+
+    // rules of the same priority apply in the order they were added.
+    pub fn add_rule(&mut self, rule: Rule) {
+        let idx = self.rules.partition_point(|r| r.priority <= rule.priority);
+        self.rules.insert(idx, rule);
+    }
+
+    // drop the rules of priority, returns how many there were.
+    pub fn remove_rules(&mut self, priority: u32) -> usize {
+        let len = self.rules.len();
+        self.rules.retain(|r| r.priority != priority);
+        len - self.rules.len()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn tables(&self) -> &MultiTable<V> {
+        &self.tables
+    }
+
+    pub fn tables_mut(&mut self) -> &mut MultiTable<V> {
+        &mut self.tables
+    }
+
+    pub fn resolve(&self, packet: &PacketMeta) -> Resolution<'_, V> {
+        let mut trace = vec![];
+        for rule in self.rules.iter() {
+            let (outcome, verdict) = if !rule.matches(packet) {
+                (RuleOutcome::NotMatched, None)
+            } else {
+                let verdict = match &rule.action {
+                    RuleAction::Lookup(table) => self
+                        .tables
+                        .lookup_table(table, packet.dst)
+                        .map(|value| Verdict::Route { table, value }),
+                    RuleAction::Blackhole => Some(Verdict::Blackhole),
+                    RuleAction::Unreachable => Some(Verdict::Unreachable),
+                    RuleAction::Prohibit => Some(Verdict::Prohibit),
+                };
+                match verdict {
+                    Some(v) => (RuleOutcome::Applied, Some(v)),
+                    None => (RuleOutcome::NoRoute, None),
+                }
+            };
+            trace.push(TraceEntry {
+                priority: rule.priority,
+                outcome,
+            });
+            if let Some(verdict) = verdict {
+                return Resolution { verdict, trace };
+            }
+        }
+        Resolution {
+            verdict: Verdict::NoRoute,
+            trace,
+        }
+    }
+}
+
+#[test]
+fn test_policy() {
+    let cidr = |s: &str| s.parse::<Cidr>().unwrap();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let lookup = |t: &str| RuleAction::Lookup(t.to_string());
+
+    let mut router: PolicyRouter = PolicyRouter::new();
+    router.add_default_rules();
+    {
+        let tables = router.tables_mut();
+        tables.put("local", cidr("192.168.1.10/32"), "local".to_string());
+        tables.put("main", cidr("0.0.0.0/0"), "wan".to_string());
+        tables.put("main", cidr("192.168.1.0/24"), "lan".to_string());
+        tables.put("100", cidr("0.0.0.0/0"), "vpn".to_string());
+        tables.put("100", cidr("2000::/3"), "vpn".to_string());
+    }
+    // the lan goes out through the vpn, marked packets bypass it, and a
+    // guest network may not reach the lan.
+    let mut vpn = Rule::new(1000, lookup("100"));
+    vpn.from = Some(cidr("192.168.1.0/24"));
+    router.add_rule(vpn);
+    let mut bypass = Rule::new(900, lookup("main"));
+    bypass.fwmark = Some((0x1, 0xff));
+    router.add_rule(bypass);
+    let mut guest = Rule::new(500, RuleAction::Prohibit);
+    guest.iif = Some("guest0".to_string());
+    guest.to = Some(cidr("192.168.1.0/24"));
+    router.add_rule(guest);
+    let mut v6 = Rule::new(1100, RuleAction::Unreachable);
+    v6.to = Some(cidr("2000::/3"));
+    v6.invert = true;
+    router.add_rule(v6);
+    assert_eq!(
+        router
+            .rules()
+            .iter()
+            .map(|r| r.priority)
+            .collect::<Vec<_>>(),
+        vec![0, 500, 900, 1000, 1100, 32766, 32767]
+    );
+
+    let entry = |priority, outcome| TraceEntry { priority, outcome };
+    let packet = PacketMeta::new(ip("192.168.1.20"), ip("8.8.8.8"));
+    let res = router.resolve(&packet);
+    assert_eq!(
+        res.verdict,
+        Verdict::Route {
+            table: "100",
+            value: &"vpn".to_string()
+        }
+    );
+    assert_eq!(
+        res.trace,
+        vec![
+            entry(0, RuleOutcome::NoRoute),
+            entry(500, RuleOutcome::NotMatched),
+            entry(900, RuleOutcome::NotMatched),
+            entry(1000, RuleOutcome::Applied),
+        ]
+    );
+
+    let mut marked = packet.clone();
+    marked.mark = 0x301;
+    let res = router.resolve(&marked);
+    assert_eq!(res.trace.last(), Some(&entry(900, RuleOutcome::Applied)));
+    match res.verdict {
+        Verdict::Route { table, value } => assert_eq!((table, value.as_str()), ("main", "wan")),
+        v => panic!("expected a route, got {:?}", v),
+    }
+
+    let mut guest = PacketMeta::new(ip("10.0.0.5"), ip("192.168.1.10"));
+    assert!(matches!(
+        router.resolve(&guest).verdict,
+        Verdict::Route { table: "local", .. }
+    ));
+    guest.dst = ip("192.168.1.30");
+    guest.iif = Some("guest0".to_string());
+    assert_eq!(router.resolve(&guest).verdict, Verdict::Prohibit);
+
+    // IPv6 outside 2000::/3 is unreachable, 2000::/3 has no route in main.
+    let packet = PacketMeta::new(ip("fd00::2"), ip("fd00::1"));
+    assert_eq!(router.resolve(&packet).verdict, Verdict::Unreachable);
+    let packet = PacketMeta::new(ip("fd00::2"), ip("2001:db8::1"));
+    let res = router.resolve(&packet);
+    assert_eq!(res.verdict, Verdict::NoRoute);
+    assert_eq!(res.trace.len(), 7);
+    assert_eq!(res.trace[4], entry(1100, RuleOutcome::NotMatched));
+
+    assert_eq!(router.remove_rules(500), 1);
+    assert!(matches!(
+        router.resolve(&guest).verdict,
+        Verdict::Route { table: "main", .. }
+    ));
+}
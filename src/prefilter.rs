@@ -0,0 +1,261 @@
+use crate::cidr::{mask_v4, mask_v6, Cidr};
+use crate::matcher::IpMatcher;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// bloom filter bits per IPv6 cidr and probes per key, about 0.5% false
+// positives per prefix length.
+const BLOOM_BITS_PER_CIDR: usize = 16;
+const BLOOM_HASHES: u64 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefilterStats {
+    // lookups the filter answered alone.
+    pub rejected: u64,
+    // lookups passed on to the matcher, and how many of those it matched.
+    pub passed: u64,
+    pub matched: u64,
+}
+
+// Puts a cheap test in front of a matcher so that most misses never reach
+// it: a bit per IPv4 /16 any cidr touches, and for IPv6 a bloom filter
+// holding every cidr under its own prefix length, which a lookup probes with
+// the address cut to each of the lengths present. The filter only rejects
+// addresses outside all cidrs, so the matcher must not match anything else
+// (no reverse match).
+pub struct Prefilter<M> {
+    matcher: M,
+    v4: Vec<u64>,
+    bloom: Vec<u64>,
+    v6_lengths: Vec<u8>,
+}
+
+fn bloom_hash(addr: u128, prefix: u8) -> u64 {
+    // splitmix64 finalizer over both halves and the length.
+    let mut h = (addr >> 64) as u64 ^ (addr as u64).rotate_left(32) ^ prefix as u64;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+impl<M: IpMatcher> Prefilter<M> {
+    // matcher has to hold exactly cidrs.
+    pub fn new<I: IntoIterator<Item = Cidr>>(matcher: M, cidrs: I) -> Prefilter<M> {
+        let mut v4 = vec![0; (1 << 16) / 64];
+        let mut v6 = vec![];
+        for cidr in cidrs {
+            match cidr {
+                Cidr::V4(addr, prefix) => {
+                    let first = (mask_v4(addr, prefix) >> 16) as usize;
+                    let len = 1 << (16 - prefix.min(16));
+                    for slot in first..first + len {
+                        v4[slot / 64] |= 1 << (slot % 64);
+                    }
+                }
+                Cidr::V6(addr, prefix) => v6.push((mask_v6(addr, prefix), prefix)),
+            }
+        }
+        let words = (v6.len() * BLOOM_BITS_PER_CIDR)
+            .div_ceil(64)
+            .next_power_of_two();
+        let mut filter = Prefilter {
+            matcher,
+            v4,
+            bloom: vec![0; words],
+            v6_lengths: vec![],
+        };
+        for (addr, prefix) in v6 {
+            filter.bloom_insert(bloom_hash(addr, prefix));
+            if !filter.v6_lengths.contains(&prefix) {
+                filter.v6_lengths.push(prefix);
+            }
+        }
+        filter.v6_lengths.sort_unstable();
+        filter
+    }
+
+    // bit positions by double hashing, h1 + i * h2.
+    fn bloom_bits(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mask = (self.bloom.len() * 64 - 1) as u64;
+        let step = (hash >> 32) | 1;
+        (0..BLOOM_HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) & mask) as usize)
+    }
+
+    fn bloom_insert(&mut self, hash: u64) {
+        for bit in self.bloom_bits(hash) {
+            self.bloom[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn bloom_contains(&self, hash: u64) -> bool {
+        self.bloom_bits(hash)
+            .all(|bit| self.bloom[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // false only when ip is in none of the cidrs.
+    pub fn may_contain(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => {
+                let slot = (u32::from(ip) >> 16) as usize;
+                self.v4[slot / 64] & (1 << (slot % 64)) != 0
+            }
+            IpAddr::V6(ip) => {
+                let addr = u128::from(ip);
+                self.v6_lengths
+                    .iter()
+                    .any(|prefix| self.bloom_contains(bloom_hash(mask_v6(addr, *prefix), *prefix)))
+            }
+        }
+    }
+
+    pub fn matcher(&self) -> &M {
+        &self.matcher
+    }
+
+    // the same filter counting its lookups, see PrefilterWithStats.
+    pub fn with_stats(self) -> PrefilterWithStats<M> {
+        PrefilterWithStats {
+            filter: self,
+            rejected: AtomicU64::new(0),
+            passed: AtomicU64::new(0),
+            matched: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<M: IpMatcher> IpMatcher for Prefilter<M> {
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.may_contain(ip) && self.matcher.contains(ip)
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        let cidrs: Vec<Cidr> = cidrs.into_iter().collect();
+        Prefilter::new(M::build_from(cidrs.iter().copied()), cidrs)
+    }
+}
+
+// A Prefilter counting what it rejects and passes on, to see how well it
+// fits the traffic. The shared counters cost every lookup an atomic add,
+// which threads looking up at once contend on, so the plain Prefilter
+// keeps none.
+pub struct PrefilterWithStats<M> {
+    filter: Prefilter<M>,
+    rejected: AtomicU64,
+    passed: AtomicU64,
+    matched: AtomicU64,
+}
+
+impl<M: IpMatcher> PrefilterWithStats<M> {
+    pub fn filter(&self) -> &Prefilter<M> {
+        &self.filter
+    }
+
+    pub fn into_filter(self) -> Prefilter<M> {
+        self.filter
+    }
+
+    pub fn stats(&self) -> PrefilterStats {
+        PrefilterStats {
+            rejected: self.rejected.load(Ordering::Relaxed),
+            passed: self.passed.load(Ordering::Relaxed),
+            matched: self.matched.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.rejected.store(0, Ordering::Relaxed);
+        self.passed.store(0, Ordering::Relaxed);
+        self.matched.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<M: IpMatcher> IpMatcher for PrefilterWithStats<M> {
+    fn contains(&self, ip: IpAddr) -> bool {
+        if !self.filter.may_contain(ip) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.passed.fetch_add(1, Ordering::Relaxed);
+        let matched = self.filter.matcher.contains(ip);
+        if matched {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+        matched
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        Prefilter::build_from(cidrs).with_stats()
+    }
+}
+
+#[test]
+fn test_prefilter() {
+    use crate::dual_stack::DualStackTrie;
+
+    let cidrs: Vec<Cidr> = [
+        "1.0.1.0/24",
+        "1.2.0.0/15",
+        "36.0.0.0/10",
+        "240e::/20",
+        "2400:da00::/32",
+        "2408:8000::/48",
+    ]
+    .iter()
+    .map(|s| s.parse().unwrap())
+    .collect();
+    let filter: Prefilter<DualStackTrie> = Prefilter::build_from(cidrs.iter().copied());
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    assert_eq!(filter.v6_lengths, vec![20, 32, 48]);
+    // whole /16s only, 1.0.2.1 shares the /16 of 1.0.1.0/24.
+    assert!(filter.may_contain(ip("1.0.2.1")));
+    assert!(!filter.may_contain(ip("36.64.0.1")));
+    assert!(!filter.may_contain(ip("8.8.8.8")));
+
+    let filter = filter.with_stats();
+    assert!(!filter.contains(ip("1.0.2.1")));
+    assert!(filter.contains(ip("1.3.255.255")));
+    assert!(filter.contains(ip("36.63.0.1")));
+    assert!(filter.contains(ip("240e:fff::1")));
+    assert!(filter.contains(ip("2408:8000:0:ffff::1")));
+    assert_eq!(
+        filter.stats(),
+        PrefilterStats {
+            rejected: 0,
+            passed: 5,
+            matched: 4
+        }
+    );
+    filter.reset_stats();
+
+    // misses are mostly rejected, and the answers stay the matcher's.
+    let mut random = crate::test_util::XorShift(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..20_000 {
+        let state = random.next();
+        let probe = if state & 1 == 0 {
+            IpAddr::V4(((state >> 32) as u32).into())
+        } else {
+            IpAddr::V6(((0x2400u128 << 112) | ((state as u128) << 64)).into())
+        };
+        assert_eq!(
+            filter.contains(probe),
+            filter.filter().matcher().contains(probe),
+            "{}",
+            probe
+        );
+    }
+    let stats = filter.stats();
+    assert_eq!(stats.rejected + stats.passed, 20_000);
+    assert!(stats.rejected > 19_000, "{:?}", stats);
+
+    // host bits set past the prefix mark the /16s of the whole cidr.
+    let unmasked: Prefilter<DualStackTrie> = Prefilter::build_from(vec![Cidr::V4(0xffff_0000, 8)]);
+    assert!(unmasked.may_contain(ip("255.0.0.1")));
+    assert!(unmasked.may_contain(ip("255.255.255.255")));
+    assert!(!unmasked.may_contain(ip("254.255.0.1")));
+
+    let empty: PrefilterWithStats<DualStackTrie> = PrefilterWithStats::build_from(vec![]);
+    assert!(!empty.contains(ip("::")));
+    assert!(!empty.contains(ip("0.0.0.0")));
+    assert_eq!(empty.stats().rejected, 2);
+}
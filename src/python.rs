@@ -0,0 +1,366 @@
+// Python bindings, built into a `cidr_matcher` extension module with maturin
+// (see pyproject.toml). Addresses are taken as strings, ipaddress objects
+// or ints, cidrs as strings.
+use crate::cidr::{self, Cidr};
+use crate::geoip_db::{GeoIpDatabase, GeoIpError};
+use crate::lpc_trie::LPCTrie;
+use numpy::{PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyInt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+fn cidr_arg(s: &str) -> PyResult<Cidr> {
+    s.parse()
+        .map_err(|e| PyValueError::new_err(format!("{}", e)))
+}
+
+fn cidrs_arg(cidrs: Vec<String>) -> PyResult<Vec<Cidr>> {
+    cidrs.iter().map(|s| cidr_arg(s)).collect()
+}
+
+fn cidr_strings(cidrs: Vec<Cidr>) -> Vec<String> {
+    cidrs.iter().map(|c| c.to_string()).collect()
+}
+
+fn ip_arg(ip: &Bound<'_, PyAny>) -> PyResult<IpAddr> {
+    ip.extract::<IpAddr>()
+        .map_err(|_| PyValueError::new_err(format!("invalid address: {}", ip)))
+}
+
+fn geoip_error(e: GeoIpError) -> PyErr {
+    match e {
+        GeoIpError::Io(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+// whether ips is a numpy array of integers. Other arrays, of strings or
+// objects, are read element by element like any iterable.
+fn is_int_array(ips: &Bound<'_, PyAny>) -> PyResult<bool> {
+    if !ips.hasattr("__array_interface__")? {
+        return Ok(false);
+    }
+    let kind: String = ips.getattr("dtype")?.getattr("kind")?.extract()?;
+    Ok(kind == "i" || kind == "u")
+}
+
+// an integer array cast to dtype, values outside 0..=max are an error
+// rather than wrapped.
+fn cast_int_array<'py>(
+    ips: &Bound<'py, PyAny>,
+    dtype: &str,
+    max: i128,
+) -> PyResult<Bound<'py, PyAny>> {
+    if ips.getattr("size")?.extract::<usize>()? > 0 {
+        let low: i128 = ips.call_method0("min")?.extract()?;
+        let high: i128 = ips.call_method0("max")?.extract()?;
+        if low < 0 || high > max {
+            return Err(PyValueError::new_err(format!(
+                "array values must lie in 0..={}",
+                max
+            )));
+        }
+    }
+    ips.call_method1("astype", (dtype,))
+}
+
+// The keys of a batch: a numpy integer array, one address per element for
+// IPv4 or an (n, 16) array of bytes in network order for IPv6, or any
+// iterable of addresses, string arrays included. Only integer arrays are
+// handed to numpy, lists work without it.
+fn batch_keys(
+    ips: &Bound<'_, PyAny>,
+    bits: u32,
+    key_of: fn(&Bound<'_, PyAny>) -> PyResult<u128>,
+) -> PyResult<Vec<u128>> {
+    if !is_int_array(ips)? {
+        let mut keys = vec![];
+        for ip in ips.try_iter()? {
+            keys.push(key_of(&ip?)?);
+        }
+        return Ok(keys);
+    }
+    if bits == 32 {
+        let ips = cast_int_array(ips, "uint32", u32::MAX as i128)?;
+        let array = ips.extract::<PyReadonlyArray1<'_, u32>>()?;
+        Ok(array.as_array().iter().map(|k| *k as u128).collect())
+    } else {
+        let ips = cast_int_array(ips, "uint8", u8::MAX as i128)?;
+        let array = ips.extract::<PyReadonlyArray2<'_, u8>>()?;
+        let array = array.as_array();
+        if array.ncols() != 16 {
+            return Err(PyValueError::new_err("IPv6 arrays need 16 columns"));
+        }
+        Ok(array
+            .rows()
+            .into_iter()
+            .map(|row| row.iter().fold(0u128, |k, b| k << 8 | *b as u128))
+            .collect())
+    }
+}
+
+macro_rules! py_trie {
+    ($name:ident, $py_name:literal, $T:ty, $V:ident, $bits:literal) => {
+        // A longest prefix match trie of one family mapping cidrs to strings.
+        #[pyclass(name = $py_name)]
+        pub struct $name {
+            trie: LPCTrie<$T>,
+        }
+
+        impl $name {
+            fn key(cidr: Cidr) -> PyResult<($T, u8)> {
+                match cidr {
+                    Cidr::$V(addr, prefix) => Ok((addr, prefix)),
+                    _ => Err(PyValueError::new_err(format!(
+                        "{} is not an IPv{} cidr",
+                        cidr,
+                        if $bits == 32 { 4 } else { 6 }
+                    ))),
+                }
+            }
+
+            // ints are taken as the address itself.
+            fn key_of(ip: &Bound<'_, PyAny>) -> PyResult<u128> {
+                if ip.is_instance_of::<PyInt>() {
+                    return Ok(ip.extract::<$T>()? as u128);
+                }
+                match ip_arg(ip)? {
+                    IpAddr::V4(ip) if $bits == 32 => Ok(u32::from(ip) as u128),
+                    IpAddr::V6(ip) if $bits == 128 => Ok(u128::from(ip)),
+                    ip => Err(PyValueError::new_err(format!(
+                        "{} is not an IPv{} address",
+                        ip,
+                        if $bits == 32 { 4 } else { 6 }
+                    ))),
+                }
+            }
+        }
+
+        #[pymethods]
+        impl $name {
+            #[new]
+            #[pyo3(signature = (cidrs=None, value=String::new()))]
+            fn new(cidrs: Option<Vec<String>>, value: String) -> PyResult<Self> {
+                let mut trie = $name {
+                    trie: LPCTrie::new(),
+                };
+                for cidr in cidrs.unwrap_or_default() {
+                    trie.put(&cidr, value.clone())?;
+                }
+                Ok(trie)
+            }
+
+            // a prefix that is already there gets the new value, as
+            // cidr_matcher_add does.
+            #[pyo3(signature = (cidr, value=String::new()))]
+            fn put(&mut self, cidr: &str, value: String) -> PyResult<()> {
+                let (key, prefix) = Self::key(cidr_arg(cidr)?)?;
+                self.trie.remove_prefix(key, prefix);
+                self.trie.put(key, prefix, value);
+                Ok(())
+            }
+
+            // drops exactly that prefix.
+            fn remove(&mut self, cidr: &str) -> PyResult<bool> {
+                let (key, prefix) = Self::key(cidr_arg(cidr)?)?;
+                Ok(self.trie.remove_prefix(key, prefix))
+            }
+
+            // the value of the longest prefix covering ip.
+            fn get(&self, ip: &Bound<'_, PyAny>) -> PyResult<Option<String>> {
+                let key = Self::key_of(ip)? as $T;
+                Ok(self.trie.get_value(key).cloned())
+            }
+
+            fn __contains__(&self, ip: &Bound<'_, PyAny>) -> PyResult<bool> {
+                Ok(self.trie.get(Self::key_of(ip)? as $T))
+            }
+
+            fn __len__(&self) -> usize {
+                self.trie.iter().count()
+            }
+
+            fn items(&self) -> Vec<(String, String)> {
+                self.trie
+                    .iter()
+                    .map(|(k, p, v)| (Cidr::$V(k, p).to_string(), v.clone()))
+                    .collect()
+            }
+
+            fn cidrs(&self) -> Vec<String> {
+                self.trie
+                    .iter()
+                    .map(|(k, p, _)| Cidr::$V(k, p).to_string())
+                    .collect()
+            }
+
+            // the minimal cidr list covering the same addresses.
+            fn aggregate(&self) -> Vec<String> {
+                cidr_strings(cidr::aggregate(
+                    self.trie.iter().map(|(k, p, _)| Cidr::$V(k, p)),
+                ))
+            }
+
+            // the addresses the trie does not cover, all holding value.
+            #[pyo3(signature = (value=String::new()))]
+            fn complement(&self, value: String) -> Self {
+                $name {
+                    trie: self.trie.complement(value),
+                }
+            }
+
+            fn lookup_many(&self, ips: &Bound<'_, PyAny>) -> PyResult<Vec<Option<String>>> {
+                let keys = batch_keys(ips, $bits, Self::key_of)?;
+                Ok(keys
+                    .into_iter()
+                    .map(|k| self.trie.get_value(k as $T).cloned())
+                    .collect())
+            }
+
+            fn contains_many<'py>(
+                &self,
+                py: Python<'py>,
+                ips: &Bound<'py, PyAny>,
+            ) -> PyResult<Bound<'py, PyArray1<bool>>> {
+                let keys = batch_keys(ips, $bits, Self::key_of)?;
+                let found =
+                    py.detach(|| keys.into_iter().map(|k| self.trie.get(k as $T)).collect());
+                Ok(PyArray1::from_vec(py, found))
+            }
+        }
+    };
+}
+
+py_trie!(PyIPv4Trie, "IPv4Trie", u32, V4, 32);
+py_trie!(PyIPv6Trie, "IPv6Trie", u128, V6, 128);
+
+// The country codes of a geoip.dat, see GeoIpDatabase.
+#[pyclass(name = "GeoIPDatabase")]
+pub struct PyGeoIpDatabase {
+    db: GeoIpDatabase,
+}
+
+#[pymethods]
+impl PyGeoIpDatabase {
+    // codes limits loading to the listed country codes.
+    #[staticmethod]
+    #[pyo3(signature = (path, codes=None))]
+    fn from_path(path: PathBuf, codes: Option<Vec<String>>) -> PyResult<Self> {
+        let db = match codes {
+            Some(codes) => {
+                let codes: Vec<&str> = codes.iter().map(|c| c.as_str()).collect();
+                GeoIpDatabase::from_path_with_codes(&path, &codes)
+            }
+            None => GeoIpDatabase::from_path(&path),
+        };
+        Ok(PyGeoIpDatabase {
+            db: db.map_err(geoip_error)?,
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (data, codes=None))]
+    fn from_bytes(data: &[u8], codes: Option<Vec<String>>) -> PyResult<Self> {
+        let db = match codes {
+            Some(codes) => {
+                let codes: Vec<&str> = codes.iter().map(|c| c.as_str()).collect();
+                GeoIpDatabase::from_bytes_with_codes(data, &codes)
+            }
+            None => GeoIpDatabase::from_bytes(data),
+        };
+        Ok(PyGeoIpDatabase {
+            db: db.map_err(geoip_error)?,
+        })
+    }
+
+    // the first loaded country code matching ip.
+    fn country_of(&self, ip: &Bound<'_, PyAny>) -> PyResult<Option<String>> {
+        Ok(self.db.country_of(ip_arg(ip)?).map(str::to_string))
+    }
+
+    // ips as batch_keys takes them, an array's shape tells its family.
+    fn country_of_many(&self, ips: &Bound<'_, PyAny>) -> PyResult<Vec<Option<String>>> {
+        let ips: Vec<IpAddr> = if !is_int_array(ips)? {
+            let mut addrs = vec![];
+            for ip in ips.try_iter()? {
+                addrs.push(ip_arg(&ip?)?);
+            }
+            addrs
+        } else if ips.getattr("ndim")?.extract::<usize>()? == 1 {
+            batch_keys(ips, 32, PyIPv4Trie::key_of)?
+                .into_iter()
+                .map(|k| IpAddr::from(Ipv4Addr::from(k as u32)))
+                .collect()
+        } else {
+            batch_keys(ips, 128, PyIPv6Trie::key_of)?
+                .into_iter()
+                .map(|k| IpAddr::from(Ipv6Addr::from(k)))
+                .collect()
+        };
+        Ok(ips
+            .into_iter()
+            .map(|ip| self.db.country_of(ip).map(str::to_string))
+            .collect())
+    }
+
+    fn contains(&self, country_code: &str, ip: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.db.contains(country_code, ip_arg(ip)?))
+    }
+
+    fn country_codes(&self) -> Vec<String> {
+        self.db.country_codes().map(str::to_string).collect()
+    }
+
+    // the cidrs loaded for the code, reverse_match is not applied.
+    fn cidrs(&self, country_code: &str) -> PyResult<Vec<String>> {
+        match self.db.get(country_code) {
+            Some(trie) => Ok(trie.iter().map(|(c, _)| c.to_string()).collect()),
+            None => Err(PyKeyError::new_err(format!(
+                "{} is not loaded",
+                country_code
+            ))),
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.db.len()
+    }
+}
+
+// the minimal sorted cidr list covering the same addresses, IPv4 first.
+#[pyfunction]
+fn aggregate(cidrs: Vec<String>) -> PyResult<Vec<String>> {
+    Ok(cidr_strings(cidr::aggregate(cidrs_arg(cidrs)?)))
+}
+
+#[pyfunction]
+fn union(a: Vec<String>, b: Vec<String>) -> PyResult<Vec<String>> {
+    Ok(cidr_strings(cidr::union(cidrs_arg(a)?, cidrs_arg(b)?)))
+}
+
+#[pyfunction]
+fn intersection(a: Vec<String>, b: Vec<String>) -> PyResult<Vec<String>> {
+    Ok(cidr_strings(cidr::intersection(
+        cidrs_arg(a)?,
+        cidrs_arg(b)?,
+    )))
+}
+
+#[pyfunction]
+fn difference(a: Vec<String>, b: Vec<String>) -> PyResult<Vec<String>> {
+    Ok(cidr_strings(cidr::difference(cidrs_arg(a)?, cidrs_arg(b)?)))
+}
+
+#[pymodule]
+fn cidr_matcher(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyIPv4Trie>()?;
+    m.add_class::<PyIPv6Trie>()?;
+    m.add_class::<PyGeoIpDatabase>()?;
+    m.add_function(wrap_pyfunction!(aggregate, m)?)?;
+    m.add_function(wrap_pyfunction!(union, m)?)?;
+    m.add_function(wrap_pyfunction!(intersection, m)?)?;
+    m.add_function(wrap_pyfunction!(difference, m)?)?;
+    Ok(())
+}
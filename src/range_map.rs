@@ -0,0 +1,354 @@
+use crate::bit_vec::BitVec;
+use crate::cidr::{host_mask, range_to_prefixes, Cidr};
+use crate::lpc_trie::LPCTrie;
+use crate::matcher::IpMatcher;
+use std::hash::Hash;
+use std::net::IpAddr;
+
+// Values over arbitrary [first, last] address ranges, kept as sorted and
+// disjoint intervals. Adjacent intervals of equal value are merged, so the
+// map stays as small as the data allows. IpRangeMap<u32, V> holds IPv4
+// ranges, IpRangeMap<u128, V> IPv6 ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRangeMap<T, V = String> {
+    ranges: Vec<(T, T, V)>,
+}
+
+impl<T: BitVec + Ord, V: Clone + PartialEq> Default for IpRangeMap<T, V> {
+    fn default() -> Self {
+        IpRangeMap::new()
+    }
+}
+
+impl<T: BitVec + Ord, V: Clone + PartialEq> IpRangeMap<T, V> {
+    pub fn new() -> IpRangeMap<T, V> {
+        IpRangeMap { ranges: vec![] }
+    }
+
+    // set value over first..=last, overwriting what the range held before.
+    pub fn insert(&mut self, first: T, last: T, value: V) {
+        if first > last {
+            return;
+        }
+        self.remove(first, last);
+        let mut idx = self.ranges.partition_point(|r| r.0 < first);
+        self.ranges.insert(idx, (first, last, value));
+        if idx + 1 < self.ranges.len() && self.mergeable(idx) {
+            let (_, last, _) = self.ranges.remove(idx + 1);
+            self.ranges[idx].1 = last;
+        }
+        if idx > 0 && self.mergeable(idx - 1) {
+            idx -= 1;
+            let (_, last, _) = self.ranges.remove(idx + 1);
+            self.ranges[idx].1 = last;
+        }
+    }
+
+    pub fn insert_prefix(&mut self, key: T, prefix: u8, value: V) {
+        let host = host_mask(T::bits() - prefix as u32);
+        let first = key.to_u128() & !host;
+        self.insert(T::from_u128(first), T::from_u128(first | host), value);
+    }
+
+    // whether interval idx and the next one touch and hold the same value.
+    fn mergeable(&self, idx: usize) -> bool {
+        let (_, last, value) = &self.ranges[idx];
+        let (first, _, next) = &self.ranges[idx + 1];
+        last.to_u128() + 1 == first.to_u128() && value == next
+    }
+
+    // clear first..=last, returns whether anything was there.
+    pub fn remove(&mut self, first: T, last: T) -> bool {
+        let start = self.ranges.partition_point(|r| r.1 < first);
+        let end = self.ranges.partition_point(|r| r.0 <= last);
+        if start >= end {
+            return false;
+        }
+        // the parts of the outer intervals sticking out of the range.
+        let mut keep = vec![];
+        let (head, _, value) = &self.ranges[start];
+        if *head < first {
+            keep.push((*head, T::from_u128(first.to_u128() - 1), value.clone()));
+        }
+        let (_, tail, value) = &self.ranges[end - 1];
+        if *tail > last {
+            keep.push((T::from_u128(last.to_u128() + 1), *tail, value.clone()));
+        }
+        self.ranges.splice(start..end, keep);
+        true
+    }
+
+    pub fn get(&self, key: T) -> Option<&V> {
+        let idx = self.ranges.partition_point(|r| r.0 <= key);
+        match self.ranges.get(idx.checked_sub(1)?) {
+            Some((_, last, value)) if key <= *last => Some(value),
+            _ => None,
+        }
+    }
+
+    // the intervals sharing an address with first..=last, unclipped.
+    pub fn overlapping(&self, first: T, last: T) -> &[(T, T, V)] {
+        let start = self.ranges.partition_point(|r| r.1 < first);
+        let end = self.ranges.partition_point(|r| r.0 <= last);
+        if start >= end {
+            return &[];
+        }
+        &self.ranges[start..end]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (T, T, &V)> {
+        self.ranges
+            .iter()
+            .map(|(first, last, value)| (*first, *last, value))
+    }
+
+    // number of disjoint intervals.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // Prefixes overlapping a longer one give way to it, as a longest prefix
+    // match would answer, whatever order they come in. Of equal prefixes the
+    // last one wins. One sweep over the prefixes sorted by address, with the
+    // prefixes enclosing the current one on a stack.
+    pub fn from_prefixes<I: IntoIterator<Item = (T, u8, V)>>(prefixes: I) -> IpRangeMap<T, V> {
+        let mut ranges: Vec<(u128, u128, V)> = prefixes
+            .into_iter()
+            .map(|(key, prefix, value)| {
+                let host = host_mask(T::bits() - prefix as u32);
+                let first = key.to_u128() & !host;
+                (first, first | host, value)
+            })
+            .collect();
+        // enclosing prefixes before the ones they hold.
+        ranges.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let mut map = IpRangeMap::new();
+        // (last, value) of the enclosing prefixes, innermost on top.
+        let mut stack: Vec<(u128, V)> = vec![];
+        // the first address not in the map yet, None past the last address.
+        let mut pos = Some(0);
+        for (first, last, value) in ranges {
+            while let Some((end, outer)) = stack.last() {
+                if *end >= first {
+                    break;
+                }
+                map.push_range(pos, *end, outer);
+                pos = end.checked_add(1);
+                stack.pop();
+            }
+            if let (Some((_, outer)), Some(end)) = (stack.last(), first.checked_sub(1)) {
+                map.push_range(pos, end, outer);
+            }
+            pos = Some(first);
+            match stack.last_mut() {
+                Some(top) if top.0 == last => top.1 = value,
+                _ => stack.push((last, value)),
+            }
+        }
+        while let Some((end, outer)) = stack.pop() {
+            map.push_range(pos, end, &outer);
+            pos = end.checked_add(1);
+        }
+        map
+    }
+
+    // append first..=last after the intervals there are, merging it into the
+    // last one when they touch and hold the same value.
+    fn push_range(&mut self, first: Option<u128>, last: u128, value: &V) {
+        let first = match first {
+            Some(first) if first <= last => first,
+            _ => return,
+        };
+        match self.ranges.last_mut() {
+            Some((_, end, v)) if end.to_u128() + 1 == first && v == value => {
+                *end = T::from_u128(last)
+            }
+            _ => self
+                .ranges
+                .push((T::from_u128(first), T::from_u128(last), value.clone())),
+        }
+    }
+
+    // the minimal sorted (key, prefix, value) list covering the map.
+    pub fn to_prefixes(&self) -> Vec<(T, u8, V)> {
+        let mut prefixes = vec![];
+        for (first, last, value) in self.ranges.iter() {
+            for (key, prefix) in range_to_prefixes(first.to_u128(), last.to_u128(), T::bits()) {
+                prefixes.push((T::from_u128(key), prefix, value.clone()));
+            }
+        }
+        prefixes
+    }
+
+    // cidrs of the other family are left out.
+    pub fn from_cidrs<I: IntoIterator<Item = (Cidr, V)>>(cidrs: I) -> IpRangeMap<T, V> {
+        IpRangeMap::from_prefixes(cidrs.into_iter().filter_map(|(cidr, value)| match cidr {
+            Cidr::V4(addr, prefix) if T::bits() == 32 => {
+                Some((T::from_u128(addr as u128), prefix, value))
+            }
+            Cidr::V6(addr, prefix) if T::bits() == 128 => Some((T::from_u128(addr), prefix, value)),
+            _ => None,
+        }))
+    }
+
+    pub fn to_cidrs(&self) -> Vec<(Cidr, V)> {
+        self.to_prefixes()
+            .into_iter()
+            .map(|(key, prefix, value)| {
+                let cidr = if T::bits() == 32 {
+                    Cidr::V4(key.to_u128() as u32, prefix)
+                } else {
+                    Cidr::V6(key.to_u128(), prefix)
+                };
+                (cidr, value)
+            })
+            .collect()
+    }
+
+    fn key_of(ip: IpAddr) -> Option<T> {
+        match ip {
+            IpAddr::V4(ip) if T::bits() == 32 => Some(T::from_u128(u32::from(ip) as u128)),
+            IpAddr::V6(ip) if T::bits() == 128 => Some(T::from_u128(ip.into())),
+            _ => None,
+        }
+    }
+
+    pub fn get_ip(&self, ip: IpAddr) -> Option<&V> {
+        self.get(IpRangeMap::<T, V>::key_of(ip)?)
+    }
+}
+
+impl<T: BitVec + Ord, V: Clone + Eq + Hash> IpRangeMap<T, V> {
+    pub fn from_trie(trie: &LPCTrie<T, V>) -> IpRangeMap<T, V> {
+        IpRangeMap::from_prefixes(
+            trie.iter()
+                .map(|(key, prefix, value)| (key, prefix, value.clone())),
+        )
+    }
+
+    pub fn to_trie(&self) -> LPCTrie<T, V> {
+        let mut trie = LPCTrie::new();
+        for (key, prefix, value) in self.to_prefixes() {
+            trie.put(key, prefix, value);
+        }
+        trie
+    }
+}
+
+impl<T: BitVec + Ord, V: Clone + PartialEq + Default> IpMatcher for IpRangeMap<T, V> {
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.get_ip(ip).is_some()
+    }
+
+    fn build_from<I: IntoIterator<Item = Cidr>>(cidrs: I) -> Self {
+        IpRangeMap::from_cidrs(cidrs.into_iter().map(|c| (c, V::default())))
+    }
+}
+
+#[test]
+fn test_range_map() {
+    let ip = |s: &str| u32::from(s.parse::<std::net::Ipv4Addr>().unwrap());
+    let value = |s: &str| s.to_string();
+
+    let mut map: IpRangeMap<u32> = IpRangeMap::new();
+    map.insert(ip("1.0.0.5"), ip("1.0.0.200"), value("A"));
+    map.insert(ip("1.0.0.201"), ip("1.0.1.10"), value("A"));
+    assert_eq!(map.len(), 1);
+    // overlapping inserts cut the older ranges.
+    map.insert(ip("1.0.0.100"), ip("1.0.0.150"), value("B"));
+    map.insert(ip("1.0.0.0"), ip("1.0.0.9"), value("C"));
+    assert_eq!(
+        map.iter()
+            .map(|(f, l, v)| (f, l, v.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            (ip("1.0.0.0"), ip("1.0.0.9"), "C"),
+            (ip("1.0.0.10"), ip("1.0.0.99"), "A"),
+            (ip("1.0.0.100"), ip("1.0.0.150"), "B"),
+            (ip("1.0.0.151"), ip("1.0.1.10"), "A"),
+        ]
+    );
+    assert_eq!(map.get(ip("1.0.0.4")).unwrap(), "C");
+    assert_eq!(map.get(ip("1.0.0.99")).unwrap(), "A");
+    assert_eq!(map.get(ip("1.0.0.150")).unwrap(), "B");
+    assert_eq!(map.get(ip("1.0.1.11")), None);
+    assert_eq!(map.get_ip("1.0.0.120".parse().unwrap()).unwrap(), "B");
+    assert_eq!(map.get_ip("::1".parse().unwrap()), None);
+
+    assert_eq!(map.overlapping(ip("1.0.0.50"), ip("1.0.0.100")).len(), 2);
+    assert_eq!(map.overlapping(ip("1.0.1.10"), ip("2.0.0.0")).len(), 1);
+    assert!(map.overlapping(ip("1.0.1.11"), ip("2.0.0.0")).is_empty());
+
+    // filling the gap again merges the neighbours back.
+    map.insert(ip("1.0.0.100"), ip("1.0.0.150"), value("A"));
+    assert_eq!(map.len(), 2);
+    assert!(map.remove(ip("1.0.0.0"), ip("1.0.0.15")));
+    assert!(!map.remove(ip("1.0.0.0"), ip("1.0.0.15")));
+    assert_eq!(map.get(ip("1.0.0.16")).unwrap(), "A");
+
+    // 1.0.0.16 - 1.0.1.10 as cidrs.
+    let cidrs: Vec<String> = map.to_cidrs().iter().map(|(c, _)| c.to_string()).collect();
+    assert_eq!(
+        cidrs,
+        vec![
+            "1.0.0.16/28",
+            "1.0.0.32/27",
+            "1.0.0.64/26",
+            "1.0.0.128/25",
+            "1.0.1.0/29",
+            "1.0.1.8/31",
+            "1.0.1.10/32"
+        ]
+    );
+    let back = IpRangeMap::from_cidrs(map.to_cidrs());
+    assert_eq!(back, map);
+
+    // the longer prefix wins, as in the trie.
+    let mut trie = LPCTrie::<u128>::new();
+    trie.put(0x2001_0db8 << 96, 32, value("A"));
+    trie.put(0x2001_0db8_0001 << 80, 48, value("B"));
+    let map = IpRangeMap::from_trie(&trie);
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get_ip("2001:db8:1::1".parse().unwrap()).unwrap(), "B");
+    assert_eq!(map.get_ip("2001:db8:2::1".parse().unwrap()).unwrap(), "A");
+    let trie = map.to_trie();
+    assert_eq!(trie.get_with_value(0x2001_0db8_0001 << 80 | 1), "B");
+    assert_eq!(trie.get_with_value(0x2001_0db8_ffff << 80), "A");
+    assert_eq!(trie.iter().count(), 17);
+
+    // nested, touching and repeated prefixes, against inserts by length.
+    let prefixes = vec![
+        (ip("10.0.0.0"), 8, value("A")),
+        (ip("10.128.0.0"), 9, value("B")),
+        (ip("10.1.0.0"), 16, value("C")),
+        (ip("10.1.2.0"), 24, value("A")),
+        (ip("10.1.3.0"), 24, value("A")),
+        (ip("10.1.0.0"), 16, value("D")),
+        (ip("11.0.0.0"), 8, value("B")),
+        (ip("0.0.0.0"), 0, value("E")),
+        (ip("255.255.255.255"), 32, value("F")),
+    ];
+    let map = IpRangeMap::from_prefixes(prefixes.clone());
+    let mut by_length: IpRangeMap<u32> = IpRangeMap::new();
+    let mut sorted = prefixes;
+    sorted.sort_by_key(|p| p.1);
+    for (key, prefix, value) in sorted {
+        by_length.insert_prefix(key, prefix, value);
+    }
+    assert_eq!(map, by_length);
+    assert_eq!(map.get(ip("10.1.1.1")).unwrap(), "D");
+    assert_eq!(map.get(ip("10.200.0.0")).unwrap(), "B");
+    assert_eq!(map.get(ip("11.0.0.0")).unwrap(), "B");
+    assert_eq!(map.len(), 9);
+
+    let mut full: IpRangeMap<u128> = IpRangeMap::new();
+    full.insert(0, u128::MAX, value("all"));
+    full.insert(u128::MAX, u128::MAX, value("last"));
+    assert_eq!(full.len(), 2);
+    assert_eq!(full.to_prefixes().len(), 129);
+    assert_eq!(full.get(u128::MAX).unwrap(), "last");
+}
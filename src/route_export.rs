@@ -0,0 +1,291 @@
+use crate::cidr::Cidr;
+use crate::dual_stack::DualStackTrie;
+use crate::lpc_trie::LPCTrie;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NextHop {
+    Gateway(IpAddr),
+    Device(String),
+    GatewayDevice(IpAddr, String),
+    Blackhole,
+    Unreachable,
+}
+
+// the gateway of an `ip route` command, an IPv6 gateway of an IPv4 route
+// (RFC 5549) needs its family spelled out.
+struct Via<'a>(&'a Cidr, &'a IpAddr);
+
+impl fmt::Display for Via<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_ipv4() && self.1.is_ipv6() {
+            write!(f, "via inet6 {}", self.1)
+        } else {
+            write!(f, "via {}", self.1)
+        }
+    }
+}
+
+// the "via .. dev .." part of an `ip route` command.
+struct IpRoute<'a>(&'a Cidr, &'a NextHop);
+
+impl fmt::Display for IpRoute<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            NextHop::Gateway(gw) => write!(f, "{} {}", self.0, Via(self.0, gw)),
+            NextHop::Device(dev) => write!(f, "{} dev {}", self.0, dev),
+            NextHop::GatewayDevice(gw, dev) => {
+                write!(f, "{} {} dev {}", self.0, Via(self.0, gw), dev)
+            }
+            NextHop::Blackhole => write!(f, "blackhole {}", self.0),
+            NextHop::Unreachable => write!(f, "unreachable {}", self.0),
+        }
+    }
+}
+
+// the route statement of a BIRD 2 static protocol.
+struct BirdRoute<'a>(&'a Cidr, &'a NextHop);
+
+impl fmt::Display for BirdRoute<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            NextHop::Gateway(gw) => write!(f, "route {} via {};", self.0, gw),
+            NextHop::Device(dev) => write!(f, "route {} via \"{}\";", self.0, dev),
+            NextHop::GatewayDevice(gw, dev) => write!(f, "route {} via {}%{};", self.0, gw, dev),
+            NextHop::Blackhole => write!(f, "route {} blackhole;", self.0),
+            NextHop::Unreachable => write!(f, "route {} unreachable;", self.0),
+        }
+    }
+}
+
+// Routes taken from a trie, the caller maps each value to its next hop so the
+// exported routes agree with what the trie matches. Values mapped to None are
+// left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteExport {
+    routes: Vec<(Cidr, NextHop)>,
+}
+
+impl RouteExport {
+    pub fn new() -> RouteExport {
+        RouteExport::default()
+    }
+
+    pub fn add_v4_trie<F>(&mut self, trie: &LPCTrie<u32>, next_hop: F)
+    where
+        F: FnMut(&str) -> Option<NextHop>,
+    {
+        let entries = trie.iter().map(|(k, p, v)| (Cidr::V4(k, p), v.as_str()));
+        self.add_entries(entries, next_hop);
+    }
+
+    pub fn add_v6_trie<F>(&mut self, trie: &LPCTrie<u128>, next_hop: F)
+    where
+        F: FnMut(&str) -> Option<NextHop>,
+    {
+        let entries = trie.iter().map(|(k, p, v)| (Cidr::V6(k, p), v.as_str()));
+        self.add_entries(entries, next_hop);
+    }
+
+    pub fn add_dual_stack<F>(&mut self, trie: &DualStackTrie, next_hop: F)
+    where
+        F: FnMut(&str) -> Option<NextHop>,
+    {
+        self.add_entries(trie.iter(), next_hop);
+    }
+
+    fn add_entries<'a, I, F>(&mut self, entries: I, mut next_hop: F)
+    where
+        I: Iterator<Item = (Cidr, &'a str)>,
+        F: FnMut(&str) -> Option<NextHop>,
+    {
+        for (cidr, value) in entries {
+            if let Some(hop) = next_hop(value) {
+                self.routes.push((cidr, hop));
+            }
+        }
+    }
+
+    pub fn routes(&self) -> &[(Cidr, NextHop)] {
+        &self.routes
+    }
+
+    // Neither the kernel nor BIRD has IPv6 routes via IPv4 gateways, the
+    // writers refuse them with an InvalidInput error before writing anything.
+    fn check_gateway_families(&self) -> io::Result<()> {
+        for (cidr, hop) in self.routes.iter() {
+            if let NextHop::Gateway(gw) | NextHop::GatewayDevice(gw, _) = hop {
+                if !cidr.is_ipv4() && gw.is_ipv4() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("IPv6 route {} via IPv4 gateway {}", cidr, gw),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `ip -batch` input, "route replace" keeps a second push idempotent.
+    pub fn write_ip_batch<W: Write>(&self, w: &mut W, table: Option<&str>) -> io::Result<()> {
+        self.check_gateway_families()?;
+        for (cidr, hop) in self.routes.iter() {
+            write!(w, "route replace {}", IpRoute(cidr, hop))?;
+            if let Some(table) = table {
+                write!(w, " table {}", table)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    // BIRD 2 `protocol static` blocks, name4 for the IPv4 routes and name6 for
+    // the IPv6 ones. A family without routes gets no block.
+    pub fn write_bird<W: Write>(&self, w: &mut W, name: &str) -> io::Result<()> {
+        self.check_gateway_families()?;
+        for (ipv4, channel, suffix) in [(true, "ipv4", "4"), (false, "ipv6", "6")].iter() {
+            let mut routes = self
+                .routes
+                .iter()
+                .filter(|(c, _)| c.is_ipv4() == *ipv4)
+                .peekable();
+            if routes.peek().is_none() {
+                continue;
+            }
+            writeln!(w, "protocol static {}{} {{", name, suffix)?;
+            writeln!(w, "\t{};", channel)?;
+            for (cidr, hop) in routes {
+                writeln!(w, "\t{}", BirdRoute(cidr, hop))?;
+            }
+            writeln!(w, "}}")?;
+        }
+        Ok(())
+    }
+
+    // Cisco and FRR prefix-list stanzas permitting every route, numbered by
+    // fives per family so entries can be inserted by hand later.
+    pub fn write_prefix_list<W: Write>(&self, w: &mut W, name: &str) -> io::Result<()> {
+        let (mut seq4, mut seq6) = (0, 0);
+        for (cidr, _) in self.routes.iter() {
+            let (family, seq) = if cidr.is_ipv4() {
+                ("ip", &mut seq4)
+            } else {
+                ("ipv6", &mut seq6)
+            };
+            *seq += 5;
+            writeln!(
+                w,
+                "{} prefix-list {} seq {} permit {}",
+                family, name, seq, cidr
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_route_export() {
+    let mut trie = DualStackTrie::new();
+    trie.put("1.0.1.0/24".parse().unwrap(), "CN".to_string());
+    trie.put("1.0.2.0/23".parse().unwrap(), "CN".to_string());
+    trie.put("10.0.0.0/8".parse().unwrap(), "LAN".to_string());
+    trie.put("8.8.8.0/24".parse().unwrap(), "US".to_string());
+    trie.put("2400:da00::/32".parse().unwrap(), "CN".to_string());
+    let gateway: IpAddr = "192.168.1.1".parse().unwrap();
+    let next_hop = |value: &str| match value {
+        "CN" => Some(NextHop::Gateway(gateway)),
+        "LAN" => Some(NextHop::Device("eth1".to_string())),
+        _ => None,
+    };
+
+    let mut export = RouteExport::new();
+    export.add_dual_stack(&trie, next_hop);
+    assert_eq!(export.routes().len(), 4);
+    match export.write_ip_batch(&mut vec![], None) {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "IPv6 route 2400:da00::/32 via IPv4 gateway 192.168.1.1"
+        ),
+        _ => panic!("expected a gateway family mismatch"),
+    }
+    let mut out = vec![];
+    match export.write_bird(&mut out, "cn") {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "IPv6 route 2400:da00::/32 via IPv4 gateway 192.168.1.1"
+        ),
+        _ => panic!("expected a gateway family mismatch"),
+    }
+    assert!(out.is_empty());
+
+    let mut export = RouteExport::new();
+    export.add_v4_trie(trie.v4(), next_hop);
+    export.add_v6_trie(trie.v6(), |_| {
+        Some(NextHop::Gateway("2001:db8::1".parse().unwrap()))
+    });
+    let mut out = vec![];
+    export.write_ip_batch(&mut out, Some("100")).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "route replace 1.0.1.0/24 via 192.168.1.1 table 100\n\
+         route replace 1.0.2.0/23 via 192.168.1.1 table 100\n\
+         route replace 10.0.0.0/8 dev eth1 table 100\n\
+         route replace 2400:da00::/32 via 2001:db8::1 table 100\n"
+    );
+
+    let mut unnumbered = RouteExport::new();
+    unnumbered.add_v4_trie(trie.v4(), |_| {
+        Some(NextHop::GatewayDevice(
+            "fe80::1".parse().unwrap(),
+            "eth0".to_string(),
+        ))
+    });
+    let mut out = vec![];
+    unnumbered.write_ip_batch(&mut out, None).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .starts_with("route replace 1.0.1.0/24 via inet6 fe80::1 dev eth0\n"));
+
+    let mut other = RouteExport::new();
+    other.add_v6_trie(trie.v6(), |_| {
+        Some(NextHop::GatewayDevice(
+            "fe80::1".parse().unwrap(),
+            "eth0".to_string(),
+        ))
+    });
+    other.add_v4_trie(trie.v4(), |v| match v {
+        "US" => Some(NextHop::Blackhole),
+        _ => None,
+    });
+    let mut out = vec![];
+    other.write_bird(&mut out, "cn").unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "protocol static cn4 {\n\
+         \tipv4;\n\
+         \troute 8.8.8.0/24 blackhole;\n\
+         }\n\
+         protocol static cn6 {\n\
+         \tipv6;\n\
+         \troute 2400:da00::/32 via fe80::1%eth0;\n\
+         }\n"
+    );
+
+    let mut out = vec![];
+    export.write_bird(&mut out, "cn").unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .contains("\troute 10.0.0.0/8 via \"eth1\";\n"));
+
+    let mut out = vec![];
+    export.write_prefix_list(&mut out, "CN").unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "ip prefix-list CN seq 5 permit 1.0.1.0/24\n\
+         ip prefix-list CN seq 10 permit 1.0.2.0/23\n\
+         ip prefix-list CN seq 15 permit 10.0.0.0/8\n\
+         ipv6 prefix-list CN seq 5 permit 2400:da00::/32\n"
+    );
+}
@@ -0,0 +1,302 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::LPCTrie;
+use crate::route_export::NextHop;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// One candidate route of a prefix. source names what installed it (static,
+// bgp, ospf, ...), a source adds one route per next hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub next_hop: NextHop,
+    pub source: String,
+    // administrative distance, lower is preferred over any metric.
+    pub distance: u8,
+    pub metric: u32,
+}
+
+impl Route {
+    pub fn new(next_hop: NextHop, source: &str, distance: u8, metric: u32) -> Route {
+        Route {
+            next_hop,
+            source: source.to_string(),
+            distance,
+            metric,
+        }
+    }
+
+    fn cost(&self) -> (u8, u32) {
+        (self.distance, self.metric)
+    }
+}
+
+// The fields ECMP hashes on, so one flow always takes the same next hop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowTuple {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: u8,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl FlowTuple {
+    // FNV-1a rather than the std hasher, whose output may change between
+    // releases and would move flows after an upgrade.
+    pub fn hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for b in bytes {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for ip in [self.src, self.dst].iter() {
+            match ip {
+                IpAddr::V4(v4) => feed(&v4.octets()),
+                IpAddr::V6(v6) => feed(&v6.octets()),
+            }
+        }
+        feed(&[self.protocol]);
+        feed(&self.src_port.to_be_bytes());
+        feed(&self.dst_port.to_be_bytes());
+        hash
+    }
+}
+
+// A RIB keeping every route of a prefix. LPCTrie holds one value per prefix,
+// so the tries map a prefix to its slot in sets and the routes live there,
+// ordered by (distance, metric) with the best first.
+pub struct RouteTable {
+    v4: LPCTrie<u32, usize>,
+    v6: LPCTrie<u128, usize>,
+    slots: HashMap<Cidr, usize>,
+    sets: Vec<Vec<Route>>,
+    // slots of prefixes whose last route was removed.
+    free: Vec<usize>,
+}
+
+impl Default for RouteTable {
+    fn default() -> Self {
+        RouteTable::new()
+    }
+}
+
+impl RouteTable {
+    pub fn new() -> RouteTable {
+        RouteTable {
+            v4: LPCTrie::new(),
+            v6: LPCTrie::new(),
+            slots: HashMap::new(),
+            sets: vec![],
+            free: vec![],
+        }
+    }
+
+    // add route to cidr, replacing the one of the same source and next hop.
+    // Returns false when a route was replaced.
+    pub fn add(&mut self, cidr: Cidr, route: Route) -> bool {
+        let slot = match self.slots.get(&cidr) {
+            Some(slot) => *slot,
+            None => {
+                let slot = match self.free.pop() {
+                    Some(slot) => slot,
+                    None => {
+                        self.sets.push(vec![]);
+                        self.sets.len() - 1
+                    }
+                };
+                match cidr {
+                    Cidr::V4(addr, prefix) => self.v4.put(addr, prefix, slot),
+                    Cidr::V6(addr, prefix) => self.v6.put(addr, prefix, slot),
+                }
+                self.slots.insert(cidr, slot);
+                slot
+            }
+        };
+        let set = &mut self.sets[slot];
+        let old = set
+            .iter()
+            .position(|r| r.source == route.source && r.next_hop == route.next_hop);
+        if let Some(idx) = old {
+            set.remove(idx);
+        }
+        // after the routes of equal cost, so earlier routes keep their order.
+        let idx = set.partition_point(|r| r.cost() <= route.cost());
+        set.insert(idx, route);
+        old.is_none()
+    }
+
+    pub fn remove(&mut self, cidr: Cidr, source: &str, next_hop: &NextHop) -> bool {
+        let slot = match self.slots.get(&cidr) {
+            Some(slot) => *slot,
+            None => return false,
+        };
+        let set = &mut self.sets[slot];
+        let len = set.len();
+        set.retain(|r| r.source != source || r.next_hop != *next_hop);
+        if set.len() == len {
+            return false;
+        }
+        if set.is_empty() {
+            match cidr {
+                Cidr::V4(addr, prefix) => self.v4.remove_prefix(addr, prefix),
+                Cidr::V6(addr, prefix) => self.v6.remove_prefix(addr, prefix),
+            };
+            self.slots.remove(&cidr);
+            self.free.push(slot);
+        }
+        true
+    }
+
+    // every route of exactly cidr, best first.
+    pub fn routes(&self, cidr: Cidr) -> &[Route] {
+        match self.slots.get(&cidr) {
+            Some(slot) => &self.sets[*slot],
+            None => &[],
+        }
+    }
+
+    // all routes of the longest prefix covering ip, best first.
+    pub fn lookup_all(&self, ip: IpAddr) -> &[Route] {
+        let slot = match ip {
+            IpAddr::V4(v4) => self.v4.get_value(u32::from(v4)),
+            IpAddr::V6(v6) => self.v6.get_value(u128::from(v6)),
+        };
+        match slot {
+            Some(slot) => &self.sets[*slot],
+            None => &[],
+        }
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&Route> {
+        self.lookup_all(ip).first()
+    }
+
+    // the routes sharing the best distance and metric, the ECMP group.
+    pub fn next_hops(&self, ip: IpAddr) -> &[Route] {
+        let routes = self.lookup_all(ip);
+        match routes.first() {
+            Some(best) => {
+                let n = routes.partition_point(|r| r.cost() == best.cost());
+                &routes[..n]
+            }
+            None => routes,
+        }
+    }
+
+    // the member of the ECMP group towards flow.dst that the flow hashes to.
+    pub fn select(&self, flow: &FlowTuple) -> Option<&Route> {
+        let group = self.next_hops(flow.dst);
+        if group.is_empty() {
+            return None;
+        }
+        group.get((flow.hash() % group.len() as u64) as usize)
+    }
+
+    // number of prefixes with at least one route.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Cidr, &[Route])> {
+        let v4 = self
+            .v4
+            .iter()
+            .map(|(addr, prefix, slot)| (Cidr::V4(addr, prefix), *slot));
+        let v6 = self
+            .v6
+            .iter()
+            .map(|(addr, prefix, slot)| (Cidr::V6(addr, prefix), *slot));
+        v4.chain(v6)
+            .map(move |(cidr, slot)| (cidr, self.sets[slot].as_slice()))
+    }
+}
+
+#[test]
+fn test_route_table() {
+    let gw = |s: &str| NextHop::Gateway(s.parse().unwrap());
+    let cidr = |s: &str| s.parse::<Cidr>().unwrap();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let route = |hop: &str, source: &str, distance: u8, metric: u32| {
+        Route::new(gw(hop), source, distance, metric)
+    };
+
+    let mut table = RouteTable::new();
+    assert!(table.add(cidr("0.0.0.0/0"), route("192.0.2.1", "static", 1, 0)));
+    assert!(table.add(cidr("10.0.0.0/8"), route("10.0.0.1", "ospf", 110, 20)));
+    assert!(table.add(cidr("10.0.0.0/8"), route("10.0.0.2", "ospf", 110, 10)));
+    assert!(table.add(cidr("10.0.0.0/8"), route("10.0.0.3", "ospf", 110, 10)));
+    assert!(table.add(cidr("10.0.0.0/8"), route("10.0.0.9", "bgp", 200, 0)));
+    assert!(table.add(
+        cidr("2001:db8::/32"),
+        Route::new(NextHop::Blackhole, "static", 1, 0)
+    ));
+    assert_eq!(table.len(), 3);
+
+    // distance wins over metric, metric breaks ties within a distance.
+    let best = table.lookup(ip("10.1.2.3")).unwrap();
+    assert_eq!(best.next_hop, gw("10.0.0.2"));
+    let group: Vec<_> = table
+        .next_hops(ip("10.1.2.3"))
+        .iter()
+        .map(|r| r.next_hop.clone())
+        .collect();
+    assert_eq!(group, vec![gw("10.0.0.2"), gw("10.0.0.3")]);
+    assert_eq!(table.lookup_all(ip("10.1.2.3")).len(), 4);
+    assert_eq!(table.lookup(ip("8.8.8.8")).unwrap().source, "static");
+    assert_eq!(
+        table.lookup(ip("2001:db8::1")).unwrap().next_hop,
+        NextHop::Blackhole
+    );
+    assert!(table.lookup(ip("2001:db9::1")).is_none());
+
+    // a source re-adding a next hop replaces its route.
+    assert!(!table.add(cidr("10.0.0.0/8"), route("10.0.0.1", "ospf", 110, 5)));
+    assert_eq!(
+        table.lookup(ip("10.0.0.1")).unwrap().next_hop,
+        gw("10.0.0.1")
+    );
+    assert_eq!(table.next_hops(ip("10.0.0.1")).len(), 1);
+    assert_eq!(table.routes(cidr("10.0.0.0/8")).len(), 4);
+
+    // every flow sticks to one member, and the flows spread over the group.
+    assert!(table.remove(cidr("10.0.0.0/8"), "ospf", &gw("10.0.0.1")));
+    assert!(!table.remove(cidr("10.0.0.0/8"), "ospf", &gw("10.0.0.1")));
+    let mut seen = vec![];
+    for port in 0..64 {
+        let flow = FlowTuple {
+            src: ip("192.168.1.10"),
+            dst: ip("10.9.8.7"),
+            protocol: 6,
+            src_port: 40000 + port,
+            dst_port: 443,
+        };
+        let hop = &table.select(&flow).unwrap().next_hop;
+        assert_eq!(hop, &table.select(&flow).unwrap().next_hop);
+        if !seen.contains(hop) {
+            seen.push(hop.clone());
+        }
+    }
+    assert_eq!(seen.len(), 2);
+
+    // removing the last route of a prefix falls back to a shorter one.
+    for hop in ["10.0.0.2", "10.0.0.3"].iter() {
+        assert!(table.remove(cidr("10.0.0.0/8"), "ospf", &gw(hop)));
+    }
+    assert_eq!(table.lookup(ip("10.1.2.3")).unwrap().source, "bgp");
+    assert!(table.remove(cidr("10.0.0.0/8"), "bgp", &gw("10.0.0.9")));
+    assert_eq!(table.lookup(ip("10.1.2.3")).unwrap().source, "static");
+    assert!(table.routes(cidr("10.0.0.0/8")).is_empty());
+    assert_eq!(table.len(), 2);
+
+    // the freed slot is reused.
+    table.add(cidr("10.0.0.0/8"), route("10.0.0.4", "ospf", 110, 1));
+    assert_eq!(table.sets.len(), 3);
+    let prefixes: Vec<String> = table.iter().map(|(c, _)| c.to_string()).collect();
+    assert_eq!(prefixes, vec!["0.0.0.0/0", "10.0.0.0/8", "2001:db8::/32"]);
+}
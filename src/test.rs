@@ -1,30 +1,49 @@
-use crate::geoip;
+use crate::geoip_db::GeoIpDatabase;
+use crate::geoip_writer::GeoIpListBuilder;
 use crate::lpc_trie::LPCTrie;
 use deepsize::DeepSizeOf;
 use std::convert::TryInto;
-use std::fs::File;
 use std::net::IpAddr;
+use std::path::PathBuf;
 
-#[cfg(test)]
-pub fn read_file() -> geoip::GeoIPList {
-    let file = "data/geoip.dat";
-    let mut f = match File::open(&file) {
-        Ok(f) => f,
-        Err(e) => {
-            panic!("open dat file {} failed: {}", file, e);
-        }
-    };
-    let geo_ip_list: geoip::GeoIPList = match protobuf::Message::parse_from_reader(&mut f) {
-        Ok(v) => v,
-        Err(e) => {
-            panic!("dat file {} has invalid format: {}", file, e);
-        }
+// A small geoip.dat standing in for the real one, with disjoint CN, US, AU
+// and PRIVATE cidrs of both families.
+pub fn geoip_fixture() -> GeoIpListBuilder {
+    let mut builder = GeoIpListBuilder::new();
+    let mut add = |code: &str, cidrs: &[&str]| {
+        builder.add_cidrs(code, cidrs.iter().map(|s| s.parse().unwrap()));
     };
-    geo_ip_list
+    add(
+        "cn",
+        &[
+            "1.0.1.0/24",
+            "1.0.2.0/23",
+            "36.96.0.0/11",
+            "114.114.0.0/16",
+            "240e::/20",
+            "2400:da00::/32",
+        ],
+    );
+    add("us", &["8.8.8.0/24", "23.0.0.0/12", "2001:4860::/32"]);
+    add("au", &["1.1.1.0/24", "2606:4700::/32"]);
+    add(
+        "private",
+        &["10.0.0.0/8", "127.0.0.0/8", "192.168.0.0/16", "fc00::/7"],
+    );
+    builder
+}
+
+// writes the fixture to a file of its own for tests loading from a path.
+fn geoip_fixture_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("cidr_matcher_{}_{}.dat", name, std::process::id()));
+    geoip_fixture().write_to_path(&path).unwrap();
+    path
 }
+
 #[test]
 fn test_lpc() {
-    let geoip_list = read_file();
+    let geoip_list = geoip_fixture().build();
     let mut lpc_trie_cn_us_v6 = LPCTrie::<u128>::new();
     let mut lpc_trie_cn_v6 = LPCTrie::<u128>::new();
     let mut lpc_trie_cn_v4 = LPCTrie::<u32>::new();
@@ -148,3 +167,28 @@ fn test_lpc() {
     assert_eq!(lpc_trie_cn_us_v6.empty(), true);
     println!("Hello, CIDRMatcher!");
 }
+
+#[test]
+fn test_geoip_database_file() {
+    let path = geoip_fixture_path("database_file");
+    let db = GeoIpDatabase::from_path(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(db.contains("cn", "114.114.114.114".parse().unwrap()));
+    assert!(!db.contains("CN", "8.8.8.8".parse().unwrap()));
+    assert!(db.country_of("1.1.1.1".parse().unwrap()).is_some());
+    assert_eq!(db.country_of("127.0.0.1".parse().unwrap()), Some("PRIVATE"));
+    assert!(db.contains("US", "2001:4860:4860::8888".parse().unwrap()));
+}
+
+#[test]
+fn test_geoip_database_file_with_codes() {
+    let path = geoip_fixture_path("database_file_with_codes");
+    let db = GeoIpDatabase::from_path_with_codes(&path, &["cn", "private"]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(db.len(), 2);
+    assert!(db.contains("CN", "114.114.114.114".parse().unwrap()));
+    assert!(db.contains("PRIVATE", "192.168.1.1".parse().unwrap()));
+    assert!(db.get("US").is_none());
+    assert_eq!(db.country_of("8.8.8.8".parse().unwrap()), None);
+    assert_eq!(db.country_of("240e::1".parse().unwrap()), Some("CN"));
+}
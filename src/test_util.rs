@@ -0,0 +1,66 @@
+use crate::cidr::Cidr;
+use crate::lpc_trie::LPCTrie;
+use std::net::IpAddr;
+
+// A xorshift64 generator for tests, seeded by hand so runs repeat. cidrs
+// gives random cidrs clustered in a few /8s and /32s, so they nest and
+// touch.
+pub struct XorShift(pub u64);
+
+impl XorShift {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn cidrs(&mut self, n: usize) -> Vec<Cidr> {
+        let mut cidrs = vec![];
+        for _ in 0..n {
+            let v4 = ((self.next() % 4) as u32) << 24 | (self.next() as u32 & 0x00ff_ffff);
+            let prefix = 8 + (self.next() % 25) as u8;
+            cidrs.push(Cidr::new(IpAddr::V4(v4.into()), prefix).unwrap());
+            let v6 = (0x2001_0db8u128 + self.next() as u128 % 4) << 96
+                | (self.next() as u128) << 32
+                | self.next() as u128 & 0xffff_ffff;
+            let prefix = 30 + (self.next() % 99) as u8;
+            cidrs.push(Cidr::new(IpAddr::V6(v6.into()), prefix).unwrap());
+        }
+        cidrs
+    }
+
+    // both sides of every cidr edge, and random addresses around them.
+    pub fn probes(&mut self, cidrs: &[Cidr]) -> Vec<IpAddr> {
+        let mut probes = vec![];
+        for cidr in cidrs.iter() {
+            let (first, last, width) = cidr.bounds();
+            for ip in [first.wrapping_sub(1), first, last, last.wrapping_add(1)].iter() {
+                probes.push(if width == 32 {
+                    IpAddr::V4((*ip as u32).into())
+                } else {
+                    IpAddr::V6((*ip).into())
+                });
+            }
+        }
+        for _ in 0..1000 {
+            let v4 = ((self.next() % 5) as u32) << 24 | self.next() as u32 >> 8;
+            probes.push(IpAddr::V4(v4.into()));
+            let v6 = (0x2001_0db8u128 + self.next() as u128 % 5) << 96 | self.next() as u128;
+            probes.push(IpAddr::V6(v6.into()));
+        }
+        probes
+    }
+}
+
+// an IPv4 trie of (cidr, value) routes, as the table tests write them.
+pub fn v4_trie(routes: &[(&str, &str)]) -> LPCTrie<u32> {
+    let mut trie = LPCTrie::new();
+    for (cidr, value) in routes.iter() {
+        match cidr.parse().unwrap() {
+            Cidr::V4(addr, prefix) => trie.put(addr, prefix, value.to_string()),
+            Cidr::V6(..) => panic!("{} is not IPv4", cidr),
+        }
+    }
+    trie
+}
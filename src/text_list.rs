@@ -0,0 +1,123 @@
+use crate::cidr::Cidr;
+use crate::dual_stack::DualStackTrie;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+// Error of the plain text importers, line numbers start at 1.
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    Line { line: usize, message: String },
+}
+
+impl ParseError {
+    pub fn at(line: usize, message: impl fmt::Display) -> ParseError {
+        ParseError::Line {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "read failed: {}", e),
+            ParseError::Line { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            ParseError::Line { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+// strip a trailing "#" or ";" comment and surrounding whitespace.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    match line.find(['#', ';']) {
+        Some(idx) => line[..idx].trim(),
+        None => line.trim(),
+    }
+}
+
+// Read a chnroutes style list, one cidr or bare address per line. Blank lines
+// and "#" or ";" comments are skipped, IPv4 and IPv6 may be mixed.
+pub fn read_cidrs<R: BufRead>(reader: R) -> Result<Vec<Cidr>, ParseError> {
+    let mut cidrs = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let text = strip_comment(&line);
+        if text.is_empty() {
+            continue;
+        }
+        let cidr: Cidr = text.parse().map_err(|e| ParseError::at(idx + 1, e))?;
+        cidrs.push(cidr);
+    }
+    Ok(cidrs)
+}
+
+pub fn parse_cidrs(text: &str) -> Result<Vec<Cidr>, ParseError> {
+    read_cidrs(text.as_bytes())
+}
+
+// build a trie from the list, every entry holding value.
+pub fn load<R: BufRead>(reader: R, value: &str) -> Result<DualStackTrie, ParseError> {
+    let mut trie = DualStackTrie::new();
+    for cidr in read_cidrs(reader)? {
+        trie.put(cidr, value.to_string());
+    }
+    Ok(trie)
+}
+
+pub fn load_path<P: AsRef<Path>>(path: P, value: &str) -> Result<DualStackTrie, ParseError> {
+    load(BufReader::new(File::open(path)?), value)
+}
+
+#[test]
+fn test_text_list() {
+    let text = "# chnroutes\n\
+                1.0.1.0/24\n\
+                \n\
+                1.0.2.0/23   ; inline comment\r\n\
+                  114.114.114.114\n\
+                2400:da00::/32 # v6\n\
+                2001:db8::1\n";
+    let cidrs = parse_cidrs(text).unwrap();
+    assert_eq!(cidrs.len(), 5);
+    assert_eq!(cidrs[2], "114.114.114.114/32".parse().unwrap());
+    assert_eq!(cidrs[4], "2001:db8::1/128".parse().unwrap());
+
+    let trie = load(text.as_bytes(), "CN").unwrap();
+    assert_eq!(trie.get_with_value("1.0.3.9".parse().unwrap()), "CN");
+    assert!(trie.get("114.114.114.114".parse().unwrap()));
+    assert!(!trie.get("114.114.114.115".parse().unwrap()));
+    assert!(trie.get("2400:da00:1::1".parse().unwrap()));
+    assert!(trie.get("2001:db8::1".parse().unwrap()));
+    assert!(!trie.get("8.8.8.8".parse().unwrap()));
+
+    match parse_cidrs("1.0.1.0/24\n# ok\n1.0.2.0/33\n") {
+        Err(ParseError::Line { line, message }) => {
+            assert_eq!(line, 3);
+            assert_eq!(message, "invalid prefix length 33");
+        }
+        _ => panic!("expected a line error"),
+    }
+    match parse_cidrs("\n\nnot-an-ip\n") {
+        Err(e) => assert_eq!(e.to_string(), "line 3: invalid address \"not-an-ip\""),
+        _ => panic!("expected a line error"),
+    }
+    assert!(load_path("no/such/list.txt", "CN").is_err());
+}
@@ -0,0 +1,100 @@
+use cidr_matcher::geoip_writer::GeoIpListBuilder;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+// include/cidr_matcher.h as cbindgen generates it from src/ffi.rs.
+fn generated_header() -> String {
+    let config = cbindgen::Config::from_file(manifest_dir().join("cbindgen.toml")).unwrap();
+    let mut header = vec![];
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(manifest_dir().join("src/ffi.rs"))
+        .generate()
+        .unwrap()
+        .write(&mut header);
+    String::from_utf8(header).unwrap()
+}
+
+// the checked-in header has to be what cbindgen makes of src/ffi.rs, so a
+// changed signature cannot go unnoticed. CIDR_MATCHER_WRITE_HEADER=1 writes
+// it instead.
+#[test]
+fn test_header_is_generated() {
+    let path = manifest_dir().join("include/cidr_matcher.h");
+    let generated = generated_header();
+    if std::env::var_os("CIDR_MATCHER_WRITE_HEADER").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    let header = fs::read_to_string(&path).unwrap();
+    assert!(
+        header == generated,
+        "include/cidr_matcher.h differs from what cbindgen generates, rerun \
+         with CIDR_MATCHER_WRITE_HEADER=1:\n{}",
+        generated
+    );
+}
+
+// builds libcidr_matcher_ffi.a the way include/cidr_matcher.h describes, in
+// a target directory of its own so it does not wait on the running cargo.
+fn build_static_lib() -> PathBuf {
+    let target = manifest_dir().join("target/ffi-test");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .current_dir(manifest_dir())
+        .args(["build", "-p", "cidr_matcher_ffi"])
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the static library failed");
+    target.join("debug/libcidr_matcher_ffi.a")
+}
+
+// builds tests/ffi_test.c against the static library and runs it.
+#[cfg(unix)]
+#[test]
+fn test_c_program() {
+    let lib = build_static_lib();
+    assert!(lib.exists(), "{} was not built", lib.display());
+
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("cidr_matcher_ffi_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dat = dir.join("geoip.dat");
+    let mut builder = GeoIpListBuilder::new();
+    builder.add_cidrs(
+        "cn",
+        ["1.0.1.0/24", "240e::/20"]
+            .iter()
+            .map(|s| s.parse().unwrap()),
+    );
+    builder.add_cidrs("us", ["8.8.8.0/24"].iter().map(|s| s.parse().unwrap()));
+    builder.write_to_path(&dat).unwrap();
+
+    let program = dir.join("ffi_test");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("tests/ffi_test.c"))
+        .arg(&lib)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling tests/ffi_test.c failed");
+
+    let output = Command::new(&program).arg(&dat).output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert!(
+        output.status.success(),
+        "ffi_test failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
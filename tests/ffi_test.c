@@ -0,0 +1,89 @@
+/*
+ * Drives the C interface the way a host application would. tests/ffi.rs
+ * builds it against libcidr_matcher_ffi.a and runs it with the path of a
+ * geoip.dat holding CN 1.0.1.0/24 240e::/20 and US 8.8.8.0/24.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "cidr_matcher.h"
+
+static int failures;
+
+#define CHECK(cond)                                                         \
+    do {                                                                    \
+        if (!(cond)) {                                                      \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #cond);                                                 \
+            failures++;                                                     \
+        }                                                                   \
+    } while (0)
+
+static const char *value_v4(const CidrMatcher *m, uint32_t addr) {
+    const char *value = cidr_matcher_value(m, cidr_matcher_lookup_v4(m, addr));
+    return value ? value : "";
+}
+
+static unsigned char *read_file(const char *path, size_t *len) {
+    FILE *f = fopen(path, "rb");
+    unsigned char *data;
+    long size;
+    if (!f) {
+        return NULL;
+    }
+    fseek(f, 0, SEEK_END);
+    size = ftell(f);
+    fseek(f, 0, SEEK_SET);
+    data = malloc(size > 0 ? (size_t)size : 1);
+    *len = fread(data, 1, (size_t)size, f);
+    fclose(f);
+    return data;
+}
+
+int main(int argc, char **argv) {
+    CidrMatcher *m;
+    unsigned char *data;
+    size_t len = 0;
+    const uint8_t v6[16] = {0x24, 0x0e, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1};
+    const uint8_t v6_miss[16] = {0x24, 0x0f};
+
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s geoip.dat\n", argv[0]);
+        return 2;
+    }
+
+    m = cidr_matcher_new();
+    CHECK(cidr_matcher_load_geoip_file(m, argv[1]) == 3);
+    CHECK(strcmp(value_v4(m, 0x01000107), "CN") == 0);
+    CHECK(strcmp(value_v4(m, 0x08080808), "US") == 0);
+    CHECK(cidr_matcher_lookup_v4(m, 0x01010101) == -1);
+    CHECK(strcmp(cidr_matcher_value(m, cidr_matcher_lookup_v6(m, v6)), "CN") == 0);
+    CHECK(cidr_matcher_lookup_v6(m, v6_miss) == -1);
+
+    CHECK(cidr_matcher_add(m, "10.0.0.0/8", "lan") == 2);
+    CHECK(strcmp(value_v4(m, 0x0a000001), "lan") == 0);
+    CHECK(cidr_matcher_remove(m, "10.0.0.0/8") == 1);
+    CHECK(cidr_matcher_lookup_v4(m, 0x0a000001) == -1);
+    CHECK(cidr_matcher_add(m, "10.0.0.0/99", "lan") == CIDR_MATCHER_ERR_CIDR);
+    CHECK(cidr_matcher_load_geoip_file(m, "/nonexistent/geoip.dat") == CIDR_MATCHER_ERR_IO);
+    CHECK(cidr_matcher_load_geoip_file(NULL, argv[1]) == CIDR_MATCHER_ERR_ARGUMENT);
+    cidr_matcher_free(m);
+
+    data = read_file(argv[1], &len);
+    CHECK(data != NULL);
+    m = cidr_matcher_new();
+    CHECK(cidr_matcher_load_geoip_buffer(m, data, len) == 3);
+    CHECK(cidr_matcher_load_geoip_buffer(m, data, len / 2) == CIDR_MATCHER_ERR_FORMAT);
+    CHECK(strcmp(value_v4(m, 0x080808ff), "US") == 0);
+    cidr_matcher_free(m);
+    free(data);
+    cidr_matcher_free(NULL);
+
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,146 @@
+# Exercises the python feature, run after `maturin develop` with
+# `python tests/test_python.py`, CI runs it with numpy installed. The numpy
+# checks, contains_many among them, are skipped without numpy.
+import ipaddress
+
+import cidr_matcher
+
+try:
+    import numpy
+except ImportError:
+    numpy = None
+
+
+def test_ipv4_trie():
+    trie = cidr_matcher.IPv4Trie(["1.0.1.0/24"], "cn")
+    trie.put("1.0.1.128/25", "lan")
+    trie.put("1.0.1.0/24", "us")
+    assert len(trie) == 2
+    assert trie.get("1.0.1.1") == "us"
+    trie.put("1.0.1.0/24", "cn")
+    assert trie.get("1.0.1.1") == "cn"
+    assert trie.get(ipaddress.ip_address("1.0.1.129")) == "lan"
+    assert trie.get(0x01000181) == "lan"
+    assert trie.get("8.8.8.8") is None
+    assert "1.0.1.1" in trie and "8.8.8.8" not in trie
+    assert trie.remove("1.0.1.128/25") and not trie.remove("1.0.1.128/25")
+    assert trie.cidrs() == ["1.0.1.0/24"]
+    assert trie.items() == [("1.0.1.0/24", "cn")]
+    assert "1.0.1.1" not in trie.complement()
+    assert "1.0.2.1" in trie.complement("other")
+    for bad in ["1.0.1.0/33", "240e::/20"]:
+        try:
+            trie.put(bad)
+        except ValueError:
+            pass
+        else:
+            raise AssertionError(bad)
+
+    ips = ["1.0.1.1", "8.8.8.8", 0x010001FF]
+    assert trie.lookup_many(ips) == ["cn", None, "cn"]
+    if numpy is not None:
+        assert trie.contains_many(ips).tolist() == [True, False, True]
+        array = numpy.array([0x01000101, 0x08080808], dtype=numpy.uint32)
+        assert trie.lookup_many(array) == ["cn", None]
+        assert trie.contains_many(array).tolist() == [True, False]
+        # numpy's default int64, and strings.
+        assert trie.lookup_many(numpy.array([0x01000101, 0x08080808])) == ["cn", None]
+        assert trie.lookup_many(numpy.array(["1.0.1.1", "8.8.8.8"])) == ["cn", None]
+        assert trie.lookup_many(numpy.array([], dtype=numpy.int64)) == []
+        for bad in [[-1], [1 << 32]]:
+            try:
+                trie.lookup_many(numpy.array(bad, dtype=numpy.int64))
+            except ValueError:
+                pass
+            else:
+                raise AssertionError(bad)
+
+
+def test_ipv6_trie():
+    trie = cidr_matcher.IPv6Trie(["240e::/20"], "cn")
+    assert trie.get("240e:1::1") == "cn"
+    assert trie.get("240f::1") is None
+    assert trie.lookup_many(["240e::1", "::1"]) == ["cn", None]
+    if numpy is not None:
+        array = numpy.array(
+            [ipaddress.ip_address("240e::1").packed, bytes(16)], dtype=numpy.uint8
+        ).reshape(2, 16)
+        assert trie.contains_many(array).tolist() == [True, False]
+
+
+def test_set_operations():
+    assert cidr_matcher.aggregate(["10.0.0.0/9", "10.128.0.0/9", "::/1", "8000::/1"]) == [
+        "10.0.0.0/8",
+        "::/0",
+    ]
+    assert cidr_matcher.union(["10.0.0.0/9"], ["10.128.0.0/9"]) == ["10.0.0.0/8"]
+    assert cidr_matcher.intersection(["10.0.0.0/8"], ["10.1.0.0/16", "11.0.0.0/8"]) == [
+        "10.1.0.0/16"
+    ]
+    assert cidr_matcher.difference(["10.0.0.0/8"], ["10.128.0.0/9"]) == ["10.0.0.0/9"]
+
+
+# a GeoIPList of (country_code, [cidr]) entries in protobuf encoding.
+def geoip_dat(entries):
+    def field(number, data):
+        assert len(data) < 128
+        return bytes([number << 3 | 2, len(data)]) + data
+
+    dat = b""
+    for code, cidrs in entries:
+        entry = field(1, code.encode())
+        for cidr in cidrs:
+            net = ipaddress.ip_network(cidr)
+            ip = field(1, net.network_address.packed)
+            entry += field(2, ip + bytes([2 << 3, net.prefixlen]))
+        dat += field(1, entry)
+    return dat
+
+
+def test_geoip_database():
+    try:
+        cidr_matcher.GeoIPDatabase.from_path("/nonexistent/geoip.dat")
+    except IOError:
+        pass
+    else:
+        raise AssertionError("missing file loaded")
+    try:
+        cidr_matcher.GeoIPDatabase.from_bytes(b"\xff\xff\xff")
+    except ValueError:
+        pass
+    else:
+        raise AssertionError("garbage loaded")
+    db = cidr_matcher.GeoIPDatabase.from_bytes(b"")
+    assert len(db) == 0
+    assert db.country_of("8.8.8.8") is None
+    assert db.country_of_many(["8.8.8.8", "::1"]) == [None, None]
+
+    dat = geoip_dat(
+        [("cn", ["1.0.1.0/24", "240e::/20"]), ("us", ["8.8.8.0/24", "1.0.1.0/24"])]
+    )
+    db = cidr_matcher.GeoIPDatabase.from_bytes(dat)
+    assert sorted(db.country_codes()) == ["CN", "US"]
+    assert db.country_of("8.8.8.8") == "US"
+    assert db.country_of(ipaddress.ip_address("240e:1::1")) == "CN"
+    assert db.country_of("9.9.9.9") is None
+    assert db.contains("cn", "1.0.1.1") and not db.contains("cn", "8.8.8.8")
+    assert db.cidrs("US") == ["1.0.1.0/24", "8.8.8.0/24"]
+    assert db.country_of_many(["8.8.8.8", "240e::1", "::1"]) == ["US", "CN", None]
+    assert len(cidr_matcher.GeoIPDatabase.from_bytes(dat, ["us"])) == 1
+    if numpy is not None:
+        array = numpy.array([0x08080808, 0x09090909], dtype=numpy.uint32)
+        assert db.country_of_many(array) == ["US", None]
+        array = numpy.array(
+            [ipaddress.ip_address("240e::1").packed, bytes(16)], dtype=numpy.uint8
+        ).reshape(2, 16)
+        assert db.country_of_many(array) == ["CN", None]
+        assert db.country_of_many(numpy.array([0x08080808])) == ["US"]
+        array = numpy.array(["8.8.8.8", "240e::1", "::1"])
+        assert db.country_of_many(array) == ["US", "CN", None]
+
+
+if __name__ == "__main__":
+    for name, test in list(globals().items()):
+        if name.startswith("test_"):
+            test()
+    print("ok")